@@ -15,18 +15,14 @@ fn main() {
             (InputKind::Gauge, _) => None,
 
             // prepend and append to metric name
-            (_, ScoreType::Count(count)) => {
-                if let Some(last) = name.pop_back() {
-                    Some((
-                        InputKind::Counter,
-                        name.append("customized_add_prefix")
-                            .append(format!("{}_and_a_suffix", last)),
-                        count,
-                    ))
-                } else {
-                    None
-                }
-            }
+            (_, ScoreType::Count(count)) => name.pop_back().map(|last| {
+                (
+                    InputKind::Counter,
+                    name.append("customized_add_prefix")
+                        .append(format!("{}_and_a_suffix", last)),
+                    count,
+                )
+            }),
 
             // scaling the score value and appending unit to name
             (kind, ScoreType::Sum(sum)) => Some((kind, name.append("per_thousand"), sum / 1000)),
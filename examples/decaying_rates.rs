@@ -0,0 +1,25 @@
+//! A dropwizard-like configuration using a single bucket
+//! publishing one, five and fifteen minutes moving average rates
+//! and percentiles of recent values, independent of the flush interval.
+
+use dipstick::*;
+use std::thread::sleep;
+use std::time::Duration;
+
+fn main() {
+    let bucket = AtomicBucket::new();
+    bucket.decaying(true);
+    bucket.percentiles(&[0.5, 0.95, 0.99]);
+    bucket.stats(stats_all);
+    bucket.drain(Stream::write_to_stdout());
+    bucket.flush_every(Duration::from_secs(10));
+
+    let requests = bucket.marker("requests");
+    let latency = bucket.timer("latency");
+
+    loop {
+        requests.mark();
+        latency.interval_us(12_000);
+        sleep(Duration::from_millis(100));
+    }
+}
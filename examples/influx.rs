@@ -0,0 +1,21 @@
+//! A sample application sending labelled metrics to InfluxDB over HTTP.
+
+use dipstick::*;
+use std::time::Duration;
+
+fn main() {
+    let metrics = Influx::write_to("http://localhost:8086/write?db=metrics")
+        .expect("Influx")
+        .named("my_app")
+        .metrics();
+
+    AppLabel::set("host", "my_host");
+
+    loop {
+        metrics
+            .counter("counter_a")
+            .write(123, labels!("path" => "/index"));
+        metrics.timer("timer_a").interval_us(2000000);
+        std::thread::sleep(Duration::from_millis(40));
+    }
+}
@@ -0,0 +1,20 @@
+//! A sample application serving metrics to Prometheus scrapers.
+//! Run with `--features prometheus_serve` then browse to http://localhost:9102/metrics
+
+use dipstick::*;
+use std::time::Duration;
+
+fn main() {
+    let metrics = Prometheus::serve_on("0.0.0.0:9102")
+        .expect("Prometheus Listener")
+        .named("my_app")
+        .metrics();
+
+    AppLabel::set("abc", "456");
+
+    loop {
+        metrics.counter("counter_a").count(123);
+        metrics.timer("timer_a").interval_us(2000000);
+        std::thread::sleep(Duration::from_millis(40));
+    }
+}
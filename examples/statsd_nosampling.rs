@@ -13,7 +13,7 @@ fn main() {
     let counter = metrics.counter("counter_a");
 
     loop {
-        for i in 1..11 as usize {
+        for i in 1..11_usize {
             counter.count(i);
         }
         std::thread::sleep(Duration::from_millis(3000));
@@ -2,18 +2,25 @@
 
 use crate::attributes::{Attributes, MetricId, OnFlush, Prefixed, WithAttributes};
 use crate::clock::TimeHandle;
-use crate::input::{Input, InputDyn, InputKind, InputMetric, InputScope};
+use crate::decay::{DecayingHistogram, EWMA_WINDOWS, Ewma};
+use crate::histogram::{AtomicBuckets, AtomicHistogram};
+use crate::input::{HistogramBuckets, Input, InputDyn, InputKind, InputMetric, InputScope};
+use crate::label::{LabelValue, Labels};
+use crate::metrics;
 use crate::name::MetricName;
 use crate::stats::ScoreType::*;
 use crate::stats::{ScoreType, stats_summary};
 use crate::{Flush, MetricValue, Void};
 
 use std::borrow::Borrow;
-use std::collections::BTreeMap;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::hash::{Hash, Hasher};
 use std::mem;
-use std::sync::Arc;
-use std::sync::atomic::AtomicIsize;
 use std::sync::atomic::Ordering::*;
+use std::sync::atomic::{AtomicBool, AtomicIsize};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
 use std::{fmt, io};
 
 #[cfg(not(feature = "parking_lot"))]
@@ -41,6 +48,9 @@ lazy_static! {
         RwLock::new(initial_drain());
 }
 
+/// Label sets aggregated separately for any metric, unless specified otherwise.
+const DEFAULT_MAX_LABEL_SETS: usize = 0;
+
 /// Central aggregation structure.
 /// Maintains a list of metrics for enumeration when used as source.
 #[derive(Debug, Clone, Default)]
@@ -51,11 +61,40 @@ pub struct AtomicBucket {
 
 #[derive(Default)]
 struct InnerAtomicBucket {
-    metrics: BTreeMap<MetricName, Arc<AtomicScores>>,
+    metrics: BTreeMap<MetricName, Arc<AtomicMetric>>,
     period_start: TimeHandle,
     stats: Option<Arc<StatsFn>>,
     drain: Option<Arc<dyn InputDyn + Send + Sync + 'static>>,
     publish_metadata: bool,
+    scoring: Scoring,
+    /// Set if percentiles are estimated, shared with every metric
+    track_percentiles: Arc<AtomicBool>,
+    max_label_sets: usize,
+}
+
+/// How scores are computed from aggregated values.
+#[derive(Debug, Clone, Default)]
+struct Scoring {
+    /// Percentiles estimated for metrics with a histogram
+    percentiles: Vec<f64>,
+    /// Also compute exponentially decaying rates and percentiles across periods
+    decaying: bool,
+    /// Keep running totals across periods
+    cumulative: bool,
+    /// Metrics overriding the cumulative mode of the bucket
+    cumulative_metrics: BTreeMap<MetricName, bool>,
+    /// Duration over which min and max are tracked in cumulative mode, the last period if none
+    window: Option<Duration>,
+}
+
+impl Scoring {
+    /// Returns true if the metric keeps running totals across periods.
+    fn is_cumulative(&self, name: &MetricName) -> bool {
+        self.cumulative_metrics
+            .get(name)
+            .copied()
+            .unwrap_or(self.cumulative)
+    }
 }
 
 impl fmt::Debug for InnerAtomicBucket {
@@ -102,13 +141,15 @@ impl InnerAtomicBucket {
         let duration_seconds = self.period_start.elapsed_us() as f64 / 1_000_000.0;
         self.period_start = now;
 
-        let mut snapshot: Vec<(&MetricName, InputKind, Vec<ScoreType>)> = self
+        let mut snapshot: Vec<(&MetricName, InputKind, LabelSet, Scores)> = self
             .metrics
             .iter()
-            .flat_map(|(name, scores)| {
-                scores
-                    .reset(duration_seconds)
-                    .map(|values| (name, scores.metric_kind(), values))
+            .flat_map(|(name, metric)| {
+                let cumulative = self.scoring.is_cumulative(name);
+                metric
+                    .reset(duration_seconds, &self.scoring, cumulative)
+                    .into_iter()
+                    .map(move |(labels, values)| (name, metric.kind, labels, values))
             })
             .collect();
 
@@ -123,7 +164,8 @@ impl InnerAtomicBucket {
                 snapshot.push((
                     &PERIOD_LENGTH,
                     InputKind::Timer,
-                    vec![Sum((duration_seconds * 1000.0) as isize)],
+                    LabelSet::new(),
+                    (vec![Sum((duration_seconds * 1000.0) as isize)], None),
                 ));
             }
 
@@ -132,13 +174,24 @@ impl InnerAtomicBucket {
                 None => read_lock!(DEFAULT_AGGREGATE_STATS).clone(),
             };
 
-            for metric in snapshot {
-                for score in metric.2 {
-                    let filtered = stats_fn(metric.1, metric.0.clone(), score);
+            for (metric_name, metric_kind, labels, (scores, buckets)) in snapshot {
+                for score in scores {
+                    let filtered = stats_fn(metric_kind, metric_name.clone(), score);
                     if let Some((kind, name, value)) = filtered {
-                        let metric: InputMetric = target.new_metric(name, kind);
-                        // TODO provide some stats context through labels?
-                        metric.write(value, labels![])
+                        match score {
+                            // outputs that can not represent buckets are skipped
+                            Buckets => {
+                                if let Some(buckets) = &buckets
+                                    && let Some(metric) = target.new_buckets(name)
+                                {
+                                    metric.write(buckets, series_labels(&labels))
+                                }
+                            }
+                            _ => {
+                                let metric: InputMetric = target.new_metric(name, kind);
+                                metric.write(value, series_labels(&labels))
+                            }
+                        }
                     }
                 }
             }
@@ -147,6 +200,19 @@ impl InnerAtomicBucket {
     }
 }
 
+/// Labels of a series, none being the default series of a metric.
+fn series_labels(labels: &LabelSet) -> Labels {
+    if labels.is_empty() {
+        Labels::default()
+    } else {
+        labels
+            .iter()
+            .cloned()
+            .collect::<HashMap<String, LabelValue>>()
+            .into()
+    }
+}
+
 impl<S: AsRef<str>> From<S> for AtomicBucket {
     fn from(name: S) -> AtomicBucket {
         AtomicBucket::new().named(name.as_ref())
@@ -165,6 +231,15 @@ impl AtomicBucket {
                 drain: None,
                 // TODO add API toggle for metadata publish
                 publish_metadata: false,
+                scoring: Scoring {
+                    percentiles: Vec::new(),
+                    decaying: false,
+                    cumulative: false,
+                    cumulative_metrics: BTreeMap::new(),
+                    window: None,
+                },
+                track_percentiles: Arc::new(AtomicBool::new(false)),
+                max_label_sets: DEFAULT_MAX_LABEL_SETS,
             })),
         }
     }
@@ -239,6 +314,56 @@ impl AtomicBucket {
         write_lock!(self.inner).drain = None
     }
 
+    /// Set the percentiles (from 0.0 to 1.0) estimated for timers, histograms and distributions.
+    /// None are estimated by default, sparing the memory and time needed to track the distribution of values.
+    pub fn percentiles(&self, quantiles: &[f64]) {
+        let mut inner = write_lock!(self.inner);
+        inner
+            .track_percentiles
+            .store(!quantiles.is_empty(), Relaxed);
+        inner.scoring.percentiles = quantiles.to_vec()
+    }
+
+    /// Score metrics across periods rather than only within each period, Dropwizard style.
+    /// Rates are also published as 1, 5 and 15 minutes moving averages,
+    /// and percentiles are estimated from values decaying over about five minutes.
+    /// Metrics that once had values keep publishing their decaying scores, even in periods without values.
+    pub fn decaying(&self, decaying: bool) {
+        write_lock!(self.inner).scoring.decaying = decaying
+    }
+
+    /// Keep running totals across periods instead of resetting scores upon each flush.
+    /// Counters, levels, markers and sets then publish their count and sum since the first value,
+    /// even in periods without values, as expected by Prometheus-style backends.
+    /// Rates and percentiles still apply to each period.
+    pub fn cumulative(&self, cumulative: bool) {
+        write_lock!(self.inner).scoring.cumulative = cumulative
+    }
+
+    /// Keep running totals of the named metric across periods, or not, regardless of the bucket's mode.
+    pub fn metric_cumulative(&self, name: &str, cumulative: bool) {
+        let name = self.prefix_append(name);
+        write_lock!(self.inner)
+            .scoring
+            .cumulative_metrics
+            .insert(name, cumulative);
+    }
+
+    /// Track min and max over a window of the specified duration in cumulative mode,
+    /// rather than only over the last period with values.
+    pub fn minmax_window(&self, window: Duration) {
+        write_lock!(self.inner).scoring.window = Some(window)
+    }
+
+    /// Aggregate up to the specified number of label sets separately for each metric defined afterwards.
+    /// Only labels provided with values make a label set, app and thread labels are left to the output.
+    /// Values with more label sets are aggregated in a single series labeled `overflow="true"`,
+    /// until label sets without values are evicted upon flush.
+    /// Zero ignores labels, aggregating all values of a metric together, which is the default.
+    pub fn max_label_sets(&self, max: usize) {
+        write_lock!(self.inner).max_label_sets = max
+    }
+
     /// Immediately flush the stats's metrics to the specified scope and stats.
     pub fn flush_to(&self, publish_scope: &dyn InputScope) -> io::Result<()> {
         let mut inner = write_lock!(self.inner);
@@ -249,13 +374,46 @@ impl AtomicBucket {
 impl InputScope for AtomicBucket {
     /// Lookup or create scores for the requested metric.
     fn new_metric(&self, name: MetricName, kind: InputKind) -> InputMetric {
-        let scores = write_lock!(self.inner)
+        let mut inner = write_lock!(self.inner);
+        let max_label_sets = inner.max_label_sets;
+        let track_percentiles = inner.track_percentiles.clone();
+        let scores = inner
+            .metrics
+            .entry(self.prefix_append(name.clone()))
+            .or_insert_with(|| {
+                Arc::new(AtomicMetric::new(
+                    kind,
+                    None,
+                    max_label_sets,
+                    track_percentiles,
+                ))
+            })
+            .clone();
+        InputMetric::new(MetricId::forge("stats", name), move |value, labels| {
+            scores.update(value, labels)
+        })
+    }
+
+    /// Lookup or create scores for the requested histogram, counting values in the declared buckets.
+    /// Buckets of a histogram already defined with the same name are left unchanged.
+    fn new_histogram(&self, name: MetricName, bounds: &[MetricValue]) -> InputMetric {
+        let mut inner = write_lock!(self.inner);
+        let max_label_sets = inner.max_label_sets;
+        let track_percentiles = inner.track_percentiles.clone();
+        let scores = inner
             .metrics
             .entry(self.prefix_append(name.clone()))
-            .or_insert_with(|| Arc::new(AtomicScores::new(kind)))
+            .or_insert_with(|| {
+                Arc::new(AtomicMetric::new(
+                    InputKind::Histogram,
+                    Some(bounds),
+                    max_label_sets,
+                    track_percentiles,
+                ))
+            })
             .clone();
-        InputMetric::new(MetricId::forge("stats", name), move |value, _labels| {
-            scores.update(value)
+        InputMetric::new(MetricId::forge("stats", name), move |value, labels| {
+            scores.update(value, labels)
         })
     }
 }
@@ -279,6 +437,195 @@ impl WithAttributes for AtomicBucket {
     }
 }
 
+/// Label pairs identifying a series of a metric, sorted by key.
+type LabelSet = Vec<(String, LabelValue)>;
+
+/// Scores of a series, with the buckets of histograms.
+type Scores = (Vec<ScoreType>, Option<HistogramBuckets>);
+
+lazy_static! {
+    static ref OVERFLOW_LABELS: LabelSet = vec![("overflow".into(), Arc::new("true".into()))];
+}
+
+/// Scores of a metric, aggregated separately for each set of labels.
+#[derive(Debug)]
+struct AtomicMetric {
+    kind: InputKind,
+    bounds: Option<Vec<MetricValue>>,
+    max_label_sets: usize,
+    /// Set if values are also counted in a histogram to estimate percentiles
+    track_percentiles: Arc<AtomicBool>,
+    /// Scores of values without labels
+    unlabeled: AtomicScores,
+    labeled: RwLock<LabeledScores>,
+}
+
+/// Scores of each label set of a metric, looked up by a hash of their labels.
+#[derive(Debug, Default)]
+struct LabeledScores {
+    series: HashMap<u64, Vec<(LabelSet, AtomicScores)>>,
+    /// Number of label sets
+    len: usize,
+    /// Scores of values whose label set exceeded the maximum
+    overflow: Option<AtomicScores>,
+}
+
+impl LabeledScores {
+    fn get(&self, hash: u64, labels: &HashMap<String, LabelValue>) -> Option<&AtomicScores> {
+        self.series
+            .get(&hash)?
+            .iter()
+            .find(|(label_set, _)| {
+                label_set.len() == labels.len()
+                    && label_set.iter().all(|(k, v)| labels.get(k) == Some(v))
+            })
+            .map(|(_, scores)| scores)
+    }
+}
+
+/// Hash label pairs regardless of their order.
+fn label_hash(labels: &HashMap<String, LabelValue>) -> u64 {
+    labels.iter().fold(0, |hash, pair| {
+        let mut hasher = DefaultHasher::new();
+        pair.hash(&mut hasher);
+        hash.wrapping_add(hasher.finish())
+    })
+}
+
+impl AtomicMetric {
+    fn new(
+        kind: InputKind,
+        bounds: Option<&[MetricValue]>,
+        max_label_sets: usize,
+        track_percentiles: Arc<AtomicBool>,
+    ) -> Self {
+        let bounds = bounds.map(|bounds| bounds.to_vec());
+        AtomicMetric {
+            unlabeled: AtomicMetric::new_scores(kind, &bounds),
+            kind,
+            bounds,
+            max_label_sets,
+            track_percentiles,
+            labeled: RwLock::new(LabeledScores::default()),
+        }
+    }
+
+    fn new_scores(kind: InputKind, bounds: &Option<Vec<MetricValue>>) -> AtomicScores {
+        match bounds {
+            Some(bounds) => AtomicScores::with_buckets(bounds),
+            None => AtomicScores::new(kind),
+        }
+    }
+
+    /// Update the scores of the value's label set.
+    /// Only labels provided with the value make a label set, not app or thread labels.
+    fn update(&self, value: MetricValue, labels: Labels) {
+        let track_percentiles = self.track_percentiles.load(Relaxed);
+        let labels = match labels.explicit() {
+            Some(labels) if self.max_label_sets > 0 && !labels.is_empty() => labels,
+            _ => return self.unlabeled.update(value, track_percentiles),
+        };
+        let hash = label_hash(labels);
+
+        // values are only recorded under lock so that flush can evict idle label sets
+        let overflow = {
+            let labeled = read_lock!(self.labeled);
+            if let Some(scores) = labeled.get(hash, labels) {
+                return scores.update(value, track_percentiles);
+            }
+            match &labeled.overflow {
+                Some(overflow) if labeled.len >= self.max_label_sets => {
+                    overflow.update(value, track_percentiles);
+                    true
+                }
+                _ => false,
+            }
+        };
+
+        let overflow = overflow || {
+            let mut labeled = write_lock!(self.labeled);
+            if let Some(scores) = labeled.get(hash, labels) {
+                // defined by a concurrent update
+                scores.update(value, track_percentiles);
+                false
+            } else if labeled.len >= self.max_label_sets {
+                labeled
+                    .overflow
+                    .get_or_insert_with(|| AtomicMetric::new_scores(self.kind, &self.bounds))
+                    .update(value, track_percentiles);
+                true
+            } else {
+                let mut label_set: LabelSet =
+                    labels.iter().map(|(k, v)| (k.clone(), v.clone())).collect();
+                label_set.sort();
+                let scores = AtomicMetric::new_scores(self.kind, &self.bounds);
+                scores.update(value, track_percentiles);
+                labeled
+                    .series
+                    .entry(hash)
+                    .or_default()
+                    .push((label_set, scores));
+                labeled.len += 1;
+                false
+            }
+        };
+        if overflow {
+            metrics::BUCKET_LABELS_OVERFLOW.mark();
+        }
+    }
+
+    /// Map raw scores of every label set (if any) to applicable statistics.
+    /// Label sets left with nothing to publish are evicted.
+    /// If values overflowed, label sets without values in the period are evicted along with their
+    /// running totals and decaying scores, making room for new label sets.
+    fn reset(
+        &self,
+        duration_seconds: f64,
+        scoring: &Scoring,
+        cumulative: bool,
+    ) -> Vec<(LabelSet, Scores)> {
+        let mut series = Vec::new();
+        if let Some(scores) = self.unlabeled.reset(duration_seconds, scoring, cumulative) {
+            series.push((LabelSet::new(), scores));
+        }
+
+        let mut labeled = write_lock!(self.labeled);
+        let overflowed = labeled.overflow.is_some();
+        let mut evicted = 0;
+        labeled.series.retain(|_, label_sets| {
+            label_sets.retain(|(labels, scores)| {
+                let idle = !scores.has_data();
+                match scores.reset(duration_seconds, scoring, cumulative) {
+                    Some(scores) => series.push((labels.clone(), scores)),
+                    None => {
+                        evicted += 1;
+                        return false;
+                    }
+                }
+                if overflowed && idle {
+                    evicted += 1;
+                    return false;
+                }
+                true
+            });
+            !label_sets.is_empty()
+        });
+        labeled.len -= evicted;
+
+        if let Some(overflow) = labeled.overflow.take() {
+            let idle = !overflow.has_data();
+            if let Some(scores) = overflow.reset(duration_seconds, scoring, cumulative) {
+                series.push((OVERFLOW_LABELS.clone(), scores));
+            }
+            // values still overflowing keep their series
+            if !idle {
+                labeled.overflow = Some(overflow);
+            }
+        }
+        series
+    }
+}
+
 const HIT: usize = 0;
 const SUM: usize = 1;
 const MAX: usize = 2;
@@ -293,6 +640,30 @@ struct AtomicScores {
     kind: InputKind,
     /// The actual recorded metric scores
     scores: [AtomicIsize; SCORES_LEN],
+    /// Distribution of values, allocated upon the first value if percentiles are estimated
+    histogram: OnceLock<AtomicHistogram>,
+    /// Counts of values in the buckets declared by a histogram's definition
+    buckets: Option<AtomicBuckets>,
+    /// Scores carried across periods, only updated upon flush
+    decay: RwLock<Option<DecayState>>,
+    /// Running totals carried across periods, only updated upon flush
+    totals: RwLock<Option<Totals>>,
+}
+
+/// Cumulative scores of a metric.
+#[derive(Debug, Default)]
+struct Totals {
+    hits: isize,
+    sum: isize,
+    /// Min and max of each period within the window
+    extremes: VecDeque<(TimeHandle, isize, isize)>,
+}
+
+/// Exponentially decaying scores of a metric.
+#[derive(Debug)]
+struct DecayState {
+    rates: [Ewma; EWMA_WINDOWS.len()],
+    histogram: DecayingHistogram,
 }
 
 impl AtomicScores {
@@ -303,12 +674,19 @@ impl AtomicScores {
             scores: unsafe {
                 mem::transmute::<[isize; 4], [AtomicIsize; 4]>(AtomicScores::blank())
             },
+            histogram: OnceLock::new(),
+            buckets: None,
+            decay: RwLock::new(None),
+            totals: RwLock::new(None),
         }
     }
 
-    /// Returns the metric's kind.
-    pub fn metric_kind(&self) -> InputKind {
-        self.kind
+    /// Create new scores to track a histogram counting values in buckets of the specified bounds
+    pub fn with_buckets(bounds: &[MetricValue]) -> Self {
+        AtomicScores {
+            buckets: Some(AtomicBuckets::new(bounds)),
+            ..AtomicScores::new(InputKind::Histogram)
+        }
     }
 
     #[inline]
@@ -316,13 +694,14 @@ impl AtomicScores {
         [0, 0, isize::MIN, isize::MAX]
     }
 
-    /// Update scores with new value
-    pub fn update(&self, value: MetricValue) {
+    /// Update scores with new value, also counting it in a histogram if percentiles are tracked
+    pub fn update(&self, value: MetricValue, track_percentiles: bool) {
         // TODO detect & report any concurrent updates / resets for measurement of contention
         // Count is tracked for all metrics
         self.scores[HIT].fetch_add(1, Relaxed);
         match self.kind {
-            InputKind::Marker => {}
+            // values of sets are not kept, only the insertions are counted
+            InputKind::Marker | InputKind::Set => {}
             InputKind::Level => {
                 // Level min & max apply to the _sum_ of values
                 // fetch_add only returns the previous sum, so min & max trail behind by one operation
@@ -332,16 +711,38 @@ impl AtomicScores {
                 swap_if(&self.scores[MAX], prev_sum, |new, current| new > current);
                 swap_if(&self.scores[MIN], prev_sum, |new, current| new < current);
             }
-            InputKind::Counter | InputKind::Timer | InputKind::Gauge => {
+            InputKind::Counter
+            | InputKind::Timer
+            | InputKind::Gauge
+            | InputKind::Histogram
+            | InputKind::Distribution => {
                 // gauges are non cumulative, but we keep the sum to compute the mean
                 // TODO use #![feature(atomic_min_max)] when stabilized
                 self.scores[SUM].fetch_add(value, Relaxed);
                 swap_if(&self.scores[MAX], value, |new, current| new > current);
                 swap_if(&self.scores[MIN], value, |new, current| new < current);
+                if track_percentiles
+                    && matches!(
+                        self.kind,
+                        InputKind::Timer | InputKind::Histogram | InputKind::Distribution
+                    )
+                {
+                    self.histogram
+                        .get_or_init(AtomicHistogram::new)
+                        .record(value);
+                }
+                if let Some(buckets) = &self.buckets {
+                    buckets.record(value);
+                }
             }
         }
     }
 
+    /// Returns true if values were recorded since the last reset.
+    fn has_data(&self) -> bool {
+        self.scores[HIT].load(Acquire) != 0
+    }
+
     /// Reset scores to zero, return previous values
     fn snapshot(&self, scores: &mut [isize; 4]) -> bool {
         // NOTE copy timestamp, count AND sum _before_ testing for data to reduce concurrent discrepancies
@@ -371,21 +772,40 @@ impl AtomicScores {
     }
 
     /// Map raw scores (if any) to applicable statistics
-    pub fn reset(&self, duration_seconds: f64) -> Option<Vec<ScoreType>> {
+    fn reset(&self, duration_seconds: f64, scoring: &Scoring, cumulative: bool) -> Option<Scores> {
         let mut scores = AtomicScores::blank();
-        if self.snapshot(&mut scores) {
-            let mut snapshot = Vec::new();
+        let has_data = self.snapshot(&mut scores);
+        // rates and percentiles always apply to the period
+        let period = scores;
+        let publish = if cumulative {
+            self.accumulate(&mut scores, has_data, scoring.window)
+        } else {
+            has_data
+        };
+        let histogram = match self.histogram.get() {
+            Some(histogram) if scoring.percentiles.is_empty() => {
+                // percentiles are no longer estimated, discard the values counted before
+                histogram.snapshot();
+                None
+            }
+            Some(histogram) if has_data || scoring.decaying => Some(histogram.snapshot()),
+            _ => None,
+        };
+
+        let mut snapshot = Vec::new();
+        let mut buckets = None;
+        if publish {
             match self.kind {
-                InputKind::Marker => {
+                InputKind::Marker | InputKind::Set => {
                     snapshot.push(Count(scores[HIT]));
-                    snapshot.push(Rate(scores[HIT] as f64 / duration_seconds))
+                    snapshot.push(Rate(period[HIT] as f64 / duration_seconds))
                 }
                 InputKind::Gauge => {
                     snapshot.push(Max(scores[MAX]));
                     snapshot.push(Min(scores[MIN]));
                     snapshot.push(Mean(scores[SUM] as f64 / scores[HIT] as f64));
                 }
-                InputKind::Timer => {
+                InputKind::Timer | InputKind::Histogram | InputKind::Distribution => {
                     snapshot.push(Count(scores[HIT]));
                     snapshot.push(Sum(scores[SUM]));
 
@@ -393,7 +813,34 @@ impl AtomicScores {
                     snapshot.push(Min(scores[MIN]));
                     snapshot.push(Mean(scores[SUM] as f64 / scores[HIT] as f64));
                     // timer rate uses the COUNT of timer calls per second (not SUM)
-                    snapshot.push(Rate(scores[HIT] as f64 / duration_seconds))
+                    // same for distributions, the sum of values per second is rarely meaningful
+                    snapshot.push(Rate(period[HIT] as f64 / duration_seconds));
+
+                    if let Some(histogram) = &histogram
+                        && !histogram.is_empty()
+                    {
+                        // decaying percentiles replace those of the period
+                        if !scoring.decaying {
+                            for &quantile in &scoring.percentiles {
+                                let value =
+                                    histogram.percentile(quantile, period[MIN], period[MAX]);
+                                snapshot.push(Percentile(quantile, value));
+                            }
+                        }
+                    }
+
+                    if self.kind == InputKind::Histogram {
+                        let counts = match &self.buckets {
+                            Some(buckets) => buckets.snapshot(),
+                            // all values fall in the unbounded bucket
+                            None => vec![(MetricValue::MAX, period[HIT] as usize)],
+                        };
+                        buckets = Some(HistogramBuckets {
+                            counts,
+                            sum: period[SUM],
+                        });
+                        snapshot.push(Buckets);
+                    }
                 }
                 InputKind::Counter => {
                     snapshot.push(Count(scores[HIT]));
@@ -403,7 +850,7 @@ impl AtomicScores {
                     snapshot.push(Min(scores[MIN]));
                     snapshot.push(Mean(scores[SUM] as f64 / scores[HIT] as f64));
                     // counter rate uses the SUM of values per second (e.g. to get bytes/s)
-                    snapshot.push(Rate(scores[SUM] as f64 / duration_seconds))
+                    snapshot.push(Rate(period[SUM] as f64 / duration_seconds))
                 }
                 InputKind::Level => {
                     snapshot.push(Count(scores[HIT]));
@@ -413,12 +860,113 @@ impl AtomicScores {
                     snapshot.push(Min(scores[MIN]));
                     snapshot.push(Mean(scores[SUM] as f64 / scores[HIT] as f64));
                     // counter rate uses the SUM of values per second (e.g. to get bytes/s)
-                    snapshot.push(Rate(scores[SUM] as f64 / duration_seconds))
+                    snapshot.push(Rate(period[SUM] as f64 / duration_seconds))
                 }
             }
-            Some(snapshot)
-        } else {
+        }
+
+        // gauges have neither rates nor percentiles to decay
+        if scoring.decaying && self.kind != InputKind::Gauge {
+            let rate_count = match self.kind {
+                InputKind::Counter | InputKind::Level => period[SUM],
+                _ => period[HIT],
+            };
+            let mut decay = write_lock!(self.decay);
+            if has_data && decay.is_none() {
+                *decay = Some(DecayState {
+                    rates: EWMA_WINDOWS.map(Ewma::new),
+                    histogram: DecayingHistogram::default(),
+                });
+            }
+            if let Some(decay) = decay.as_mut() {
+                for (window, ewma) in EWMA_WINDOWS.iter().zip(decay.rates.iter_mut()) {
+                    snapshot.push(MovingRate(
+                        *window,
+                        ewma.update(rate_count, duration_seconds),
+                    ));
+                }
+                if scoring.percentiles.is_empty() {
+                    // percentiles are no longer estimated
+                    decay.histogram = DecayingHistogram::default();
+                } else if let Some(histogram) = &histogram {
+                    decay.histogram.update(histogram, duration_seconds);
+                    if !decay.histogram.is_empty() {
+                        for &quantile in &scoring.percentiles {
+                            snapshot
+                                .push(Percentile(quantile, decay.histogram.percentile(quantile)));
+                        }
+                    }
+                }
+            }
+        }
+
+        if snapshot.is_empty() {
             None
+        } else {
+            Some((snapshot, buckets))
+        }
+    }
+}
+
+impl AtomicScores {
+    /// Add period scores to running totals, replacing count and sum with the totals
+    /// and min and max with those of the window.
+    /// Returns true if there are scores to publish.
+    fn accumulate(
+        &self,
+        scores: &mut [isize; SCORES_LEN],
+        has_data: bool,
+        window: Option<Duration>,
+    ) -> bool {
+        let mut totals = write_lock!(self.totals);
+        let totals = totals.get_or_insert_with(Totals::default);
+        let now = TimeHandle::now();
+        match self.kind {
+            InputKind::Level => {
+                if has_data {
+                    // level min & max are relative to the level at the start of the period
+                    totals.extremes.push_back((
+                        now,
+                        scores[MIN] + totals.sum,
+                        scores[MAX] + totals.sum,
+                    ));
+                } else if totals.hits > 0 {
+                    // the level did not change
+                    totals.extremes.push_back((now, totals.sum, totals.sum));
+                }
+            }
+            _ if has_data => totals.extremes.push_back((now, scores[MIN], scores[MAX])),
+            _ => {}
+        }
+
+        // always keep the last period with values
+        while totals.extremes.len() > 1 {
+            let expired = match window {
+                Some(window) => totals.extremes[0].0.elapsed_us() >= window.as_micros() as u64,
+                None => true,
+            };
+            if !expired {
+                break;
+            }
+            totals.extremes.pop_front();
+        }
+        scores[MIN] = isize::MAX;
+        scores[MAX] = isize::MIN;
+        for &(_, min, max) in &totals.extremes {
+            scores[MIN] = scores[MIN].min(min);
+            scores[MAX] = scores[MAX].max(max);
+        }
+
+        match self.kind {
+            InputKind::Marker | InputKind::Set | InputKind::Counter | InputKind::Level => {
+                totals.hits += scores[HIT];
+                totals.sum += scores[SUM];
+                scores[HIT] = totals.hits;
+                scores[SUM] = totals.sum;
+                totals.hits > 0
+            }
+            // gauges, timers and histograms values do not add up across periods
+            _ => has_data,
         }
     }
 }
@@ -448,13 +996,19 @@ mod bench {
     #[bench]
     fn update_marker(b: &mut test::Bencher) {
         let metric = AtomicScores::new(InputKind::Marker);
-        b.iter(|| test::black_box(metric.update(1)));
+        b.iter(|| test::black_box(metric.update(1, false)));
     }
 
     #[bench]
     fn update_count(b: &mut test::Bencher) {
         let metric = AtomicScores::new(InputKind::Counter);
-        b.iter(|| test::black_box(metric.update(4)));
+        b.iter(|| test::black_box(metric.update(4, false)));
+    }
+
+    #[bench]
+    fn update_timer(b: &mut test::Bencher) {
+        let metric = AtomicScores::new(InputKind::Timer);
+        b.iter(|| test::black_box(metric.update(4, false)));
     }
 
     #[bench]
@@ -485,6 +1039,8 @@ mod mtest {
     use crate::stats::{stats_all, stats_average, stats_summary};
 
     use crate::clock::{mock_clock_advance, mock_clock_reset};
+    use crate::label::AppLabel;
+    use crate::label::test::TEST_SEQUENCE;
     use crate::output::map::StatsMapScope;
 
     use std::collections::BTreeMap;
@@ -502,6 +1058,8 @@ mod mtest {
         let gauge = metrics.gauge("gauge_a");
         let level = metrics.level("level_a");
         let marker = metrics.marker("marker_a");
+        let set = metrics.set("set_a");
+        let histogram = metrics.histogram("histogram_a");
 
         marker.mark();
         marker.mark();
@@ -524,6 +1082,12 @@ mod mtest {
         level.adjust(-7789);
         level.adjust(77788);
 
+        set.insert(5);
+        set.insert(5);
+
+        histogram.value(3);
+        histogram.value(9);
+
         mock_clock_advance(Duration::from_secs(3));
 
         let map = StatsMapScope::default();
@@ -566,6 +1130,297 @@ mod mtest {
 
         assert_eq!(map["test.marker_a.count"], 3);
         assert_eq!(map["test.marker_a.rate"], 1);
+
+        assert_eq!(map["test.set_a.count"], 2);
+        assert!(!map.contains_key("test.set_a.sum"));
+
+        assert_eq!(map["test.histogram_a.count"], 2);
+        assert_eq!(map["test.histogram_a.sum"], 12);
+        assert_eq!(map["test.histogram_a.min"], 3);
+        assert_eq!(map["test.histogram_a.max"], 9);
+        assert_eq!(map["test.histogram_a.mean"], 6);
+    }
+
+    #[test]
+    fn histogram_allocated_for_percentiles() {
+        let metrics = AtomicBucket::new();
+        let timer = metrics.timer("timer_a");
+        timer.interval_us(10);
+        let scores = read_lock!(metrics.inner).metrics[&"timer_a".into()].clone();
+        assert!(scores.unlabeled.histogram.get().is_none());
+
+        metrics.percentiles(&[0.5]);
+        timer.interval_us(10);
+        assert!(scores.unlabeled.histogram.get().is_some());
+    }
+
+    #[test]
+    fn percentiles_cleared() {
+        let metrics = AtomicBucket::new();
+        metrics.percentiles(&[0.5]);
+        let timer = metrics.timer("timer_a");
+        timer.interval_us(10);
+        let scores = read_lock!(metrics.inner).metrics[&"timer_a".into()].clone();
+
+        // values counted before percentiles were cleared are discarded
+        metrics.percentiles(&[]);
+        metrics.flush_to(&StatsMapScope::default()).unwrap();
+        let histogram = scores.unlabeled.histogram.get().unwrap();
+        assert!(histogram.snapshot().is_empty());
+    }
+
+    #[test]
+    fn aggregate_percentiles() {
+        let _lock = TEST_SEQUENCE.lock().expect("Test Sequence");
+        mock_clock_reset();
+        let metrics = AtomicBucket::new();
+        metrics.stats(stats_all);
+        metrics.percentiles(&[0.5, 0.999]);
+
+        let timer = metrics.timer("timer_a");
+        for value in 1..=10_000 {
+            timer.interval_us(value);
+        }
+        let map = StatsMapScope::default();
+        metrics.flush_to(&map).unwrap();
+        let map: BTreeMap<String, MetricValue> = map.into();
+
+        assert!((map["timer_a.p50"] - 5000).abs() < 150);
+        assert!((map["timer_a.p999"] - 9990).abs() < 300);
+        assert!(!map.contains_key("timer_a.p99"));
+    }
+
+    #[test]
+    fn declared_buckets() {
+        let _lock = TEST_SEQUENCE.lock().expect("Test Sequence");
+        let metrics = AtomicBucket::new();
+        let histogram = metrics.histogram_buckets("histogram_a", &[10, 100]);
+        histogram.value(5);
+        histogram.value(50);
+        histogram.value(500);
+
+        let scores = read_lock!(metrics.inner).metrics[&"histogram_a".into()].clone();
+        let (_, buckets) = scores
+            .unlabeled
+            .reset(1.0, &Scoring::default(), false)
+            .unwrap();
+        assert_eq!(
+            Some(HistogramBuckets {
+                counts: vec![(10, 1), (100, 2), (MetricValue::MAX, 3)],
+                sum: 555,
+            }),
+            buckets
+        );
+    }
+
+    #[test]
+    fn undeclared_buckets() {
+        let metrics = AtomicBucket::new();
+        let histogram = metrics.histogram("histogram_a");
+        histogram.value(5);
+        histogram.value(50);
+
+        let scores = read_lock!(metrics.inner).metrics[&"histogram_a".into()].clone();
+        let (_, buckets) = scores
+            .unlabeled
+            .reset(1.0, &Scoring::default(), false)
+            .unwrap();
+        assert_eq!(
+            Some(HistogramBuckets {
+                counts: vec![(MetricValue::MAX, 2)],
+                sum: 55,
+            }),
+            buckets
+        );
+    }
+
+    #[test]
+    fn decaying_scores() {
+        let _lock = TEST_SEQUENCE.lock().expect("Test Sequence");
+        mock_clock_reset();
+        let metrics = AtomicBucket::new();
+        metrics.stats(stats_all);
+        metrics.decaying(true);
+        metrics.percentiles(&[0.99]);
+
+        let marker = metrics.marker("marker_a");
+        let timer = metrics.timer("timer_a");
+        for _ in 0..6000 {
+            marker.mark();
+        }
+        timer.interval_us(20_000);
+        mock_clock_advance(Duration::from_secs(60));
+        let map = StatsMapScope::default();
+        metrics.flush_to(&map).unwrap();
+        let map: BTreeMap<String, MetricValue> = map.into();
+        assert_eq!(map["marker_a.m1_rate"], 100);
+        assert_eq!(map["marker_a.m15_rate"], 100);
+
+        // decaying scores are published even without new values
+        mock_clock_advance(Duration::from_secs(60));
+        let map = StatsMapScope::default();
+        metrics.flush_to(&map).unwrap();
+        let map: BTreeMap<String, MetricValue> = map.into();
+        assert_eq!(map["marker_a.m1_rate"], 37);
+        assert_eq!(map["marker_a.m5_rate"], 82);
+        assert_eq!(map["marker_a.m15_rate"], 94);
+        assert!(!map.contains_key("marker_a.count"));
+        assert!((map["timer_a.p99"] - 20_000).abs() < 400);
+    }
+
+    #[test]
+    fn cumulative_scores() {
+        let _lock = TEST_SEQUENCE.lock().expect("Test Sequence");
+        mock_clock_reset();
+        let metrics = AtomicBucket::new();
+        metrics.stats(stats_all);
+        metrics.cumulative(true);
+        metrics.metric_cumulative("counter_b", false);
+        metrics.minmax_window(Duration::from_secs(90));
+
+        let counter = metrics.counter("counter_a");
+        let counter_b = metrics.counter("counter_b");
+        let level = metrics.level("level_a");
+        let gauge = metrics.gauge("gauge_a");
+
+        let flush = || {
+            mock_clock_advance(Duration::from_secs(60));
+            let map = StatsMapScope::default();
+            metrics.flush_to(&map).unwrap();
+            let map: BTreeMap<String, MetricValue> = map.into();
+            map
+        };
+
+        counter.count(10);
+        counter_b.count(10);
+        level.adjust(5);
+        gauge.value(3);
+        flush();
+
+        counter.count(20);
+        counter_b.count(20);
+        level.adjust(-2);
+        gauge.value(7);
+        let map = flush();
+        assert_eq!(map["counter_a.sum"], 30);
+        assert_eq!(map["counter_a.count"], 2);
+        assert_eq!(map["counter_a.min"], 10);
+        assert_eq!(map["counter_a.max"], 20);
+        assert_eq!(map["counter_a.rate"], 0);
+        assert_eq!(map["counter_b.sum"], 20);
+        assert_eq!(map["level_a.sum"], 3);
+        // the level started from zero
+        assert_eq!(map["level_a.min"], 0);
+        assert_eq!(map["level_a.max"], 5);
+        assert_eq!(map["gauge_a.min"], 3);
+
+        // totals are published without new values, the window slides past the first period
+        let map = flush();
+        assert_eq!(map["counter_a.sum"], 30);
+        assert_eq!(map["counter_a.min"], 20);
+        assert_eq!(map["level_a.sum"], 3);
+        assert_eq!(map["level_a.min"], 3);
+        assert_eq!(map["level_a.max"], 5);
+        assert!(!map.contains_key("counter_b.sum"));
+        assert!(!map.contains_key("gauge_a.mean"));
+    }
+
+    #[test]
+    fn label_sets_overflow() {
+        let _lock = TEST_SEQUENCE.lock().expect("Test Sequence");
+        let metrics = AtomicBucket::new();
+        metrics.max_label_sets(2);
+        let counter = metrics.counter("counter_a");
+        counter.count(1);
+        counter.write(2, labels!("path" => "/a"));
+        counter.write(3, labels!("path" => "/b"));
+        counter.write(4, labels!("path" => "/a"));
+        counter.write(5, labels!("path" => "/c"));
+        counter.write(6, labels!("path" => "/d"));
+
+        let metric = read_lock!(metrics.inner).metrics[&"counter_a".into()].clone();
+        let series: BTreeMap<String, MetricValue> = metric
+            .reset(1.0, &Scoring::default(), false)
+            .into_iter()
+            .map(|(labels, (scores, _))| {
+                let labels = labels
+                    .iter()
+                    .map(|(k, v)| format!("{k}={v}"))
+                    .collect::<Vec<_>>()
+                    .join(",");
+                let sum = scores.iter().find_map(|score| match score {
+                    Sum(sum) => Some(*sum),
+                    _ => None,
+                });
+                (labels, sum.unwrap())
+            })
+            .collect();
+
+        assert_eq!(series[""], 1);
+        assert_eq!(series["path=/a"], 6);
+        assert_eq!(series["path=/b"], 3);
+        assert_eq!(series["overflow=true"], 11);
+        assert_eq!(series.len(), 4);
+    }
+
+    /// Reset the metric, returning the labels of each published series.
+    fn reset_label_sets(metric: &AtomicMetric) -> Vec<String> {
+        metric
+            .reset(1.0, &Scoring::default(), false)
+            .into_iter()
+            .map(|(labels, _)| {
+                labels
+                    .iter()
+                    .map(|(k, v)| format!("{k}={v}"))
+                    .collect::<Vec<_>>()
+                    .join(",")
+            })
+            .collect()
+    }
+
+    #[test]
+    fn label_sets_evicted() {
+        let metrics = AtomicBucket::new();
+        metrics.max_label_sets(1);
+        let counter = metrics.counter("counter_a");
+        counter.write(1, labels!("path" => "/a"));
+        counter.write(2, labels!("path" => "/b"));
+
+        let metric = read_lock!(metrics.inner).metrics[&"counter_a".into()].clone();
+        assert_eq!(vec!["path=/a", "overflow=true"], reset_label_sets(&metric));
+
+        // the idle label set is evicted after the period
+        counter.write(3, labels!("path" => "/b"));
+        assert_eq!(vec!["overflow=true"], reset_label_sets(&metric));
+
+        counter.write(4, labels!("path" => "/b"));
+        assert_eq!(vec!["path=/b"], reset_label_sets(&metric));
+        assert!(read_lock!(metric.labeled).overflow.is_none());
+    }
+
+    #[test]
+    fn context_labels_not_aggregated() {
+        let _lock = TEST_SEQUENCE.lock().expect("Test Sequence");
+        AppLabel::set("app_label", "app");
+        let metrics = AtomicBucket::new();
+        metrics.max_label_sets(10);
+        let counter = metrics.counter("counter_a");
+        counter.count(1);
+        counter.write(2, labels!("path" => "/a"));
+        AppLabel::unset("app_label");
+
+        let metric = read_lock!(metrics.inner).metrics[&"counter_a".into()].clone();
+        assert_eq!(vec!["", "path=/a"], reset_label_sets(&metric));
+    }
+
+    #[test]
+    fn labels_ignored_by_default() {
+        let metrics = AtomicBucket::new();
+        let counter = metrics.counter("counter_a");
+        counter.write(2, labels!("path" => "/a"));
+
+        let metric = read_lock!(metrics.inner).metrics[&"counter_a".into()].clone();
+        assert_eq!(vec![""], reset_label_sets(&metric));
     }
 
     #[test]
@@ -578,6 +1433,8 @@ mod mtest {
         assert_eq!(map["test.timer_a"], 30_000_000);
         assert_eq!(map["test.gauge_a"], 15);
         assert_eq!(map["test.marker_a"], 3);
+        assert_eq!(map["test.set_a"], 2);
+        assert_eq!(map["test.histogram_a"], 6);
     }
 
     #[test]
@@ -164,10 +164,8 @@ where
         OnFlushCancel(Arc::new(move || {
             let mut listeners = write_lock!(flush_listeners);
             let installed_listener_id = listeners.get(&metric_id).map(|v| v.listener_id);
-            if let Some(id) = installed_listener_id {
-                if id == listener_id {
-                    listeners.remove(&metric_id);
-                }
+            if installed_listener_id == Some(listener_id) {
+                listeners.remove(&metric_id);
             }
         }))
     }
@@ -195,7 +193,7 @@ pub trait Observe {
         &self,
         metric: impl Deref<Target = InputMetric>,
         operation: F,
-    ) -> ObserveWhen<Self::Inner, F>
+    ) -> ObserveWhen<'_, Self::Inner, F>
     where
         F: Fn(Instant) -> MetricValue + Send + Sync + 'static,
         Self: Sized;
@@ -207,7 +205,7 @@ impl<T: InputScope + WithAttributes> Observe for T {
         &self,
         metric: impl Deref<Target = InputMetric>,
         operation: F,
-    ) -> ObserveWhen<Self, F>
+    ) -> ObserveWhen<'_, Self, F>
     where
         F: Fn(Instant) -> MetricValue + Send + Sync + 'static,
         Self: Sized,
@@ -300,6 +298,16 @@ pub trait Sampled: WithAttributes {
     }
 }
 
+/// Most values an output keeps pending until they are sent, whatever the buffering strategy,
+/// such as while its destination can not be reached.
+/// Outputs keeping a single sample per series count series instead.
+/// Values beyond it are dropped and counted by the output's `pending_dropped` self-metric.
+pub(crate) const MAX_PENDING_VALUES: usize = 10_000;
+
+/// Most bytes of text an output keeps pending until they are sent, for outputs buffering
+/// formatted lines rather than values. Lines beyond it are dropped the same way.
+pub(crate) const MAX_PENDING_BYTES: usize = 1024 * 1024;
+
 /// Determine scope buffering strategy, if supported by output.
 /// Changing this only affects scopes opened afterwards.
 /// Buffering is done on best effort, meaning flush will occur if buffer capacity is exceeded.
@@ -319,6 +327,16 @@ pub trait Buffered: WithAttributes {
     fn is_buffered(&self) -> bool {
         !(self.get_attributes().buffering == Buffering::Unbuffered)
     }
+
+    /// Returns true if a buffer of the specified size exceeds the `Buffering::BufferSize` limit,
+    /// counted in the output's own unit (bytes of text or number of values).
+    /// Other strategies never fill the buffer, leaving it to be emptied by flush.
+    fn is_buffer_full(&self, size: usize) -> bool {
+        match self.get_buffering() {
+            Buffering::BufferSize(max) => size > max,
+            _ => false,
+        }
+    }
 }
 
 #[cfg(test)]
@@ -1,10 +1,10 @@
 //! Metric input scope caching.
 
-use crate::Flush;
 use crate::attributes::{Attributes, OnFlush, Prefixed, WithAttributes};
-use crate::input::{Input, InputDyn, InputKind, InputMetric, InputScope};
+use crate::input::{BucketsMetric, Input, InputDyn, InputKind, InputMetric, InputScope};
 use crate::lru_cache as lru;
 use crate::name::MetricName;
+use crate::{Flush, MetricValue};
 
 use std::sync::Arc;
 
@@ -98,6 +98,20 @@ impl InputScope for InputScopeCache {
             new_metric
         })
     }
+
+    fn new_histogram(&self, name: MetricName, bounds: &[MetricValue]) -> InputMetric {
+        let name = self.prefix_append(name);
+        let lookup = { write_lock!(self.cache).get(&name).cloned() };
+        lookup.unwrap_or_else(|| {
+            let new_metric = self.target.new_histogram(name.clone(), bounds);
+            write_lock!(self.cache).insert(name, new_metric.clone());
+            new_metric
+        })
+    }
+
+    fn new_buckets(&self, name: MetricName) -> Option<BucketsMetric> {
+        self.target.new_buckets(self.prefix_append(name))
+    }
 }
 
 impl Flush for InputScopeCache {
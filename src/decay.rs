@@ -0,0 +1,141 @@
+//! Exponentially decaying statistics, updated upon each flush of an aggregation period.
+
+use crate::MetricValue;
+use crate::histogram::{HistogramSnapshot, bucket_bounds};
+
+use std::collections::BTreeMap;
+
+/// Windows of the moving average rates, in minutes.
+pub const EWMA_WINDOWS: [usize; 3] = [1, 5, 15];
+
+/// Decay factor per second of histogram weights.
+/// Same as Dropwizard's reservoir, heavily biasing the distribution towards the last five minutes.
+const DECAY_ALPHA: f64 = 0.015;
+
+/// Decayed weights below this are dropped.
+const MIN_WEIGHT: f64 = 0.001;
+
+/// Exponentially weighted moving average of a rate per second.
+/// Decay is computed from the actual length of each period, making it independent of flush interval.
+#[derive(Debug, Clone, Copy)]
+pub struct Ewma {
+    window_seconds: f64,
+    rate: Option<f64>,
+}
+
+impl Ewma {
+    /// Create a moving average over a window of the specified minutes.
+    pub fn new(window_minutes: usize) -> Self {
+        Ewma {
+            window_seconds: window_minutes as f64 * 60.0,
+            rate: None,
+        }
+    }
+
+    /// Account for a count observed over a period, returning the updated rate.
+    /// The first period sets the initial rate.
+    pub fn update(&mut self, count: isize, seconds: f64) -> f64 {
+        if seconds <= 0.0 {
+            return self.rate.unwrap_or(0.0);
+        }
+        let instant = count as f64 / seconds;
+        let rate = match self.rate {
+            None => instant,
+            Some(rate) => {
+                let alpha = 1.0 - (-seconds / self.window_seconds).exp();
+                rate + alpha * (instant - rate)
+            }
+        };
+        self.rate = Some(rate);
+        rate
+    }
+}
+
+/// Histogram whose weights decay exponentially with the age of values, standing in for
+/// a forward-decaying reservoir without having to sample values upon recording.
+#[derive(Debug, Clone, Default)]
+pub struct DecayingHistogram {
+    weights: BTreeMap<usize, f64>,
+}
+
+impl DecayingHistogram {
+    /// Decay previous weights by the length of the period, then add the values counted during it.
+    pub fn update(&mut self, snapshot: &HistogramSnapshot, seconds: f64) {
+        let decay = (-DECAY_ALPHA * seconds).exp();
+        self.weights.retain(|_, weight| {
+            *weight *= decay;
+            *weight >= MIN_WEIGHT
+        });
+        for &(idx, count) in snapshot.counts() {
+            *self.weights.entry(idx).or_insert(0.0) += count as f64;
+        }
+    }
+
+    /// Returns true if all values have decayed away.
+    pub fn is_empty(&self) -> bool {
+        self.weights.is_empty()
+    }
+
+    /// Estimate the value below which the specified fraction (0.0 to 1.0) of weighted values fall.
+    pub fn percentile(&self, quantile: f64) -> MetricValue {
+        let rank = quantile * self.weights.values().sum::<f64>();
+        let mut seen = 0.0;
+        let idx = self
+            .weights
+            .iter()
+            .find(|&(_, weight)| {
+                seen += weight;
+                seen >= rank
+            })
+            .or_else(|| self.weights.iter().next_back())
+            .map(|(&idx, _)| idx)
+            .unwrap_or(0);
+        let (lower, upper) = bucket_bounds(idx);
+        lower + (upper - lower) / 2
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::histogram::AtomicHistogram;
+
+    #[test]
+    fn moving_average() {
+        let mut ewma = Ewma::new(1);
+        assert_eq!(10.0, ewma.update(50, 5.0));
+        // a minute without hits decays the rate by e
+        let rate = ewma.update(0, 60.0);
+        assert!((rate - 10.0 / std::f64::consts::E).abs() < 0.001);
+
+        // twelve 5s periods decay as much as a single 60s one
+        let mut ewma = Ewma::new(1);
+        ewma.update(50, 5.0);
+        let mut rate = 0.0;
+        for _ in 0..12 {
+            rate = ewma.update(0, 5.0);
+        }
+        assert!((rate - 10.0 / std::f64::consts::E).abs() < 0.001);
+    }
+
+    #[test]
+    fn decay_old_values() {
+        let histogram = AtomicHistogram::new();
+        let mut decaying = DecayingHistogram::default();
+        for _ in 0..100 {
+            histogram.record(10);
+        }
+        decaying.update(&histogram.snapshot(), 1.0);
+        assert_eq!(10, decaying.percentile(0.5));
+
+        // ten minutes later, recent values outweigh the older ones
+        for _ in 0..10 {
+            histogram.record(1000);
+        }
+        decaying.update(&histogram.snapshot(), 600.0);
+        assert!(decaying.percentile(0.5) > 900);
+
+        decaying.update(&histogram.snapshot(), 3600.0);
+        assert!(decaying.is_empty());
+    }
+}
@@ -0,0 +1,235 @@
+//! Lock-free histograms of aggregated values.
+//! Log-linear histograms estimate percentiles, fixed buckets count values for export.
+
+use crate::MetricValue;
+
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering::*;
+
+/// Number of bits of the value kept as linear sub-buckets of each power of two.
+/// 32 sub-buckets bound the relative error of any estimate to about 3%.
+const SUB_BITS: u32 = 5;
+const SUB_BUCKETS: usize = 1 << SUB_BITS;
+
+/// Enough buckets to cover every positive isize value.
+const BUCKETS_LEN: usize = (MetricValue::BITS - SUB_BITS) as usize * SUB_BUCKETS;
+
+/// Counts values in buckets whose width grows with their magnitude.
+/// Values smaller than the number of sub-buckets are counted exactly.
+/// Negative values are counted as zero.
+pub struct AtomicHistogram {
+    buckets: Box<[AtomicUsize]>,
+}
+
+impl std::fmt::Debug for AtomicHistogram {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "AtomicHistogram")
+    }
+}
+
+/// Values counted in each bucket of a histogram, taken by a snapshot.
+#[derive(Debug, Clone, Default)]
+pub struct HistogramSnapshot {
+    /// Non-empty buckets as (index, count) pairs, by ascending index.
+    counts: Vec<(usize, usize)>,
+    total: usize,
+}
+
+impl AtomicHistogram {
+    /// Create an empty histogram.
+    pub fn new() -> Self {
+        AtomicHistogram {
+            buckets: (0..BUCKETS_LEN).map(|_| AtomicUsize::new(0)).collect(),
+        }
+    }
+
+    /// Count a value.
+    #[inline]
+    pub fn record(&self, value: MetricValue) {
+        self.buckets[bucket_index(value)].fetch_add(1, Relaxed);
+    }
+
+    /// Reset counts to zero, return previous counts.
+    pub fn snapshot(&self) -> HistogramSnapshot {
+        let mut snapshot = HistogramSnapshot::default();
+        for (idx, bucket) in self.buckets.iter().enumerate() {
+            // load first to avoid a costly swap of the many empty buckets
+            if bucket.load(Relaxed) != 0 {
+                let count = bucket.swap(0, AcqRel);
+                snapshot.counts.push((idx, count));
+                snapshot.total += count;
+            }
+        }
+        snapshot
+    }
+}
+
+impl HistogramSnapshot {
+    /// Returns true if no values were counted.
+    pub fn is_empty(&self) -> bool {
+        self.total == 0
+    }
+
+    /// Estimate the value below which the specified fraction (0.0 to 1.0) of counted values fall.
+    /// The estimate is the midpoint of the bucket holding the value, bounded by `min` and `max`.
+    /// The highest percentile is always `max`.
+    pub fn percentile(&self, quantile: f64, min: MetricValue, max: MetricValue) -> MetricValue {
+        let rank = ((quantile * self.total as f64).ceil() as usize).clamp(1, self.total.max(1));
+        if rank == self.total {
+            // the highest value is known exactly
+            return max;
+        }
+        let mut seen = 0;
+        for &(idx, count) in &self.counts {
+            seen += count;
+            if seen >= rank {
+                let (lower, upper) = bucket_bounds(idx);
+                let mid = lower + (upper - lower) / 2;
+                return mid.clamp(min, max);
+            }
+        }
+        max
+    }
+
+    /// Index and count of every non-empty bucket, by ascending index.
+    pub fn counts(&self) -> &[(usize, usize)] {
+        &self.counts
+    }
+}
+
+/// Counts values in buckets of upper bounds (inclusive) declared by the metric's definition.
+/// The last bucket counts all values above the highest bound.
+pub struct AtomicBuckets {
+    bounds: Vec<MetricValue>,
+    counts: Box<[AtomicUsize]>,
+}
+
+impl std::fmt::Debug for AtomicBuckets {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "AtomicBuckets {:?}", self.bounds)
+    }
+}
+
+impl AtomicBuckets {
+    /// Create empty buckets with the specified upper bounds, in any order.
+    pub fn new(bounds: &[MetricValue]) -> Self {
+        let mut bounds = bounds.to_vec();
+        bounds.sort_unstable();
+        bounds.dedup();
+        // the unbounded bucket is always there
+        if bounds.last() != Some(&MetricValue::MAX) {
+            bounds.push(MetricValue::MAX);
+        }
+        AtomicBuckets {
+            counts: bounds.iter().map(|_| AtomicUsize::new(0)).collect(),
+            bounds,
+        }
+    }
+
+    /// Count a value in the first bucket whose bound is equal or above it.
+    #[inline]
+    pub fn record(&self, value: MetricValue) {
+        let idx = self.bounds.partition_point(|&bound| bound < value);
+        self.counts[idx].fetch_add(1, Relaxed);
+    }
+
+    /// Reset counts to zero, return the upper bound of every bucket
+    /// with the cumulative count of values up to that bound.
+    pub fn snapshot(&self) -> Vec<(MetricValue, usize)> {
+        let mut seen = 0;
+        self.bounds
+            .iter()
+            .zip(self.counts.iter())
+            .map(|(&bound, count)| {
+                seen += count.swap(0, AcqRel);
+                (bound, seen)
+            })
+            .collect()
+    }
+}
+
+#[inline]
+fn bucket_index(value: MetricValue) -> usize {
+    if value < SUB_BUCKETS as MetricValue {
+        return value.max(0) as usize;
+    }
+    let exponent = MetricValue::BITS - 1 - value.leading_zeros();
+    let shift = exponent - SUB_BITS;
+    (shift as usize + 1) * SUB_BUCKETS + ((value >> shift) as usize - SUB_BUCKETS)
+}
+
+/// Smallest and largest values counted in the bucket.
+pub fn bucket_bounds(idx: usize) -> (MetricValue, MetricValue) {
+    if idx < SUB_BUCKETS {
+        return (idx as MetricValue, idx as MetricValue);
+    }
+    let shift = idx / SUB_BUCKETS - 1;
+    let lower = ((SUB_BUCKETS + idx % SUB_BUCKETS) as MetricValue) << shift;
+    (lower, lower + ((1 << shift) - 1))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn bucket_bounds_contain_values() {
+        for value in [
+            0,
+            1,
+            31,
+            32,
+            33,
+            63,
+            64,
+            65,
+            1000,
+            123_456_789,
+            MetricValue::MAX,
+        ] {
+            let (lower, upper) = bucket_bounds(bucket_index(value));
+            assert!(
+                lower <= value && value <= upper,
+                "{value} in {lower}..={upper}"
+            );
+        }
+        assert_eq!(0, bucket_index(-5));
+        assert_eq!(BUCKETS_LEN - 1, bucket_index(MetricValue::MAX));
+    }
+
+    #[test]
+    fn estimate_percentiles() {
+        let histogram = AtomicHistogram::new();
+        for value in 1..=1000 {
+            histogram.record(value);
+        }
+        let snapshot = histogram.snapshot();
+        assert!(histogram.snapshot().is_empty());
+
+        for (quantile, exact) in [(0.5, 500.0), (0.95, 950.0), (0.99, 990.0)] {
+            let estimate = snapshot.percentile(quantile, 1, 1000) as f64;
+            assert!(
+                (estimate - exact).abs() / exact < 0.03,
+                "p{quantile} {estimate}"
+            );
+        }
+        assert_eq!(1000, snapshot.percentile(1.0, 1, 1000));
+        assert_eq!(1, snapshot.percentile(0.0, 1, 1000));
+    }
+
+    #[test]
+    fn count_fixed_buckets() {
+        let buckets = AtomicBuckets::new(&[100, 10]);
+        for value in [-1, 10, 11, 100, 1000] {
+            buckets.record(value);
+        }
+        assert_eq!(
+            vec![(10, 2), (100, 4), (MetricValue::MAX, 5)],
+            buckets.snapshot()
+        );
+        assert_eq!(
+            vec![(10, 0), (100, 0), (MetricValue::MAX, 0)],
+            buckets.snapshot()
+        );
+    }
+}
@@ -75,6 +75,44 @@ pub trait InputScope: Flush {
     fn level(&self, name: &str) -> Level {
         self.new_metric(name.into(), InputKind::Level).into()
     }
+
+    /// Define a Set.
+    /// Statsd servers count the unique values sent to them, but aggregating scopes such as `AtomicBucket`
+    /// do not keep the values and publish the number of insertions as a counter instead.
+    fn set(&self, name: &str) -> Set {
+        self.new_metric(name.into(), InputKind::Set).into()
+    }
+
+    /// Define a Histogram.
+    fn histogram(&self, name: &str) -> Histogram {
+        self.new_metric(name.into(), InputKind::Histogram).into()
+    }
+
+    /// Define a Histogram counting values in buckets of the specified upper bounds (inclusive).
+    /// Values above the highest bound are counted in a last, unbounded bucket.
+    /// Outputs that do not aggregate values treat it as a plain histogram.
+    fn histogram_buckets(&self, name: &str, bounds: &[MetricValue]) -> Histogram {
+        self.new_histogram(name.into(), bounds).into()
+    }
+
+    /// Define a generic histogram metric with buckets of the specified upper bounds.
+    /// It is preferable to use the histogram_buckets() method.
+    fn new_histogram(&self, name: MetricName, bounds: &[MetricValue]) -> InputMetric {
+        let _ = bounds;
+        self.new_metric(name, InputKind::Histogram)
+    }
+
+    /// Define a Distribution.
+    fn distribution(&self, name: &str) -> Distribution {
+        self.new_metric(name.into(), InputKind::Distribution).into()
+    }
+
+    /// Define a metric receiving the bucket counts of histograms aggregated upstream.
+    /// Returns `None` if this scope can not represent buckets, which is the default.
+    fn new_buckets(&self, name: MetricName) -> Option<BucketsMetric> {
+        let _ = name;
+        None
+    }
 }
 
 /// A metric is actually a function that knows to write a metric value to a metric output.
@@ -114,6 +152,66 @@ impl InputMetric {
     }
 }
 
+/// Counts of an aggregated histogram's values in each of its buckets.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistogramBuckets {
+    /// Upper bound (inclusive) of each bucket with the cumulative count of values up to it,
+    /// by ascending bound. The last bound is always `MetricValue::MAX`, counting all values.
+    pub counts: Vec<(MetricValue, usize)>,
+    /// Sum of the counted values.
+    pub sum: MetricValue,
+}
+
+impl HistogramBuckets {
+    /// Returns the number of counted values.
+    pub fn count(&self) -> usize {
+        self.counts
+            .last()
+            .map(|&(_, count)| count)
+            .unwrap_or_default()
+    }
+}
+
+/// A function writing the buckets of a histogram.
+type WriteBucketsFn = dyn Fn(&HistogramBuckets, Labels) + Send + Sync;
+
+/// A metric writing the buckets of aggregated histograms to an output that can represent them.
+#[derive(Clone)]
+pub struct BucketsMetric {
+    identifier: MetricId,
+    inner: Arc<WriteBucketsFn>,
+}
+
+impl fmt::Debug for BucketsMetric {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "BucketsMetric")
+    }
+}
+
+impl BucketsMetric {
+    /// Utility constructor
+    pub fn new<F: Fn(&HistogramBuckets, Labels) + Send + Sync + 'static>(
+        identifier: MetricId,
+        metric: F,
+    ) -> BucketsMetric {
+        BucketsMetric {
+            identifier,
+            inner: Arc::new(metric),
+        }
+    }
+
+    /// Collect the buckets of a histogram.
+    #[inline]
+    pub fn write(&self, buckets: &HistogramBuckets, labels: Labels) {
+        (self.inner)(buckets, labels)
+    }
+
+    /// Returns the unique identifier of this metric.
+    pub fn metric_id(&self) -> &MetricId {
+        &self.identifier
+    }
+}
+
 /// Used to differentiate between metric kinds in the backend.
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
 pub enum InputKind {
@@ -127,6 +225,12 @@ pub enum InputKind {
     Gauge,
     /// Time interval, internal to the app or provided by an external source
     Timer,
+    /// Occurrence of unique values
+    Set,
+    /// Statistical distribution of values, computed by the agent
+    Histogram,
+    /// Statistical distribution of values, computed server-side over all agents
+    Distribution,
 }
 
 /// Used by the metrics! macro to obtain the InputKind from the stringified type.
@@ -138,6 +242,9 @@ impl From<&str> for InputKind {
             "Gauge" => InputKind::Gauge,
             "Timer" => InputKind::Timer,
             "Level" => InputKind::Level,
+            "Set" => InputKind::Set,
+            "Histogram" => InputKind::Histogram,
+            "Distribution" => InputKind::Distribution,
             _ => panic!("No InputKind '{s}' defined"),
         }
     }
@@ -255,6 +362,47 @@ impl Timer {
     }
 }
 
+/// A count of unique values, such as user ids.
+/// If aggregated, values are not kept and only the number of insertions is tracked.
+#[derive(Debug, Clone)]
+pub struct Set {
+    inner: InputMetric,
+}
+
+impl Set {
+    /// Record a value occurrence.
+    pub fn insert<V: ToPrimitive>(&self, value: V) {
+        self.inner.write(value.to_isize().unwrap(), labels![])
+    }
+}
+
+/// A distribution of values (e.g. request sizes) for which the backend computes statistics.
+#[derive(Debug, Clone)]
+pub struct Histogram {
+    inner: InputMetric,
+}
+
+impl Histogram {
+    /// Record a value of the distribution.
+    pub fn value<V: ToPrimitive>(&self, value: V) {
+        self.inner.write(value.to_isize().unwrap(), labels![])
+    }
+}
+
+/// A distribution of values that are aggregated globally by the backend rather than per agent.
+/// Backends that do not support global distributions treat them as histograms.
+#[derive(Debug, Clone)]
+pub struct Distribution {
+    inner: InputMetric,
+}
+
+impl Distribution {
+    /// Record a value of the distribution.
+    pub fn value<V: ToPrimitive>(&self, value: V) {
+        self.inner.write(value.to_isize().unwrap(), labels![])
+    }
+}
+
 impl From<InputMetric> for Gauge {
     fn from(metric: InputMetric) -> Gauge {
         Gauge { inner: metric }
@@ -285,6 +433,24 @@ impl From<InputMetric> for Level {
     }
 }
 
+impl From<InputMetric> for Set {
+    fn from(metric: InputMetric) -> Set {
+        Set { inner: metric }
+    }
+}
+
+impl From<InputMetric> for Histogram {
+    fn from(metric: InputMetric) -> Histogram {
+        Histogram { inner: metric }
+    }
+}
+
+impl From<InputMetric> for Distribution {
+    fn from(metric: InputMetric) -> Distribution {
+        Distribution { inner: metric }
+    }
+}
+
 impl Deref for Counter {
     type Target = InputMetric;
 
@@ -324,3 +490,27 @@ impl Deref for Level {
         &self.inner
     }
 }
+
+impl Deref for Set {
+    type Target = InputMetric;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+impl Deref for Histogram {
+    type Target = InputMetric;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+impl Deref for Distribution {
+    type Target = InputMetric;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
@@ -10,7 +10,7 @@ use std::sync::RwLock;
 use parking_lot::RwLock;
 
 /// Label values are immutable but can move around a lot.
-type LabelValue = Arc<String>;
+pub(crate) type LabelValue = Arc<String>;
 
 /// A reference table of key / value string pairs that may be used on output for additional metric context.
 ///
@@ -136,7 +136,7 @@ impl AppLabel {
         *write_lock!(APP_LABELS) = b;
     }
 
-    fn collect(map: &mut HashMap<String, LabelValue>) {
+    pub(crate) fn collect(map: &mut HashMap<String, LabelValue>) {
         read_lock!(APP_LABELS).collect(map)
     }
 }
@@ -147,6 +147,8 @@ impl AppLabel {
 #[derive(Debug, Clone)]
 pub struct Labels {
     scopes: Vec<LabelScope>,
+    /// The first scope holds the labels provided with the value.
+    value_labels: bool,
 }
 
 impl From<HashMap<String, LabelValue>> for Labels {
@@ -155,6 +157,7 @@ impl From<HashMap<String, LabelValue>> for Labels {
             scopes: vec![LabelScope {
                 pairs: Some(Arc::new(map)),
             }],
+            value_labels: true,
         }
     }
 }
@@ -164,7 +167,10 @@ impl Default for Labels {
     /// Only Thread and App labels will be used for lookups.
     #[inline]
     fn default() -> Self {
-        Labels { scopes: vec![] }
+        Labels {
+            scopes: vec![],
+            value_labels: false,
+        }
     }
 }
 
@@ -176,6 +182,15 @@ impl Labels {
         self.scopes.push(read_lock!(APP_LABELS).clone());
     }
 
+    /// Labels provided with the value, without any thread or app context labels.
+    pub(crate) fn explicit(&self) -> Option<&HashMap<String, LabelValue>> {
+        if self.value_labels {
+            self.scopes[0].pairs.as_deref()
+        } else {
+            None
+        }
+    }
+
     /// Generic label lookup function.
     /// Searches provided labels, provided scopes or default scopes.
     // TODO needs less magic, add checks?
@@ -252,7 +267,7 @@ pub mod test {
         /// Label tests use the globally shared AppLabels which may make them interfere as tests are run concurrently.
         /// We do not want to mandate usage of `RUST_TEST_THREADS=1` which would penalize the whole test suite.
         /// Instead we use a local mutex to make sure the label tests run in sequence.
-        static ref TEST_SEQUENCE: Mutex<()> = Mutex::new(());
+        pub static ref TEST_SEQUENCE: Mutex<()> = Mutex::new(());
     }
 
     #[test]
@@ -274,7 +289,7 @@ pub mod test {
         );
         AppLabel::unset("abc");
 
-        assert_eq!(true, labels!().lookup("abc").is_none());
+        assert!(labels!().lookup("abc").is_none());
     }
 
     #[test]
@@ -316,5 +331,23 @@ pub mod test {
             Arc::new("789".into()),
             labels.lookup("abc").expect("Label Value")
         );
+
+        ThreadLabel::unset("abc");
+        AppLabel::unset("abc");
+    }
+
+    #[test]
+    fn explicit_labels() {
+        let _lock = TEST_SEQUENCE.lock().expect("Test Sequence");
+
+        let mut labels = labels! { "abc" => "789" };
+        labels.save_context();
+        labels.save_context();
+        let explicit = labels.explicit().expect("Explicit Labels");
+        assert_eq!(1, explicit.len());
+
+        let mut labels = Labels::default();
+        labels.save_context();
+        assert!(labels.explicit().is_none());
     }
 }
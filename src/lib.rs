@@ -70,6 +70,8 @@ mod proxy;
 mod scheduler;
 
 mod atomic;
+mod decay;
+mod histogram;
 mod stats;
 
 mod cache;
@@ -84,7 +86,8 @@ pub use crate::attributes::{
 };
 pub use crate::clock::TimeHandle;
 pub use crate::input::{
-    Counter, Gauge, Input, InputDyn, InputKind, InputMetric, InputScope, Level, Marker, Timer,
+    BucketsMetric, Counter, Distribution, Gauge, Histogram, HistogramBuckets, Input, InputDyn,
+    InputKind, InputMetric, InputScope, Level, Marker, Set, Timer,
 };
 pub use crate::label::{AppLabel, Labels, ThreadLabel};
 pub use crate::name::{MetricName, NameParts};
@@ -98,23 +101,40 @@ pub use crate::proxy::Proxy;
 
 mod output;
 pub use crate::output::format::{
-    Formatting, LabelOp, LineFormat, LineOp, LineTemplate, SimpleFormat,
+    BatchFormat, BatchValue, EmfFormat, Formatting, JsonFormat, LabelOp, LineFormat, LineOp,
+    LineTemplate, SimpleFormat, TemplateFormat,
 };
-pub use crate::output::graphite::{Graphite, GraphiteMetric, GraphiteScope};
+pub use crate::output::graphite::{Graphite, GraphiteMetric, GraphiteProtocol, GraphiteScope};
 pub use crate::output::graphite_udp::{GraphiteUdp, GraphiteUdpMetric, GraphiteUdpScope};
+pub use crate::output::influx::{Influx, InfluxMetric, InfluxScope};
 pub use crate::output::log::{Log, LogScope};
-pub use crate::output::map::StatsMapScope;
-pub use crate::output::statsd::{Statsd, StatsdMetric, StatsdScope};
+pub use crate::output::map::{StatsMap, StatsMapScope};
+pub use crate::output::opentsdb::{OpenTsdb, OpenTsdbMetric, OpenTsdbScope};
+pub use crate::output::rolling::{ReopenHandle, RollingFile};
+pub use crate::output::statsd::{
+    ServiceCheckStatus, Statsd, StatsdDialect, StatsdMetric, StatsdScope,
+};
 pub use crate::output::stream::{Stream, TextScope};
+pub use crate::output::syslog::{Syslog, SyslogFacility, SyslogProtocol, SyslogScope};
 
 //#[cfg(feature="prometheus")]
 pub use crate::output::prometheus::{Prometheus, PrometheusScope};
+#[cfg(feature = "remote_write")]
+pub use crate::output::remote_write::{RemoteWrite, RemoteWriteMetric, RemoteWriteScope};
+
+pub use crate::output::openmetrics::{OpenMetrics, OpenMetricsScope};
+
+pub use crate::output::otlp::{Otlp, OtlpEncoding, OtlpMetric, OtlpScope};
+
+pub use crate::output::http::{Http, HttpEncoder, HttpEntry, HttpMethod, HttpScope, JsonEncoder};
 
 pub use crate::atomic::AtomicBucket;
 pub use crate::cache::CachedInput;
 pub use crate::multi::{MultiInput, MultiInputScope};
 pub use crate::queue::{InputQueue, InputQueueScope, QueuedInput};
-pub use crate::stats::{ScoreType, stats_all, stats_average, stats_summary};
+pub use crate::stats::{
+    ScoreType, percentile_name, stats_all, stats_average, stats_buckets, stats_summary,
+};
 
 use std::io;
 
@@ -14,21 +14,78 @@ metrics! {
             pub SEND_FAILED: Marker = "send_failed";
         }
 
+        "bucket" => {
+            pub BUCKET_LABELS_OVERFLOW: Marker = "labels_overflow";
+        }
+
         "prometheus" => {
             pub PROMETHEUS_SEND_ERR: Marker = "send_failed";
-            pub PROMETHEUS_OVERFLOW: Marker = "buf_overflow";
             pub PROMETHEUS_SENT_BYTES: Counter = "sent_bytes";
+            pub PROMETHEUS_PENDING_DROPPED: Marker = "pending_dropped";
         }
 
         "graphite" => {
             pub GRAPHITE_SEND_ERR: Marker = "send_failed";
-            pub GRAPHITE_OVERFLOW: Marker = "buf_overflow";
             pub GRAPHITE_SENT_BYTES: Counter = "sent_bytes";
+            pub GRAPHITE_OVERSIZE: Marker = "oversize_dropped";
+            pub GRAPHITE_PENDING_DROPPED: Marker = "pending_dropped";
+        }
+
+        "remote_write" => {
+            pub REMOTE_WRITE_SEND_ERR: Marker = "send_failed";
+            pub REMOTE_WRITE_SENT_BYTES: Counter = "sent_bytes";
+            pub REMOTE_WRITE_DROPPED: Marker = "rejected_dropped";
+            pub REMOTE_WRITE_PENDING_DROPPED: Marker = "pending_dropped";
+        }
+
+        "influx" => {
+            pub INFLUX_SEND_ERR: Marker = "send_failed";
+            pub INFLUX_SENT_BYTES: Counter = "sent_bytes";
+            pub INFLUX_OVERSIZE: Marker = "oversize_dropped";
+            pub INFLUX_DROPPED: Marker = "rejected_dropped";
+            pub INFLUX_PENDING_DROPPED: Marker = "pending_dropped";
+        }
+
+        "opentsdb" => {
+            pub OPENTSDB_SEND_ERR: Marker = "send_failed";
+            pub OPENTSDB_SENT_BYTES: Counter = "sent_bytes";
+            pub OPENTSDB_UNTAGGED: Marker = "untagged_dropped";
+            pub OPENTSDB_TOO_MANY_TAGS: Marker = "too_many_tags_dropped";
+            pub OPENTSDB_DROPPED: Marker = "rejected_dropped";
+            pub OPENTSDB_PENDING_DROPPED: Marker = "pending_dropped";
+        }
+
+        "openmetrics" => {
+            pub OPENMETRICS_SEND_ERR: Marker = "send_failed";
+            pub OPENMETRICS_SENT_BYTES: Counter = "sent_bytes";
+            pub OPENMETRICS_PENDING_DROPPED: Marker = "pending_dropped";
+        }
+
+        "otlp" => {
+            pub OTLP_SEND_ERR: Marker = "send_failed";
+            pub OTLP_SENT_BYTES: Counter = "sent_bytes";
+            pub OTLP_DROPPED: Marker = "rejected_dropped";
+            pub OTLP_PENDING_DROPPED: Marker = "pending_dropped";
+        }
+
+        "http" => {
+            pub HTTP_SEND_ERR: Marker = "send_failed";
+            pub HTTP_SENT_BYTES: Counter = "sent_bytes";
+            pub HTTP_DROPPED: Marker = "rejected_dropped";
+            pub HTTP_PENDING_DROPPED: Marker = "pending_dropped";
+        }
+
+        "syslog" => {
+            pub SYSLOG_SEND_ERR: Marker = "send_failed";
+            pub SYSLOG_SENT_BYTES: Counter = "sent_bytes";
+            pub SYSLOG_PENDING_DROPPED: Marker = "pending_dropped";
         }
 
         "statsd" => {
             pub STATSD_SEND_ERR: Marker ="send_failed";
             pub STATSD_SENT_BYTES: Counter = "sent_bytes";
+            pub STATSD_OVERSIZE: Marker = "oversize_dropped";
+            pub STATSD_PENDING_DROPPED: Marker = "pending_dropped";
         }
     }
 }
@@ -1,9 +1,9 @@
 //! Dispatch metrics to multiple sinks.
 
-use crate::Flush;
 use crate::attributes::{Attributes, MetricId, OnFlush, Prefixed, WithAttributes};
-use crate::input::{Input, InputDyn, InputKind, InputMetric, InputScope};
+use crate::input::{BucketsMetric, Input, InputDyn, InputKind, InputMetric, InputScope};
 use crate::name::MetricName;
+use crate::{Flush, MetricValue};
 
 use std::io;
 use std::sync::Arc;
@@ -90,17 +90,53 @@ impl InputScope for MultiInputScope {
             .iter()
             .map(move |scope| scope.new_metric(name.clone(), kind))
             .collect();
-        InputMetric::new(
-            MetricId::forge("multi", name.clone()),
-            move |value, labels| {
+        multi_metric(name, metrics)
+    }
+
+    fn new_histogram(&self, name: MetricName, bounds: &[MetricValue]) -> InputMetric {
+        let name = &self.prefix_append(name);
+        let metrics: Vec<InputMetric> = self
+            .scopes
+            .iter()
+            .map(move |scope| scope.new_histogram(name.clone(), bounds))
+            .collect();
+        multi_metric(name, metrics)
+    }
+
+    /// Write buckets to the scopes that can represent them, if any.
+    fn new_buckets(&self, name: MetricName) -> Option<BucketsMetric> {
+        let name = self.prefix_append(name);
+        let metrics: Vec<BucketsMetric> = self
+            .scopes
+            .iter()
+            .filter_map(|scope| scope.new_buckets(name.clone()))
+            .collect();
+        if metrics.is_empty() {
+            return None;
+        }
+        Some(BucketsMetric::new(
+            MetricId::forge("multi", name),
+            move |buckets, labels| {
                 for metric in &metrics {
-                    metric.write(value, labels.clone())
+                    metric.write(buckets, labels.clone())
                 }
             },
-        )
+        ))
     }
 }
 
+/// Write values to every metric.
+fn multi_metric(name: &MetricName, metrics: Vec<InputMetric>) -> InputMetric {
+    InputMetric::new(
+        MetricId::forge("multi", name.clone()),
+        move |value, labels| {
+            for metric in &metrics {
+                metric.write(value, labels.clone())
+            }
+        },
+    )
+}
+
 impl Flush for MultiInputScope {
     fn flush(&self) -> io::Result<()> {
         self.notify_flush_listeners();
@@ -135,7 +135,7 @@ mod test {
         let mut sd1: NameParts = "c".into();
         sd1.push_front("b".into());
 
-        assert_eq!(true, sd1.is_within(&sd1));
+        assert!(sd1.is_within(&sd1));
     }
 
     #[test]
@@ -147,7 +147,7 @@ mod test {
         sd2.push_front("b".into());
         sd2.push_front("a".into());
 
-        assert_eq!(true, sd2.is_within(&sd1));
-        assert_eq!(false, sd1.is_within(&sd2));
+        assert!(sd2.is_within(&sd1));
+        assert!(!sd1.is_within(&sd2));
     }
 }
@@ -0,0 +1,142 @@
+//! Pack metric entries into datagrams no larger than a maximum payload.
+
+use crate::output::socket::DatagramSocket;
+
+use std::io;
+use std::sync::Arc;
+
+/// Full packets held before they are sent together.
+const MAX_BATCH_PACKETS: usize = 64;
+
+/// Result of appending an entry to a batch.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Push {
+    /// The entry was appended.
+    Appended,
+    /// The entry was appended and enough packets are ready to be sent.
+    Full,
+    /// The entry is larger than the maximum payload and was dropped.
+    Oversize,
+}
+
+/// Packs entries into datagrams of at most `max_payload` bytes.
+/// Entries are never split across datagrams.
+#[derive(Debug)]
+pub struct DatagramBatch {
+    socket: Arc<DatagramSocket>,
+    max_payload: usize,
+    separator: &'static str,
+    packet: String,
+    packets: Vec<String>,
+}
+
+impl DatagramBatch {
+    /// Create an empty batch sending to the socket.
+    /// The separator is inserted between entries of a same datagram.
+    pub fn new(socket: Arc<DatagramSocket>, max_payload: usize, separator: &'static str) -> Self {
+        DatagramBatch {
+            socket,
+            max_payload,
+            separator,
+            packet: String::with_capacity(max_payload),
+            packets: Vec::new(),
+        }
+    }
+
+    /// Append an entry made of the parts provided, starting a new datagram if it would not fit.
+    pub fn push(&mut self, parts: &[&str]) -> Push {
+        let entry_len: usize = parts.iter().map(|part| part.len()).sum();
+        if entry_len > self.max_payload {
+            return Push::Oversize;
+        }
+        if !self.packet.is_empty() {
+            if self.packet.len() + self.separator.len() + entry_len > self.max_payload {
+                let full =
+                    std::mem::replace(&mut self.packet, String::with_capacity(self.max_payload));
+                self.packets.push(full);
+            } else {
+                self.packet.push_str(self.separator);
+            }
+        }
+        for part in parts {
+            self.packet.push_str(part);
+        }
+        if self.packets.len() >= MAX_BATCH_PACKETS {
+            Push::Full
+        } else {
+            Push::Appended
+        }
+    }
+
+    /// The datagram currently being filled.
+    #[cfg(test)]
+    pub fn current_packet(&self) -> &str {
+        &self.packet
+    }
+
+    /// Returns true if there is nothing to send.
+    pub fn is_empty(&self) -> bool {
+        self.packet.is_empty() && self.packets.is_empty()
+    }
+
+    /// Send all datagrams, returning the number of bytes sent.
+    /// The batch is emptied even if sending fails, datagrams that could not be sent are lost.
+    pub fn send(&mut self) -> io::Result<usize> {
+        if !self.packet.is_empty() {
+            let last = std::mem::replace(&mut self.packet, String::with_capacity(self.max_payload));
+            self.packets.push(last);
+        }
+        let result = match self.packets.len() {
+            0 => Ok(0),
+            1 => self.socket.send(self.packets[0].as_bytes()),
+            _ => self.socket.send_batch(&self.packets),
+        };
+        self.packets.clear();
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::UdpSocket;
+
+    #[test]
+    fn pack_entries() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let socket = Arc::new(DatagramSocket::udp(receiver.local_addr().unwrap()).unwrap());
+        let mut batch = DatagramBatch::new(socket, 10, "\n");
+
+        assert_eq!(Push::Appended, batch.push(&["aaaa"]));
+        assert_eq!(Push::Appended, batch.push(&["bb", "bb"]));
+        assert_eq!(Push::Oversize, batch.push(&["too long to fit"]));
+        assert_eq!(Push::Appended, batch.push(&["cccc"]));
+        assert_eq!(13, batch.send().unwrap());
+        assert!(batch.is_empty());
+
+        let mut buf = [0; 16];
+        let len = receiver.recv(&mut buf).unwrap();
+        assert_eq!(b"aaaa\nbbbb", &buf[..len]);
+        let len = receiver.recv(&mut buf).unwrap();
+        assert_eq!(b"cccc", &buf[..len]);
+    }
+
+    #[test]
+    fn full_batch() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let socket = Arc::new(DatagramSocket::udp(receiver.local_addr().unwrap()).unwrap());
+        let mut batch = DatagramBatch::new(socket, 4, "");
+
+        for _ in 0..MAX_BATCH_PACKETS {
+            assert_eq!(Push::Appended, batch.push(&["xxxx"]));
+        }
+        assert_eq!(Push::Full, batch.push(&["xxxx"]));
+        assert_eq!(4 * (MAX_BATCH_PACKETS + 1), batch.send().unwrap());
+
+        let mut buf = [0; 8];
+        for _ in 0..=MAX_BATCH_PACKETS {
+            let len = receiver.recv(&mut buf).unwrap();
+            assert_eq!(b"xxxx", &buf[..len]);
+        }
+    }
+}
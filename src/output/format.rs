@@ -1,11 +1,15 @@
 use self::LineOp::*;
 use crate::MetricValue;
 use crate::input::InputKind;
+use crate::label::Labels;
 use crate::name::MetricName;
 
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
 use std::io;
 use std::io::Write;
 use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 /// Print commands are steps in the execution of output templates.
 pub enum LineOp {
@@ -19,6 +23,15 @@ pub enum LineOp {
     ScaledValueAsText(f64),
     /// Print the newline character.labels.lookup(key)
     NewLine,
+    /// Print the current Unix time in seconds.
+    Timestamp,
+    /// Print the current Unix time in milliseconds.
+    TimestampMillis,
+    /// Print every label sorted by key, separated by the given string.
+    /// Labels can only be enumerated when the template is printed with `print_labels`.
+    AllLabels(Vec<LabelOp>, Vec<u8>),
+    /// Print the value on its own using a batch format.
+    Batch(Arc<dyn BatchFormat>, MetricName, InputKind),
 }
 
 /// Print commands are steps in the execution of output templates.
@@ -29,6 +42,10 @@ pub enum LabelOp {
     LabelKey,
     /// Print the label value.
     LabelValue,
+    /// Print the label key, escaped as the contents of a JSON string.
+    JsonLabelKey,
+    /// Print the label value, escaped as the contents of a JSON string.
+    JsonLabelValue,
 }
 
 /// An sequence of print commands, embodying an output strategy for a single metric.
@@ -53,6 +70,27 @@ impl LineTemplate {
     where
         L: Fn(&str) -> Option<Arc<String>>,
     {
+        self.print_ops(output, value, &lookup, None)
+    }
+
+    /// Template execution applies commands in turn, writing to the output.
+    /// All labels are made available to the `AllLabels` command.
+    pub fn print_labels(
+        &self,
+        output: &mut dyn Write,
+        value: MetricValue,
+        labels: &Labels,
+    ) -> io::Result<()> {
+        self.print_ops(output, value, &|key| labels.lookup(key), Some(labels))
+    }
+
+    fn print_ops(
+        &self,
+        output: &mut dyn Write,
+        value: MetricValue,
+        lookup: &dyn Fn(&str) -> Option<Arc<String>>,
+        labels: Option<&Labels>,
+    ) -> io::Result<()> {
         for cmd in &self.ops {
             match cmd {
                 Literal(src) => output.write_all(src.as_ref())?,
@@ -62,14 +100,31 @@ impl LineTemplate {
                     output.write_all(format!("{scaled}").as_ref())?
                 }
                 NewLine => writeln!(output)?,
+                Timestamp => write!(output, "{}", epoch().as_secs())?,
+                TimestampMillis => write!(output, "{}", epoch().as_millis())?,
                 LabelExists(label_key, print_label) => {
                     if let Some(label_value) = lookup(label_key.as_ref()) {
-                        for label_cmd in print_label {
-                            match label_cmd {
-                                LabelOp::LabelValue => output.write_all(label_value.as_bytes())?,
-                                LabelOp::LabelKey => output.write_all(label_key.as_bytes())?,
-                                LabelOp::Literal(src) => output.write_all(src.as_ref())?,
+                        print_label_ops(output, print_label, label_key, &label_value)?
+                    }
+                }
+                Batch(format, name, kind) => {
+                    let value = BatchValue {
+                        name: name.clone(),
+                        kind: *kind,
+                        value,
+                        labels: labels.cloned().unwrap_or_default(),
+                    };
+                    format.print_batch(output, &[value])?
+                }
+                AllLabels(print_label, separator) => {
+                    if let Some(labels) = labels {
+                        let mut labels: Vec<_> = labels.clone().into_map().into_iter().collect();
+                        labels.sort();
+                        for (i, (label_key, label_value)) in labels.iter().enumerate() {
+                            if i > 0 {
+                                output.write_all(separator)?;
                             }
+                            print_label_ops(output, print_label, label_key, label_value)?
                         }
                     }
                 }
@@ -79,6 +134,49 @@ impl LineTemplate {
     }
 }
 
+fn print_label_ops(
+    output: &mut dyn Write,
+    ops: &[LabelOp],
+    label_key: &str,
+    label_value: &str,
+) -> io::Result<()> {
+    for label_cmd in ops {
+        match label_cmd {
+            LabelOp::LabelValue => output.write_all(label_value.as_bytes())?,
+            LabelOp::LabelKey => output.write_all(label_key.as_bytes())?,
+            LabelOp::JsonLabelValue => output.write_all(escape_json(label_value).as_bytes())?,
+            LabelOp::JsonLabelKey => output.write_all(escape_json(label_key).as_bytes())?,
+            LabelOp::Literal(src) => output.write_all(src.as_ref())?,
+        }
+    }
+    Ok(())
+}
+
+fn epoch() -> std::time::Duration {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+}
+
+/// Escape a string for inclusion between JSON double quotes.
+pub(crate) fn escape_json(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(escaped, "\\u{:04x}", c as u32);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
 /// Format output config support.
 pub trait Formatting {
     /// Specify formatting of output.
@@ -89,6 +187,30 @@ pub trait Formatting {
 pub trait LineFormat: Send + Sync {
     /// Prepare a template for output of metric values.
     fn template(&self, name: &MetricName, kind: InputKind) -> LineTemplate;
+
+    /// Print the values written between two flushes of a buffered output together,
+    /// rather than one template at a time. Returns `None` if the format prints values one by one.
+    fn batch(&self) -> Option<Arc<dyn BatchFormat>> {
+        None
+    }
+}
+
+/// A value kept by a buffered output until it is printed along with the rest of its batch.
+pub struct BatchValue {
+    /// The metric name, including any output prefix.
+    pub name: MetricName,
+    /// The metric kind.
+    pub kind: InputKind,
+    /// The value written.
+    pub value: MetricValue,
+    /// The labels of the value.
+    pub labels: Labels,
+}
+
+/// Formats printing a batch of values together, e.g. as a single document.
+pub trait BatchFormat: Send + Sync {
+    /// Print the values, in the order they were written.
+    fn print_batch(&self, output: &mut dyn Write, values: &[BatchValue]) -> io::Result<()>;
 }
 
 /// A simple metric output format of "MetricName {Value}"
@@ -108,10 +230,355 @@ impl LineFormat for SimpleFormat {
     }
 }
 
+/// A JSON Lines metric output format, printing each value as a single line JSON object:
+/// `{"name":"a.b","parts":["a","b"],"kind":"Timer","value":1500,"scaled_value":1.5,"timestamp":1700000000000,"labels":{"k":"v"}}`
+/// Timer values are scaled to milliseconds, the timestamp is in milliseconds since the Unix epoch.
+#[derive(Default)]
+pub struct JsonFormat;
+
+impl LineFormat for JsonFormat {
+    fn template(&self, name: &MetricName, kind: InputKind) -> LineTemplate {
+        let scale = match kind {
+            // timers are in µs, scale them to ms
+            InputKind::Timer => 1000.0,
+            _ => 1.0,
+        };
+        let parts: Vec<String> = name
+            .iter()
+            .map(|part| format!("\"{}\"", escape_json(part)))
+            .collect();
+        let header = format!(
+            "{{\"name\":\"{}\",\"parts\":[{}],\"kind\":\"{kind:?}\",\"value\":",
+            escape_json(&name.join(".")),
+            parts.join(",")
+        );
+        LineTemplate {
+            ops: vec![
+                Literal(header.into_bytes()),
+                ValueAsText,
+                Literal(",\"scaled_value\":".into()),
+                ScaledValueAsText(scale),
+                Literal(",\"timestamp\":".into()),
+                TimestampMillis,
+                Literal(",\"labels\":{".into()),
+                AllLabels(
+                    vec![
+                        LabelOp::Literal("\"".into()),
+                        LabelOp::JsonLabelKey,
+                        LabelOp::Literal("\":\"".into()),
+                        LabelOp::JsonLabelValue,
+                        LabelOp::Literal("\"".into()),
+                    ],
+                    ",".into(),
+                ),
+                Literal("}}".into()),
+                NewLine,
+            ],
+        }
+    }
+}
+
+/// CloudWatch ignores dimensions beyond this count.
+const MAX_EMF_DIMENSIONS: usize = 30;
+
+/// CloudWatch rejects documents defining more metrics than this.
+const MAX_EMF_METRICS: usize = 100;
+
+/// CloudWatch rejects metrics with more values than this in a single document.
+const MAX_EMF_VALUES: usize = 100;
+
+/// Name, unit and values of a metric printed in an EMF document.
+type EmfMetric<'a> = (&'a str, &'a str, &'a [f64]);
+
+/// AWS CloudWatch Embedded Metric Format (EMF), printing values as single line JSON documents:
+/// `{"_aws":{"Timestamp":1700000000000,"CloudWatchMetrics":[{"Namespace":"MyApp","Dimensions":[["host"]],
+/// "Metrics":[{"Name":"app.timer_a","Unit":"Milliseconds"}]}]},"host":"h1","app.timer_a":1.5}`
+///
+/// Labels are used as dimensions. Written to stdout (e.g. from a Lambda function) or to a log file
+/// collected by the CloudWatch agent, the documents are turned into CloudWatch metrics.
+/// Buffered outputs print a single document for all the values sharing the same labels at every flush,
+/// values written more than once are sent as an array of up to 100 values, the rest in following documents.
+/// Timer values are scaled to milliseconds.
+///
+/// Labels named `_aws` or like one of the metrics of the document are skipped.
+/// Only the first 30 labels, by key, are used as dimensions.
+#[derive(Clone)]
+pub struct EmfFormat {
+    namespace: String,
+}
+
+impl EmfFormat {
+    /// Publish metrics to the specified CloudWatch namespace.
+    pub fn new(namespace: &str) -> Self {
+        EmfFormat {
+            namespace: namespace.to_string(),
+        }
+    }
+
+    /// Print a document defining the metrics, each with their values.
+    /// The document is returned whole so that it can be written at once.
+    fn print_document(&self, labels: &[(String, Arc<String>)], metrics: &[EmfMetric]) -> Vec<u8> {
+        let labels: Vec<_> = labels
+            .iter()
+            .filter(|(key, _)| {
+                !key.is_empty() && key != "_aws" && !metrics.iter().any(|(name, ..)| name == key)
+            })
+            .collect();
+
+        // writing to a Vec never fails
+        let mut output = vec![];
+        let _ = write!(
+            output,
+            "{{\"_aws\":{{\"Timestamp\":{},\"CloudWatchMetrics\":[{{\"Namespace\":\"{}\",",
+            epoch().as_millis(),
+            escape_json(&self.namespace)
+        );
+        if !labels.is_empty() {
+            let dimensions: Vec<_> = labels
+                .iter()
+                .take(MAX_EMF_DIMENSIONS)
+                .map(|(key, _)| format!("\"{}\"", escape_json(key)))
+                .collect();
+            let _ = write!(output, "\"Dimensions\":[[{}]],", dimensions.join(","));
+        }
+        let definitions: Vec<_> = metrics
+            .iter()
+            .map(|(name, unit, _)| {
+                format!("{{\"Name\":\"{}\",\"Unit\":\"{unit}\"}}", escape_json(name))
+            })
+            .collect();
+        let _ = write!(output, "\"Metrics\":[{}]}}]}}", definitions.join(","));
+
+        // dimension values are members of the root object
+        for (key, value) in labels {
+            let _ = write!(
+                output,
+                ",\"{}\":\"{}\"",
+                escape_json(key),
+                escape_json(value)
+            );
+        }
+        for (name, _, values) in metrics {
+            let _ = write!(output, ",\"{}\":", escape_json(name));
+            let _ = match values {
+                [value] => write!(output, "{value}"),
+                values => {
+                    let values: Vec<_> = values.iter().map(|value| value.to_string()).collect();
+                    write!(output, "[{}]", values.join(","))
+                }
+            };
+        }
+        let _ = writeln!(output, "}}");
+        output
+    }
+}
+
+/// CloudWatch unit and scale of the metric kind.
+fn emf_unit(kind: InputKind) -> (&'static str, f64) {
+    match kind {
+        // timers are in µs, scale them to ms
+        InputKind::Timer => ("Milliseconds", 1000.0),
+        InputKind::Marker | InputKind::Counter => ("Count", 1.0),
+        _ => ("None", 1.0),
+    }
+}
+
+impl LineFormat for EmfFormat {
+    fn template(&self, name: &MetricName, kind: InputKind) -> LineTemplate {
+        LineTemplate {
+            ops: vec![Batch(Arc::new(self.clone()), name.clone(), kind)],
+        }
+    }
+
+    fn batch(&self) -> Option<Arc<dyn BatchFormat>> {
+        Some(Arc::new(self.clone()))
+    }
+}
+
+impl BatchFormat for EmfFormat {
+    fn print_batch(&self, output: &mut dyn Write, values: &[BatchValue]) -> io::Result<()> {
+        // values sharing the same labels are printed in the same document
+        type Metrics<'a> = BTreeMap<String, (&'a str, Vec<f64>)>;
+        let mut documents: BTreeMap<Vec<(String, Arc<String>)>, Metrics> = BTreeMap::new();
+        for value in values {
+            let mut labels: Vec<_> = value.labels.clone().into_map().into_iter().collect();
+            labels.sort();
+            let (unit, scale) = emf_unit(value.kind);
+            documents
+                .entry(labels)
+                .or_default()
+                .entry(value.name.join("."))
+                .or_insert_with(|| (unit, Vec::new()))
+                .1
+                .push(value.value as f64 / scale);
+        }
+
+        for (labels, metrics) in documents {
+            let metrics: Vec<_> = metrics.into_iter().collect();
+            for metrics in metrics.chunks(MAX_EMF_METRICS) {
+                // values beyond the limit of a document are printed in the following ones
+                for offset in (0..).step_by(MAX_EMF_VALUES) {
+                    let chunk: Vec<EmfMetric> = metrics
+                        .iter()
+                        .filter(|(_, (_, values))| values.len() > offset)
+                        .map(|(name, (unit, values))| {
+                            let end = values.len().min(offset + MAX_EMF_VALUES);
+                            (name.as_str(), *unit, &values[offset..end])
+                        })
+                        .collect();
+                    if chunk.is_empty() {
+                        break;
+                    }
+                    output.write_all(&self.print_document(&labels, &chunk))?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A metric output format parsed from a template string, e.g.
+/// `{name:.} {value:/1000} {label:host?host=}{newline}`
+///
+/// Text outside of braces is printed as is, `{{` and `}}` print literal braces.
+/// Placeholders are:
+/// - `{name}` the metric name parts joined by dots, `{name:SEP}` joined by SEP instead
+/// - `{value}` the metric value, `{value:/N}` the metric value divided by N (e.g. `/1000` for timers in ms)
+/// - `{kind}` the metric kind, e.g. `Counter`
+/// - `{timestamp}` the Unix time in seconds, `{timestamp:ms}` in milliseconds
+/// - `{label:KEY}` the value of label KEY, `{label:KEY?PREFIX}` preceded by PREFIX.
+///   Nothing is printed if the label is not set.
+/// - `{labels}` all labels as `key=value` pairs separated by commas, `{labels:SEP}` separated by SEP
+/// - `{newline}` the newline character
+pub struct TemplateFormat {
+    parts: Vec<TemplatePart>,
+}
+
+/// A parsed template element.
+enum TemplatePart {
+    Literal(String),
+    Name(String),
+    Value,
+    ScaledValue(f64),
+    Kind,
+    Timestamp,
+    TimestampMillis,
+    Label(String, String),
+    Labels(String),
+    NewLine,
+}
+
+impl TemplateFormat {
+    /// Parse a template string, returning an `InvalidInput` error describing any syntax error.
+    pub fn parse(template: &str) -> io::Result<TemplateFormat> {
+        let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidInput, msg);
+        let mut parts = vec![];
+        let mut literal = String::new();
+        let mut chars = template.char_indices().peekable();
+        while let Some((pos, c)) = chars.next() {
+            match c {
+                '{' if chars.next_if(|(_, c)| *c == '{').is_some() => literal.push('{'),
+                '}' if chars.next_if(|(_, c)| *c == '}').is_some() => literal.push('}'),
+                '}' => return Err(invalid(format!("Unmatched '}}' at {pos}"))),
+                '{' => {
+                    let mut placeholder = String::new();
+                    loop {
+                        match chars.next() {
+                            Some((_, '}')) => break,
+                            Some((_, c)) => placeholder.push(c),
+                            None => return Err(invalid(format!("Unclosed '{{' at {pos}"))),
+                        }
+                    }
+                    if !literal.is_empty() {
+                        parts.push(TemplatePart::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(Self::parse_placeholder(&placeholder).map_err(invalid)?);
+                }
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            parts.push(TemplatePart::Literal(literal));
+        }
+        Ok(TemplateFormat { parts })
+    }
+
+    fn parse_placeholder(placeholder: &str) -> Result<TemplatePart, String> {
+        let (name, arg) = match placeholder.split_once(':') {
+            Some((name, arg)) => (name, Some(arg)),
+            None => (placeholder, None),
+        };
+        Ok(match (name, arg) {
+            ("name", None) => TemplatePart::Name(".".to_string()),
+            ("name", Some(separator)) => TemplatePart::Name(separator.to_string()),
+            ("value", None) => TemplatePart::Value,
+            ("value", Some(scale)) => match scale.strip_prefix('/').map(str::parse::<f64>) {
+                Some(Ok(scale)) if scale != 0.0 => TemplatePart::ScaledValue(scale),
+                _ => return Err(format!("Invalid value scale '{scale}', expected '/N'")),
+            },
+            ("kind", None) => TemplatePart::Kind,
+            ("timestamp", None) => TemplatePart::Timestamp,
+            ("timestamp", Some("ms")) => TemplatePart::TimestampMillis,
+            ("label", Some(arg)) => match arg.split_once('?') {
+                Some((key, prefix)) => TemplatePart::Label(key.to_string(), prefix.to_string()),
+                None => TemplatePart::Label(arg.to_string(), String::new()),
+            },
+            ("labels", None) => TemplatePart::Labels(",".to_string()),
+            ("labels", Some(separator)) => TemplatePart::Labels(separator.to_string()),
+            ("newline", None) => TemplatePart::NewLine,
+            _ => return Err(format!("Unknown placeholder '{{{placeholder}}}'")),
+        })
+    }
+}
+
+impl std::str::FromStr for TemplateFormat {
+    type Err = io::Error;
+
+    fn from_str(template: &str) -> io::Result<Self> {
+        TemplateFormat::parse(template)
+    }
+}
+
+impl LineFormat for TemplateFormat {
+    fn template(&self, name: &MetricName, kind: InputKind) -> LineTemplate {
+        let mut ops = vec![];
+        for part in &self.parts {
+            let op = match part {
+                TemplatePart::Literal(text) => Literal(text.clone().into_bytes()),
+                TemplatePart::Name(separator) => Literal(name.join(separator).into_bytes()),
+                TemplatePart::Value => ValueAsText,
+                TemplatePart::ScaledValue(scale) => ScaledValueAsText(*scale),
+                TemplatePart::Kind => Literal(format!("{kind:?}").into_bytes()),
+                TemplatePart::Timestamp => Timestamp,
+                TemplatePart::TimestampMillis => TimestampMillis,
+                TemplatePart::Label(key, prefix) => LabelExists(
+                    key.clone(),
+                    vec![
+                        LabelOp::Literal(prefix.clone().into_bytes()),
+                        LabelOp::LabelValue,
+                    ],
+                ),
+                TemplatePart::Labels(separator) => AllLabels(
+                    vec![
+                        LabelOp::LabelKey,
+                        LabelOp::Literal("=".into()),
+                        LabelOp::LabelValue,
+                    ],
+                    separator.clone().into_bytes(),
+                ),
+                TemplatePart::NewLine => NewLine,
+            };
+            ops.push(op);
+        }
+        LineTemplate { ops }
+    }
+}
+
 #[cfg(test)]
 pub mod test {
     use super::*;
     use crate::label::Labels;
+    use crate::label::test::TEST_SEQUENCE;
 
     pub struct TestFormat;
 
@@ -172,4 +639,180 @@ pub mod test {
             String::from_utf8(out).unwrap()
         );
     }
+
+    #[test]
+    fn print_parsed_template() {
+        let _lock = TEST_SEQUENCE.lock().expect("Test Sequence");
+        let format =
+            TemplateFormat::parse("{kind} {name:_} {value:/1000} {{{value}}}{label:host?host=}{label:nope?x=} [{labels:;}]{newline}")
+                .unwrap();
+        let name = MetricName::from("timer_a").prepend("app");
+        let template = format.template(&name, InputKind::Timer);
+        let mut out = vec![];
+        let labels = labels!("host" => "h1", "dc" => "east");
+        template.print_labels(&mut out, 1500, &labels).unwrap();
+        assert_eq!(
+            "Timer app_timer_a 1.5 {1500}host=h1 [dc=east;host=h1]\n",
+            String::from_utf8(out).unwrap()
+        );
+    }
+
+    #[test]
+    fn parse_template_errors() {
+        for bad in [
+            "{name",
+            "name}",
+            "{value:1000}",
+            "{value:/0}",
+            "{bogus}",
+            "{label}",
+        ] {
+            let err = TemplateFormat::parse(bad).err().expect(bad);
+            assert_eq!(io::ErrorKind::InvalidInput, err.kind());
+        }
+    }
+
+    #[test]
+    fn print_json() {
+        let _lock = TEST_SEQUENCE.lock().expect("Test Sequence");
+        let labels: Labels = labels!("path" => "/\"a\"", "host" => "h1");
+        let name = MetricName::from("timer_a").prepend("app");
+        let template = JsonFormat.template(&name, InputKind::Timer);
+        let mut out = vec![];
+        template.print_labels(&mut out, 1500, &labels).unwrap();
+        let out = String::from_utf8(out).unwrap();
+
+        let timestamp_start = out.find("\"timestamp\":").unwrap() + 12;
+        let timestamp_end = out.find(",\"labels\"").unwrap();
+        assert!(out[timestamp_start..timestamp_end].parse::<u64>().is_ok());
+        assert_eq!(
+            "{\"name\":\"app.timer_a\",\"parts\":[\"app\",\"timer_a\"],\"kind\":\"Timer\",\
+             \"value\":1500,\"scaled_value\":1.5,\"timestamp\":,\
+             \"labels\":{\"host\":\"h1\",\"path\":\"/\\\"a\\\"\"}}\n",
+            format!("{}{}", &out[..timestamp_start], &out[timestamp_end..])
+        );
+    }
+
+    /// Replace the variable timestamp of every EMF document.
+    fn zero_timestamps(out: &str) -> String {
+        out.lines()
+            .map(|line| {
+                let start = line.find("\"Timestamp\":").unwrap() + 12;
+                let end = line.find(",\"CloudWatchMetrics\"").unwrap();
+                assert!(line[start..end].parse::<u64>().is_ok());
+                format!("{}0{}\n", &line[..start], &line[end..])
+            })
+            .collect()
+    }
+
+    #[test]
+    fn print_emf() {
+        let _lock = TEST_SEQUENCE.lock().expect("Test Sequence");
+        let labels: Labels = labels!("path" => "/\"a\"", "host" => "h1");
+        let name = MetricName::from("timer_a").prepend("app");
+        let template = EmfFormat::new("MyApp").template(&name, InputKind::Timer);
+        let mut out = vec![];
+        template.print_labels(&mut out, 1500, &labels).unwrap();
+        assert_eq!(
+            "{\"_aws\":{\"Timestamp\":0,\"CloudWatchMetrics\":[{\"Namespace\":\"MyApp\",\
+             \"Dimensions\":[[\"host\",\"path\"]],\"Metrics\":[{\"Name\":\"app.timer_a\",\"Unit\":\"Milliseconds\"}]}]},\
+             \"host\":\"h1\",\"path\":\"/\\\"a\\\"\",\"app.timer_a\":1.5}\n",
+            zero_timestamps(&String::from_utf8(out).unwrap())
+        );
+
+        // no labels, no dimensions
+        let template = EmfFormat::new("MyApp").template(&name, InputKind::Counter);
+        let mut out = vec![];
+        template.print_labels(&mut out, 3, &labels![]).unwrap();
+        assert_eq!(
+            "{\"_aws\":{\"Timestamp\":0,\"CloudWatchMetrics\":[{\"Namespace\":\"MyApp\",\
+             \"Metrics\":[{\"Name\":\"app.timer_a\",\"Unit\":\"Count\"}]}]},\"app.timer_a\":3}\n",
+            zero_timestamps(&String::from_utf8(out).unwrap())
+        );
+    }
+
+    #[test]
+    fn print_emf_batch() {
+        let _lock = TEST_SEQUENCE.lock().expect("Test Sequence");
+        let value = |name: &str, kind, value, labels| BatchValue {
+            name: MetricName::from(name),
+            kind,
+            value,
+            labels,
+        };
+        let values = [
+            value("c", InputKind::Counter, 1, labels!("host" => "h1")),
+            value("g", InputKind::Gauge, 5, labels!("host" => "h1")),
+            value("c", InputKind::Counter, 2, labels!("host" => "h1")),
+            value("c", InputKind::Counter, 4, labels!("host" => "h2")),
+        ];
+        let mut out = vec![];
+        EmfFormat::new("MyApp")
+            .print_batch(&mut out, &values)
+            .unwrap();
+        assert_eq!(
+            "{\"_aws\":{\"Timestamp\":0,\"CloudWatchMetrics\":[{\"Namespace\":\"MyApp\",\
+             \"Dimensions\":[[\"host\"]],\"Metrics\":[{\"Name\":\"c\",\"Unit\":\"Count\"},\
+             {\"Name\":\"g\",\"Unit\":\"None\"}]}]},\"host\":\"h1\",\"c\":[1,2],\"g\":5}\n\
+             {\"_aws\":{\"Timestamp\":0,\"CloudWatchMetrics\":[{\"Namespace\":\"MyApp\",\
+             \"Dimensions\":[[\"host\"]],\"Metrics\":[{\"Name\":\"c\",\"Unit\":\"Count\"}]}]},\
+             \"host\":\"h2\",\"c\":4}\n",
+            zero_timestamps(&String::from_utf8(out).unwrap())
+        );
+    }
+
+    #[test]
+    fn emf_value_limit() {
+        let _lock = TEST_SEQUENCE.lock().expect("Test Sequence");
+        let values: Vec<_> = (0..MAX_EMF_VALUES + 1)
+            .map(|i| BatchValue {
+                name: MetricName::from("c"),
+                kind: InputKind::Counter,
+                value: i as MetricValue,
+                labels: labels![],
+            })
+            .chain([BatchValue {
+                name: MetricName::from("g"),
+                kind: InputKind::Gauge,
+                value: 5,
+                labels: labels![],
+            }])
+            .collect();
+        let mut out = vec![];
+        EmfFormat::new("MyApp")
+            .print_batch(&mut out, &values)
+            .unwrap();
+        let out = String::from_utf8(out).unwrap();
+
+        let documents: Vec<_> = out.lines().collect();
+        assert_eq!(2, documents.len());
+        let first: Vec<_> = (0..MAX_EMF_VALUES).map(|i| i.to_string()).collect();
+        assert!(documents[0].ends_with(&format!(",\"c\":[{}],\"g\":5}}", first.join(","))));
+        assert!(documents[1].ends_with(&format!(",\"c\":{MAX_EMF_VALUES}}}")));
+        assert!(!documents[1].contains("\"g\""));
+    }
+
+    #[test]
+    fn emf_label_limits() {
+        let _lock = TEST_SEQUENCE.lock().expect("Test Sequence");
+        let mut map = std::collections::HashMap::new();
+        for i in 0..32 {
+            map.insert(format!("k{i:02}"), Arc::new("v".to_string()));
+        }
+        map.insert("_aws".to_string(), Arc::new("x".to_string()));
+        map.insert("g".to_string(), Arc::new("x".to_string()));
+        let template = EmfFormat::new("MyApp").template(&MetricName::from("g"), InputKind::Gauge);
+        let mut out = vec![];
+        template
+            .print_labels(&mut out, 1, &Labels::from(map))
+            .unwrap();
+        let out = String::from_utf8(out).unwrap();
+
+        let dimensions: Vec<_> = (0..30).map(|i| format!("\"k{i:02}\"")).collect();
+        assert!(out.contains(&format!("\"Dimensions\":[[{}]]", dimensions.join(","))));
+        // extra labels are still properties of the document
+        assert!(out.contains(",\"k31\":\"v\","));
+        assert!(!out.contains("\"_aws\":\"x\""));
+        assert!(out.ends_with(",\"g\":1}\n"));
+    }
 }
@@ -1,8 +1,11 @@
 //! Send metrics to a graphite server.
 
-use crate::attributes::{Attributes, Buffered, MetricId, OnFlush, Prefixed, WithAttributes};
+use crate::attributes::{
+    Attributes, Buffered, MAX_PENDING_BYTES, MetricId, OnFlush, Prefixed, WithAttributes,
+};
 use crate::input::InputKind;
 use crate::input::{Input, InputMetric, InputScope};
+use crate::label::Labels;
 use crate::metrics;
 use crate::name::MetricName;
 use crate::output::socket::RetrySocket;
@@ -24,12 +27,24 @@ use std::sync::{RwLock, RwLockWriteGuard};
 use parking_lot::{RwLock, RwLockWriteGuard};
 use std::io;
 
+/// Wire format of the points sent to graphite.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub enum GraphiteProtocol {
+    /// One `name value timestamp` text line per point.
+    #[default]
+    Plaintext,
+    /// Length-prefixed pickled lists of points, cheaper for carbon relays to process.
+    Pickle,
+}
+
 /// Graphite Input holds a socket to a graphite server.
 /// The socket is shared between scopes opened from the Input.
 #[derive(Clone, Debug)]
 pub struct Graphite {
     attributes: Attributes,
     socket: Arc<RwLock<RetrySocket>>,
+    tagged: bool,
+    protocol: GraphiteProtocol,
 }
 
 impl Input for Graphite {
@@ -38,8 +53,10 @@ impl Input for Graphite {
     fn metrics(&self) -> Self::SCOPE {
         GraphiteScope {
             attributes: self.attributes.clone(),
-            buffer: Arc::new(RwLock::new(String::new())),
+            buffer: Arc::new(RwLock::new(Vec::new())),
             socket: self.socket.clone(),
+            tagged: self.tagged,
+            protocol: self.protocol,
         }
     }
 }
@@ -53,8 +70,26 @@ impl Graphite {
         Ok(Graphite {
             attributes: Attributes::default(),
             socket,
+            tagged: false,
+            protocol: GraphiteProtocol::default(),
         })
     }
+
+    /// Use the specified wire format for scopes opened afterwards.
+    /// The pickle protocol is usually received on port 2004.
+    pub fn protocol(&self, protocol: GraphiteProtocol) -> Self {
+        let mut cloned = self.clone();
+        cloned.protocol = protocol;
+        cloned
+    }
+
+    /// Send metric labels as graphite tags (`name;tag=value`) from scopes opened afterwards.
+    /// Requires Carbon 1.1 or later.
+    pub fn tagged(&self, tagged: bool) -> Self {
+        let mut cloned = self.clone();
+        cloned.tagged = tagged;
+        cloned
+    }
 }
 
 impl WithAttributes for Graphite {
@@ -72,15 +107,19 @@ impl Buffered for Graphite {}
 #[derive(Debug, Clone)]
 pub struct GraphiteScope {
     attributes: Attributes,
-    buffer: Arc<RwLock<String>>,
+    buffer: Arc<RwLock<Vec<u8>>>,
     socket: Arc<RwLock<RetrySocket>>,
+    tagged: bool,
+    protocol: GraphiteProtocol,
 }
 
 impl InputScope for GraphiteScope {
     /// Define a metric of the specified type.
     fn new_metric(&self, name: MetricName, kind: InputKind) -> InputMetric {
         let mut prefix = self.prefix_prepend(name.clone()).join(".");
-        prefix.push(' ');
+        if self.tagged {
+            prefix = sanitize_tagged_name(&prefix);
+        }
 
         let scale = match kind {
             // timers are in µs, but we give graphite milliseconds
@@ -92,8 +131,8 @@ impl InputScope for GraphiteScope {
         let metric = GraphiteMetric { prefix, scale };
         let metric_id = MetricId::forge("graphite", name);
 
-        InputMetric::new(metric_id, move |value, _labels| {
-            cloned.print(&metric, value);
+        InputMetric::new(metric_id, move |value, labels| {
+            cloned.print(&metric, value, labels);
         })
     }
 }
@@ -107,50 +146,74 @@ impl Flush for GraphiteScope {
 }
 
 impl GraphiteScope {
-    fn print(&self, metric: &GraphiteMetric, value: MetricValue) {
+    fn print(&self, metric: &GraphiteMetric, value: MetricValue, labels: Labels) {
         let scaled_value = value / metric.scale;
-        let value_str = scaled_value.to_string();
-
-        let start = SystemTime::now();
+        let tags = if self.tagged {
+            format_tags(labels)
+        } else {
+            String::new()
+        };
 
-        let mut buffer = write_lock!(self.buffer);
-        match start.duration_since(UNIX_EPOCH) {
-            Ok(timestamp) => {
-                buffer.push_str(&metric.prefix);
-                buffer.push_str(&value_str);
-                buffer.push(' ');
-                buffer.push_str(&timestamp.as_secs().to_string());
-                buffer.push('\n');
-
-                if buffer.len() > BUFFER_FLUSH_THRESHOLD {
-                    metrics::GRAPHITE_OVERFLOW.mark();
-                    warn!("Graphite Buffer Size Exceeded: {BUFFER_FLUSH_THRESHOLD}");
-                    let _ = self.flush_inner(buffer);
-                    buffer = write_lock!(self.buffer);
-                }
-            }
+        let timestamp = match SystemTime::now().duration_since(UNIX_EPOCH) {
+            Ok(timestamp) => timestamp.as_secs(),
             Err(e) => {
                 warn!("Could not compute epoch timestamp. {e}");
+                return;
             }
         };
 
-        if self.is_buffered() {
-            if let Err(e) = self.flush_inner(buffer) {
-                debug!("Could not send to graphite {e}")
+        let mut point = Vec::new();
+        match self.protocol {
+            GraphiteProtocol::Plaintext => {
+                let _ = writeln!(
+                    point,
+                    "{}{} {} {}",
+                    metric.prefix, tags, scaled_value, timestamp
+                );
+            }
+            GraphiteProtocol::Pickle => {
+                let path = format!("{}{}", metric.prefix, tags);
+                pickle::point(&mut point, &path, timestamp, scaled_value);
             }
         }
+
+        let mut buffer = write_lock!(self.buffer);
+        let full = if buffer.len() + point.len() > MAX_PENDING_BYTES {
+            metrics::GRAPHITE_PENDING_DROPPED.mark();
+            debug!(
+                "Graphite pending points exceed {MAX_PENDING_BYTES} bytes, dropping {}",
+                metric.prefix
+            );
+            // unbuffered scopes still try to send what is pending
+            false
+        } else {
+            buffer.extend_from_slice(&point);
+            self.is_buffer_full(buffer.len())
+        };
+
+        if (!self.is_buffered() || full)
+            && let Err(e) = self.flush_inner(buffer)
+        {
+            debug!("Could not send to graphite {e}")
+        }
     }
 
-    fn flush_inner(&self, mut buf: RwLockWriteGuard<String>) -> io::Result<()> {
+    fn flush_inner(&self, mut buf: RwLockWriteGuard<Vec<u8>>) -> io::Result<()> {
         if buf.is_empty() {
             return Ok(());
         }
 
+        let payload = match self.protocol {
+            GraphiteProtocol::Plaintext => None,
+            GraphiteProtocol::Pickle => Some(pickle::frame(&buf)),
+        };
+        let payload = payload.as_deref().unwrap_or(&buf);
+
         let mut sock = write_lock!(self.socket);
-        match sock.write_all(buf.as_bytes()) {
+        match sock.write_all(payload) {
             Ok(()) => {
-                metrics::GRAPHITE_SENT_BYTES.count(buf.len());
-                trace!("Sent {} bytes to graphite", buf.len());
+                metrics::GRAPHITE_SENT_BYTES.count(payload.len());
+                trace!("Sent {} bytes to graphite", payload.len());
                 buf.clear();
                 Ok(())
             }
@@ -177,10 +240,6 @@ impl Buffered for GraphiteScope {}
 impl QueuedInput for Graphite {}
 impl CachedInput for Graphite {}
 
-/// Its hard to see how a single scope could get more metrics than this.
-// TODO make configurable?
-const BUFFER_FLUSH_THRESHOLD: usize = 65_536;
-
 /// Key of a graphite metric.
 #[derive(Debug, Clone)]
 pub struct GraphiteMetric {
@@ -188,6 +247,105 @@ pub struct GraphiteMetric {
     scale: isize,
 }
 
+/// Minimal pickle (protocol 2) encoding of a `[(path, (timestamp, value)), ...]` list.
+mod pickle {
+    const PROTO: u8 = 0x80;
+    const EMPTY_LIST: u8 = b']';
+    const MARK: u8 = b'(';
+    const APPENDS: u8 = b'e';
+    const STOP: u8 = b'.';
+    const BINUNICODE: u8 = b'X';
+    const BININT: u8 = b'J';
+    const LONG1: u8 = 0x8a;
+    const TUPLE2: u8 = 0x86;
+
+    /// Append a pickled `(path, (timestamp, value))` tuple.
+    pub fn point(buf: &mut Vec<u8>, path: &str, timestamp: u64, value: isize) {
+        buf.push(BINUNICODE);
+        buf.extend_from_slice(&(path.len() as u32).to_le_bytes());
+        buf.extend_from_slice(path.as_bytes());
+        int(buf, timestamp as i64);
+        int(buf, value as i64);
+        buf.push(TUPLE2);
+        buf.push(TUPLE2);
+    }
+
+    fn int(buf: &mut Vec<u8>, value: i64) {
+        match i32::try_from(value) {
+            Ok(small) => {
+                buf.push(BININT);
+                buf.extend_from_slice(&small.to_le_bytes());
+            }
+            Err(_) => {
+                buf.push(LONG1);
+                buf.push(8);
+                buf.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+    }
+
+    /// Wrap pickled points in a list, prefixed by the payload's big-endian length.
+    pub fn frame(points: &[u8]) -> Vec<u8> {
+        let payload_len = points.len() + 6;
+        let mut framed = Vec::with_capacity(payload_len + 4);
+        framed.extend_from_slice(&(payload_len as u32).to_be_bytes());
+        framed.extend_from_slice(&[PROTO, 2, EMPTY_LIST, MARK]);
+        framed.extend_from_slice(points);
+        framed.extend_from_slice(&[APPENDS, STOP]);
+        framed
+    }
+}
+
+/// Replace the characters that would be mistaken for tags or field separators by an underscore.
+pub(crate) fn sanitize_tagged_name(name: &str) -> String {
+    name.chars()
+        .map(|c| match c {
+            ';' => '_',
+            c if c.is_whitespace() => '_',
+            c => c,
+        })
+        .collect()
+}
+
+/// Print labels sorted by key as `;key=value...` graphite tags, or nothing if there are no labels.
+/// Tag keys may not contain `;!^=`, tag values may not contain `;` nor start with `~`.
+/// Both are limited to non-blank ASCII characters, anything else is replaced by an underscore.
+/// Labels with an empty key or value are skipped.
+pub(crate) fn format_tags(labels: Labels) -> String {
+    fn sanitize(part: &str, forbidden: &str) -> String {
+        part.chars()
+            .map(|c| {
+                if !c.is_ascii_graphic() || forbidden.contains(c) {
+                    '_'
+                } else {
+                    c
+                }
+            })
+            .collect()
+    }
+
+    let mut labels: Vec<_> = labels.into_map().into_iter().collect();
+    labels.sort();
+
+    let mut tags = String::new();
+    for (key, value) in labels {
+        if key.is_empty() || value.is_empty() {
+            continue;
+        }
+        tags.push(';');
+        tags.push_str(&sanitize(&key, ";!^="));
+        tags.push('=');
+        let value = sanitize(&value, ";");
+        if let Some(stripped) = value.strip_prefix('~') {
+            tags.push('_');
+            tags.push_str(stripped);
+        } else {
+            tags.push_str(&value);
+        }
+    }
+    tags
+}
+
 /// Any remaining buffered data is flushed on Drop.
 impl Drop for GraphiteScope {
     fn drop(&mut self) {
@@ -197,6 +355,74 @@ impl Drop for GraphiteScope {
     }
 }
 
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::label::test::TEST_SEQUENCE;
+    use std::io::Read;
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn sanitize_tags() {
+        let _lock = TEST_SEQUENCE.lock().expect("Test Sequence");
+        assert_eq!(
+            ";a_b_c=_x;path=/a_b;z=_tilde",
+            format_tags(
+                labels!("z" => "~tilde", "a!b=c" => "~x", "path" => "/a;b", "" => "e", "f" => "")
+            )
+        );
+        assert_eq!("a_b_c", sanitize_tagged_name("a;b c"));
+    }
+
+    #[test]
+    fn pickle_points() {
+        let mut points = Vec::new();
+        pickle::point(&mut points, "app.a;k=v", 1_700_000_000, -3);
+        pickle::point(&mut points, "app.b", 1_700_000_000, 1 << 40);
+        let framed = pickle::frame(&points);
+
+        // as produced by python's pickle.dumps([("app.a;k=v", (1700000000, -3)), ...], 2)
+        let mut expected = vec![0, 0, 0, 59, 0x80, 2, b']', b'(', b'X', 9, 0, 0, 0];
+        expected.extend_from_slice(b"app.a;k=v");
+        expected.extend_from_slice(&[b'J', 0x00, 0xf1, 0x53, 0x65]);
+        expected.extend_from_slice(&[b'J', 0xfd, 0xff, 0xff, 0xff, 0x86, 0x86]);
+        expected.extend_from_slice(&[b'X', 5, 0, 0, 0]);
+        expected.extend_from_slice(b"app.b");
+        expected.extend_from_slice(&[b'J', 0x00, 0xf1, 0x53, 0x65]);
+        expected.extend_from_slice(&[0x8a, 8, 0, 0, 0, 0, 0, 1, 0, 0, 0x86, 0x86]);
+        expected.extend_from_slice(b"e.");
+        assert_eq!(expected, framed);
+    }
+
+    #[test]
+    fn unbuffered_pickle() {
+        let _lock = TEST_SEQUENCE.lock().expect("Test Sequence");
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let metrics = Graphite::send_to(listener.local_addr().unwrap())
+            .unwrap()
+            .protocol(GraphiteProtocol::Pickle)
+            .metrics();
+
+        let gauge = metrics.gauge("gauge_a");
+        gauge.value(5);
+        // the socket connects once its initial reconnect delay expires, points are kept until then
+        while metrics.flush().is_err() {
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut len = [0; 4];
+        stream.read_exact(&mut len).unwrap();
+        let mut payload = vec![0; u32::from_be_bytes(len) as usize];
+        stream.read_exact(&mut payload).unwrap();
+        assert_eq!(&[0x80, 2, b']', b'(', b'X', 7, 0, 0, 0], &payload[..9]);
+        assert_eq!(b"gauge_a", &payload[9..16]);
+        assert_eq!(&[b'J', 5, 0, 0, 0, 0x86, 0x86, b'e', b'.'], &payload[21..]);
+    }
+}
+
 #[cfg(feature = "bench")]
 mod bench {
 
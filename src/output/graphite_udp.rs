@@ -3,16 +3,21 @@
 use crate::attributes::{Attributes, Buffered, MetricId, OnFlush, Prefixed, WithAttributes};
 use crate::input::InputKind;
 use crate::input::{Input, InputMetric, InputScope};
+use crate::label::Labels;
 use crate::metrics;
 use crate::name::MetricName;
+use crate::output::datagram::{DatagramBatch, Push};
+use crate::output::graphite::{format_tags, sanitize_tagged_name};
+use crate::output::socket::DatagramSocket;
 
 use crate::{CachedInput, QueuedInput};
 use crate::{Flush, MetricValue};
 
 use std::net::ToSocketAddrs;
+#[cfg(unix)]
+use std::path::Path;
 
 use std::fmt::Debug;
-use std::net::UdpSocket;
 use std::sync::Arc;
 
 use std::time::{SystemTime, UNIX_EPOCH};
@@ -24,15 +29,14 @@ use std::sync::{RwLock, RwLockWriteGuard};
 use parking_lot::{RwLock, RwLockWriteGuard};
 use std::io;
 
-/// Use a safe maximum size for UDP to prevent fragmentation.
-const MAX_UDP_PAYLOAD: usize = 576;
-
 /// GraphiteUdp Input holds a socket to a graphite server.
 /// The socket is shared between scopes opened from the Input.
 #[derive(Clone, Debug)]
 pub struct GraphiteUdp {
     attributes: Attributes,
-    socket: Arc<UdpSocket>,
+    socket: Arc<DatagramSocket>,
+    max_payload: usize,
+    tagged: bool,
 }
 
 impl Input for GraphiteUdp {
@@ -41,8 +45,12 @@ impl Input for GraphiteUdp {
     fn metrics(&self) -> Self::SCOPE {
         GraphiteUdpScope {
             attributes: self.attributes.clone(),
-            buffer: Arc::new(RwLock::new(String::with_capacity(MAX_UDP_PAYLOAD))),
-            socket: self.socket.clone(),
+            batch: Arc::new(RwLock::new(DatagramBatch::new(
+                self.socket.clone(),
+                self.max_payload,
+                "",
+            ))),
+            tagged: self.tagged,
         }
     }
 }
@@ -50,15 +58,47 @@ impl Input for GraphiteUdp {
 impl GraphiteUdp {
     /// Send metrics to a graphite server at the address and port provided.
     pub fn send_to<ADDR: ToSocketAddrs>(address: ADDR) -> io::Result<GraphiteUdp> {
-        let socket = Arc::new(UdpSocket::bind("0.0.0.0:0")?);
-        socket.set_nonblocking(true)?;
-        socket.connect(address)?;
+        let socket = Arc::new(DatagramSocket::udp(address)?);
+
+        Ok(GraphiteUdp {
+            attributes: Attributes::default(),
+            max_payload: socket.max_payload(),
+            socket,
+            tagged: false,
+        })
+    }
+
+    /// Send metrics to a graphite agent listening on the Unix datagram socket at the path provided.
+    /// Unix sockets do not drop packets and allow larger payloads than UDP.
+    #[cfg(unix)]
+    pub fn send_to_unix<P: AsRef<Path>>(path: P) -> io::Result<GraphiteUdp> {
+        let socket = Arc::new(DatagramSocket::unix(path)?);
 
         Ok(GraphiteUdp {
             attributes: Attributes::default(),
+            max_payload: socket.max_payload(),
             socket,
+            tagged: false,
         })
     }
+
+    /// Send metric labels as graphite tags (`name;tag=value`) from scopes opened afterwards.
+    /// Requires Carbon 1.1 or later.
+    pub fn tagged(&self, tagged: bool) -> Self {
+        let mut cloned = self.clone();
+        cloned.tagged = tagged;
+        cloned
+    }
+
+    /// Send datagrams of up to the specified size from scopes opened afterwards.
+    /// Defaults to 576 bytes for UDP, which never gets fragmented.
+    /// Networks with a known MTU can use larger payloads, e.g. 1432 bytes for Ethernet
+    /// or 8932 bytes for jumbo frames.
+    pub fn max_payload(&self, bytes: usize) -> Self {
+        let mut cloned = self.clone();
+        cloned.max_payload = bytes;
+        cloned
+    }
 }
 
 impl WithAttributes for GraphiteUdp {
@@ -76,15 +116,17 @@ impl Buffered for GraphiteUdp {}
 #[derive(Debug, Clone)]
 pub struct GraphiteUdpScope {
     attributes: Attributes,
-    buffer: Arc<RwLock<String>>,
-    socket: Arc<UdpSocket>,
+    batch: Arc<RwLock<DatagramBatch>>,
+    tagged: bool,
 }
 
 impl InputScope for GraphiteUdpScope {
     /// Define a metric of the specified type.
     fn new_metric(&self, name: MetricName, kind: InputKind) -> InputMetric {
         let mut prefix = self.prefix_prepend(name.clone()).join(".");
-        prefix.push(' ');
+        if self.tagged {
+            prefix = sanitize_tagged_name(&prefix);
+        }
 
         let scale = match kind {
             // timers are in µs, but we give graphite milliseconds
@@ -96,8 +138,8 @@ impl InputScope for GraphiteUdpScope {
         let metric = GraphiteUdpMetric { prefix, scale };
         let metric_id = MetricId::forge("graphite", name);
 
-        InputMetric::new(metric_id, move |value, _labels| {
-            cloned.print(&metric, value);
+        InputMetric::new(metric_id, move |value, labels| {
+            cloned.print(&metric, value, labels);
         })
     }
 }
@@ -105,66 +147,63 @@ impl InputScope for GraphiteUdpScope {
 impl Flush for GraphiteUdpScope {
     fn flush(&self) -> io::Result<()> {
         self.notify_flush_listeners();
-        let buf = write_lock!(self.buffer);
-        self.flush_inner(buf)
+        let batch = write_lock!(self.batch);
+        self.flush_inner(batch)
     }
 }
 
 impl GraphiteUdpScope {
-    fn print(&self, metric: &GraphiteUdpMetric, value: MetricValue) {
+    fn print(&self, metric: &GraphiteUdpMetric, value: MetricValue, labels: Labels) {
         let scaled_value = value / metric.scale;
         let value_str = scaled_value.to_string();
-        let start = SystemTime::now();
-
-        let mut buffer = write_lock!(self.buffer);
-
-        match start.duration_since(UNIX_EPOCH) {
-            Ok(timestamp) => {
-                let metric = format!(
-                    "{}{} {}\n",
-                    &metric.prefix,
-                    &value_str,
-                    &timestamp.as_secs().to_string()
-                );
-                let entry_len = metric.len();
-                let available = buffer.capacity() - buffer.len();
-                if entry_len > buffer.capacity() {
-                    // entry simply too  big to fit in buffer
-                    return;
-                }
-                if entry_len > available {
-                    let _ = self.flush_inner(buffer);
-                    buffer = write_lock!(self.buffer);
-                }
-                buffer.push_str(&metric);
-            }
+        let tags = if self.tagged {
+            format_tags(labels)
+        } else {
+            String::new()
+        };
+        let timestamp = match SystemTime::now().duration_since(UNIX_EPOCH) {
+            Ok(timestamp) => timestamp.as_secs(),
             Err(e) => {
                 warn!("Could not compute epoch timestamp. {e}");
+                return;
             }
         };
-        if !self.is_buffered() {
-            if let Err(e) = self.flush_inner(buffer) {
-                debug!("Could not send to graphite {e}")
-            }
+        let entry = format!("{}{} {} {}\n", &metric.prefix, &tags, &value_str, timestamp);
+
+        let mut batch = write_lock!(self.batch);
+        let push = batch.push(&[&entry]);
+        if push == Push::Oversize {
+            metrics::GRAPHITE_OVERSIZE.mark();
+            debug!(
+                "Graphite entry too big to fit in a datagram, dropping {}",
+                metric.prefix
+            );
+            return;
+        }
+
+        if (!self.is_buffered() || push == Push::Full)
+            && let Err(e) = self.flush_inner(batch)
+        {
+            debug!("Could not send to graphite {e}")
         }
     }
 
-    fn flush_inner(&self, mut buffer: RwLockWriteGuard<String>) -> io::Result<()> {
-        if !buffer.is_empty() {
-            match self.socket.send(buffer.as_bytes()) {
-                Ok(size) => {
-                    metrics::GRAPHITE_SENT_BYTES.count(size);
-                    trace!("Sent {} bytes to graphite", buffer.len());
-                }
-                Err(e) => {
-                    metrics::GRAPHITE_SEND_ERR.mark();
-                    debug!("Failed to send buffer to graphite: {e}");
-                    return Err(e);
-                }
-            };
-            buffer.clear();
+    fn flush_inner(&self, mut batch: RwLockWriteGuard<DatagramBatch>) -> io::Result<()> {
+        if batch.is_empty() {
+            return Ok(());
+        }
+        match batch.send() {
+            Ok(size) => {
+                metrics::GRAPHITE_SENT_BYTES.count(size);
+                trace!("Sent {size} bytes to graphite");
+                Ok(())
+            }
+            Err(e) => {
+                metrics::GRAPHITE_SEND_ERR.mark();
+                debug!("Failed to send buffer to graphite: {e}");
+                Err(e)
+            }
         }
-        Ok(())
     }
 }
 
@@ -198,6 +237,72 @@ impl Drop for GraphiteUdpScope {
     }
 }
 
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ThreadLabel;
+    use crate::label::test::TEST_SEQUENCE;
+    use crate::output::socket::MAX_UDP_PAYLOAD;
+    use std::net::UdpSocket;
+
+    fn receive(metrics: &GraphiteUdpScope, receiver: &UdpSocket) -> Vec<String> {
+        metrics.flush().unwrap();
+        let mut buf = [0; MAX_UDP_PAYLOAD];
+        let len = receiver.recv(&mut buf).unwrap();
+        // strip timestamps
+        String::from_utf8_lossy(&buf[..len])
+            .lines()
+            .map(|line| line[..line.rfind(' ').unwrap()].to_string())
+            .collect()
+    }
+
+    #[test]
+    fn tagged_series() {
+        let _lock = TEST_SEQUENCE.lock().expect("Test Sequence");
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let graphite = GraphiteUdp::send_to(receiver.local_addr().unwrap())
+            .unwrap()
+            .buffered(crate::Buffering::Unlimited);
+
+        let metrics = graphite.tagged(true).named("app").metrics();
+        let counter = metrics.counter("counter a");
+        ThreadLabel::set("thread", "t1");
+        counter.write(3, labels!("path" => "/a b"));
+        ThreadLabel::unset("thread");
+        assert_eq!(
+            vec!["app.counter_a;path=/a_b;thread=t1 3"],
+            receive(&metrics, &receiver)
+        );
+
+        // labels are dropped by default
+        let metrics = graphite.named("app").metrics();
+        let counter = metrics.counter("counter_a");
+        counter.write(3, labels!("path" => "/a"));
+        assert_eq!(vec!["app.counter_a 3"], receive(&metrics, &receiver));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unix_socket() {
+        use std::os::unix::net::UnixDatagram;
+
+        let _lock = TEST_SEQUENCE.lock().expect("Test Sequence");
+        let path = std::env::temp_dir().join(format!("dipstick-graphite-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let receiver = UnixDatagram::bind(&path).unwrap();
+        let metrics = GraphiteUdp::send_to_unix(&path).unwrap().metrics();
+
+        let gauge = metrics.gauge("gauge_a");
+        gauge.value(5);
+
+        let mut buf = [0; MAX_UDP_PAYLOAD];
+        let len = receiver.recv(&mut buf).unwrap();
+        let line = String::from_utf8_lossy(&buf[..len]);
+        assert!(line.starts_with("gauge_a 5 "));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
+
 #[cfg(feature = "bench")]
 mod bench {
 
@@ -0,0 +1,520 @@
+//! Push batches of metric values to any HTTP endpoint, using a pluggable body encoder.
+
+use crate::attributes::{
+    Attributes, Buffered, MAX_PENDING_VALUES, MetricId, OnFlush, Prefixed, WithAttributes,
+};
+use crate::input::InputKind;
+use crate::input::{Input, InputMetric, InputScope};
+use crate::label::Labels;
+use crate::metrics;
+use crate::name::MetricName;
+use crate::output::format::escape_json;
+use crate::{CachedInput, QueuedInput};
+use crate::{Flush, MetricValue};
+
+use std::fmt::{self, Write};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[cfg(not(feature = "parking_lot"))]
+use std::sync::{RwLock, RwLockWriteGuard};
+
+#[cfg(feature = "parking_lot")]
+use parking_lot::{RwLock, RwLockWriteGuard};
+use std::io;
+
+/// A metric value waiting to be sent.
+#[derive(Debug, Clone)]
+pub struct HttpEntry {
+    /// Full name of the metric, parts joined with a dot.
+    pub name: String,
+    /// Kind of the metric.
+    pub kind: InputKind,
+    /// Value as written, timers are in microseconds.
+    pub value: MetricValue,
+    /// Labels of the value, sorted by key.
+    pub labels: Vec<(String, Arc<String>)>,
+    /// When the value was written.
+    pub timestamp: SystemTime,
+}
+
+/// Turns a batch of values into an HTTP request body.
+pub trait HttpEncoder: Send + Sync {
+    /// The `Content-Type` header of the encoded body.
+    fn content_type(&self) -> &str;
+
+    /// Encode the entries as a request body.
+    /// Entries failing to encode are dropped, as encoding them again would fail the same way.
+    fn encode(&self, entries: &[HttpEntry]) -> io::Result<Vec<u8>>;
+}
+
+/// Encode batches as a JSON array of objects.
+/// `[{"name":"app.timer_a","kind":"Timer","value":1500,"timestamp":1700000000000,"labels":{"k":"v"}}]`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JsonEncoder;
+
+impl HttpEncoder for JsonEncoder {
+    fn content_type(&self) -> &str {
+        "application/json"
+    }
+
+    fn encode(&self, entries: &[HttpEntry]) -> io::Result<Vec<u8>> {
+        let mut json = String::from("[");
+        for (i, entry) in entries.iter().enumerate() {
+            if i > 0 {
+                json.push(',');
+            }
+            let timestamp = entry
+                .timestamp
+                .duration_since(UNIX_EPOCH)
+                .map_err(io::Error::other)?
+                .as_millis();
+            let _ = write!(
+                json,
+                "{{\"name\":\"{}\",\"kind\":\"{:?}\",\"value\":{},\"timestamp\":{timestamp},\"labels\":{{",
+                escape_json(&entry.name),
+                entry.kind,
+                entry.value,
+            );
+            for (j, (key, value)) in entry.labels.iter().enumerate() {
+                if j > 0 {
+                    json.push(',');
+                }
+                let _ = write!(json, "\"{}\":\"{}\"", escape_json(key), escape_json(value));
+            }
+            json.push_str("}}");
+        }
+        json.push(']');
+        Ok(json.into_bytes())
+    }
+}
+
+/// Request method used to send batches.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub enum HttpMethod {
+    /// POST the batch.
+    #[default]
+    Post,
+    /// PUT the batch.
+    Put,
+}
+
+/// Http Input sends batches of values to an HTTP endpoint, encoded by an `HttpEncoder`.
+/// Values are encoded as JSON by default.
+#[derive(Clone)]
+pub struct Http {
+    attributes: Attributes,
+    url: String,
+    encoder: Arc<dyn HttpEncoder>,
+    method: HttpMethod,
+    headers: Vec<(String, String)>,
+    timeout: Option<Duration>,
+}
+
+impl fmt::Debug for Http {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Http({:?} {})", self.method, self.url)
+    }
+}
+
+impl Http {
+    /// Send metrics to the endpoint at the URL provided.
+    pub fn push_to(url: &str) -> io::Result<Http> {
+        debug!("Pushing to HTTP {url:?}");
+
+        Ok(Http {
+            attributes: Attributes::default(),
+            url: url.to_string(),
+            encoder: Arc::new(JsonEncoder),
+            method: HttpMethod::default(),
+            headers: Vec::new(),
+            timeout: None,
+        })
+    }
+
+    /// Encode batches with the specified encoder for scopes opened afterwards.
+    pub fn encoder(&self, encoder: impl HttpEncoder + 'static) -> Self {
+        let mut cloned = self.clone();
+        cloned.encoder = Arc::new(encoder);
+        cloned
+    }
+
+    /// Send batches with the specified method for scopes opened afterwards.
+    pub fn method(&self, method: HttpMethod) -> Self {
+        let mut cloned = self.clone();
+        cloned.method = method;
+        cloned
+    }
+
+    /// Add a header to the requests of scopes opened afterwards, e.g. for authentication.
+    pub fn header(&self, name: &str, value: &str) -> Self {
+        let mut cloned = self.clone();
+        cloned.headers.push((name.to_string(), value.to_string()));
+        cloned
+    }
+
+    /// Give up on requests taking longer than the specified timeout, rounded up to the second.
+    pub fn timeout(&self, timeout: Duration) -> Self {
+        let mut cloned = self.clone();
+        cloned.timeout = Some(timeout);
+        cloned
+    }
+}
+
+impl Input for Http {
+    type SCOPE = HttpScope;
+
+    fn metrics(&self) -> Self::SCOPE {
+        HttpScope {
+            attributes: self.attributes.clone(),
+            entries: Arc::new(RwLock::new(Vec::new())),
+            http: self.clone(),
+        }
+    }
+}
+
+impl WithAttributes for Http {
+    fn get_attributes(&self) -> &Attributes {
+        &self.attributes
+    }
+    fn mut_attributes(&mut self) -> &mut Attributes {
+        &mut self.attributes
+    }
+}
+
+impl Buffered for Http {}
+
+impl QueuedInput for Http {}
+impl CachedInput for Http {}
+
+/// Http Input
+#[derive(Debug, Clone)]
+pub struct HttpScope {
+    attributes: Attributes,
+    entries: Arc<RwLock<Vec<HttpEntry>>>,
+    http: Http,
+}
+
+impl InputScope for HttpScope {
+    /// Define a metric of the specified type.
+    fn new_metric(&self, name: MetricName, kind: InputKind) -> InputMetric {
+        let full_name = self.prefix_prepend(name.clone()).join(".");
+        let cloned = self.clone();
+        let metric_id = MetricId::forge("http", name);
+
+        InputMetric::new(metric_id, move |value, labels| {
+            cloned.print(&full_name, kind, value, labels);
+        })
+    }
+}
+
+impl Flush for HttpScope {
+    fn flush(&self) -> io::Result<()> {
+        self.notify_flush_listeners();
+        let entries = write_lock!(self.entries);
+        self.flush_inner(entries)
+    }
+}
+
+impl HttpScope {
+    fn print(&self, name: &str, kind: InputKind, value: MetricValue, labels: Labels) {
+        let mut labels: Vec<_> = labels.into_map().into_iter().collect();
+        labels.sort();
+
+        let mut entries = write_lock!(self.entries);
+        if entries.len() >= MAX_PENDING_VALUES {
+            metrics::HTTP_PENDING_DROPPED.mark();
+            debug!("HTTP pending values exceed {MAX_PENDING_VALUES}, dropping {name}");
+        } else {
+            entries.push(HttpEntry {
+                name: name.to_string(),
+                kind,
+                value,
+                labels,
+                timestamp: SystemTime::now(),
+            });
+        }
+
+        if self.is_buffer_full(entries.len()) {
+            let _ = self.flush_inner(entries);
+            entries = write_lock!(self.entries);
+        }
+
+        if !self.is_buffered()
+            && let Err(e) = self.flush_inner(entries)
+        {
+            debug!("Could not send to HTTP {e}")
+        }
+    }
+
+    fn flush_inner(&self, mut entries: RwLockWriteGuard<Vec<HttpEntry>>) -> io::Result<()> {
+        if entries.is_empty() {
+            return Ok(());
+        }
+
+        let http = &self.http;
+        let body = match http.encoder.encode(&entries) {
+            Ok(body) => body,
+            Err(e) => {
+                metrics::HTTP_DROPPED.mark();
+                debug!("Could not encode HTTP push, dropping it: {e}");
+                entries.clear();
+                return Err(e);
+            }
+        };
+        let body_len = body.len();
+        let method = match http.method {
+            HttpMethod::Post => minreq::Method::Post,
+            HttpMethod::Put => minreq::Method::Put,
+        };
+        let mut request = minreq::Request::new(method, http.url.as_str())
+            .with_header("Content-Type", http.encoder.content_type())
+            .with_headers(http.headers.iter().cloned())
+            .with_body(body);
+        if let Some(timeout) = http.timeout {
+            request = request.with_timeout(timeout.as_secs_f64().ceil() as u64);
+        }
+
+        match request.send() {
+            Ok(response) if (200..300).contains(&response.status_code) => {
+                metrics::HTTP_SENT_BYTES.count(body_len);
+                trace!("Sent {body_len} bytes to HTTP");
+                entries.clear();
+                Ok(())
+            }
+            Ok(response) if response.status_code == 429 || response.status_code >= 500 => {
+                metrics::HTTP_SEND_ERR.mark();
+                debug!("HTTP push deferred: {}", response.status_code);
+                Err(io::Error::other(format!(
+                    "HTTP push failed with status {} {}",
+                    response.status_code, response.reason_phrase
+                )))
+            }
+            Ok(response) => {
+                // the endpoint will never accept these values, retrying would only block the next ones
+                metrics::HTTP_DROPPED.mark();
+                debug!("HTTP push rejected: {}", response.status_code);
+                entries.clear();
+                Err(io::Error::other(format!(
+                    "HTTP push failed with status {} {}",
+                    response.status_code, response.reason_phrase
+                )))
+            }
+            Err(e) => {
+                metrics::HTTP_SEND_ERR.mark();
+                debug!("Failed to send buffer to HTTP: {e}");
+                Err(io::Error::other(e))
+            }
+        }
+    }
+}
+
+impl WithAttributes for HttpScope {
+    fn get_attributes(&self) -> &Attributes {
+        &self.attributes
+    }
+    fn mut_attributes(&mut self) -> &mut Attributes {
+        &mut self.attributes
+    }
+}
+
+impl Buffered for HttpScope {}
+
+/// Any remaining buffered data is flushed on Drop.
+impl Drop for HttpScope {
+    fn drop(&mut self) {
+        if let Err(err) = self.flush() {
+            warn!("Could not flush HTTP metrics upon Drop: {err}")
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::label::test::TEST_SEQUENCE;
+    use crate::output::stand_in::stand_in;
+    use std::net::TcpListener;
+
+    /// Print every entry as a `name value` line.
+    struct LineEncoder;
+
+    impl HttpEncoder for LineEncoder {
+        fn content_type(&self) -> &str {
+            "text/plain"
+        }
+
+        fn encode(&self, entries: &[HttpEntry]) -> io::Result<Vec<u8>> {
+            let mut body = String::new();
+            for entry in entries {
+                let _ = writeln!(body, "{} {}", entry.name, entry.value);
+            }
+            Ok(body.into_bytes())
+        }
+    }
+
+    #[test]
+    fn push_json() {
+        let _lock = TEST_SEQUENCE.lock().expect("Test Sequence");
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/ingest", listener.local_addr().unwrap());
+        let server = stand_in(listener, "202 Accepted");
+
+        let metrics = Http::push_to(&url)
+            .unwrap()
+            .header("Authorization", "Bearer t0k3n")
+            .buffered(crate::Buffering::Unlimited)
+            .named("app")
+            .metrics();
+        let counter = metrics.counter("counter_a");
+        let timer = metrics.timer("timer_a");
+        counter.write(3, labels!("path" => "/\"a\""));
+        timer.interval_us(1500);
+        metrics.flush().unwrap();
+
+        let (head, body) = server.join().unwrap();
+        assert!(head.starts_with("POST /ingest HTTP/1.1\r\n"));
+        assert!(head.contains("Content-Type: application/json"));
+        assert!(head.contains("Authorization: Bearer t0k3n"));
+
+        // replace the variable timestamps by zeros
+        let body = String::from_utf8(body).unwrap();
+        let mut zeroed = String::new();
+        for (i, part) in body.split("\"timestamp\":").enumerate() {
+            if i > 0 {
+                zeroed.push_str("\"timestamp\":0");
+            }
+            zeroed.push_str(part.trim_start_matches(|c: char| c.is_ascii_digit()));
+        }
+        assert_eq!(
+            "[{\"name\":\"app.counter_a\",\"kind\":\"Counter\",\"value\":3,\"timestamp\":0,\
+             \"labels\":{\"path\":\"/\\\"a\\\"\"}},\
+             {\"name\":\"app.timer_a\",\"kind\":\"Timer\",\"value\":1500,\"timestamp\":0,\"labels\":{}}]",
+            zeroed
+        );
+    }
+
+    #[test]
+    fn custom_encoder_and_method() {
+        let _lock = TEST_SEQUENCE.lock().expect("Test Sequence");
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/ingest", listener.local_addr().unwrap());
+        let server = stand_in(listener, "200 OK");
+
+        let metrics = Http::push_to(&url)
+            .unwrap()
+            .encoder(LineEncoder)
+            .method(HttpMethod::Put)
+            .timeout(Duration::from_millis(1500))
+            .metrics();
+        let gauge = metrics.gauge("gauge_a");
+        gauge.value(7);
+
+        let (head, body) = server.join().unwrap();
+        assert!(head.starts_with("PUT /ingest HTTP/1.1\r\n"));
+        assert!(head.contains("Content-Type: text/plain"));
+        assert_eq!(b"gauge_a 7\n", body.as_slice());
+    }
+
+    #[test]
+    fn push_full_buffer() {
+        let _lock = TEST_SEQUENCE.lock().expect("Test Sequence");
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/ingest", listener.local_addr().unwrap());
+        let server = stand_in(listener, "200 OK");
+
+        let metrics = Http::push_to(&url)
+            .unwrap()
+            .encoder(LineEncoder)
+            .buffered(crate::Buffering::BufferSize(2))
+            .metrics();
+        let gauge = metrics.gauge("gauge_a");
+        gauge.value(1);
+        gauge.value(2);
+        assert_eq!(2, read_lock!(metrics.entries).len());
+        gauge.value(3);
+
+        let (_, body) = server.join().unwrap();
+        assert_eq!(b"gauge_a 1\ngauge_a 2\ngauge_a 3\n", body.as_slice());
+    }
+
+    #[test]
+    fn push_rejected() {
+        let _lock = TEST_SEQUENCE.lock().expect("Test Sequence");
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/ingest", listener.local_addr().unwrap());
+        let server = stand_in(listener, "400 Bad Request");
+
+        let metrics = Http::push_to(&url)
+            .unwrap()
+            .buffered(crate::Buffering::Unlimited)
+            .metrics();
+        let marker = metrics.marker("m");
+        marker.mark();
+        assert!(metrics.flush().is_err());
+        server.join().unwrap();
+
+        // the values would never be accepted, they are dropped
+        assert!(read_lock!(metrics.entries).is_empty());
+    }
+
+    #[test]
+    fn push_unencodable() {
+        struct FailingEncoder;
+
+        impl HttpEncoder for FailingEncoder {
+            fn content_type(&self) -> &str {
+                "text/plain"
+            }
+
+            fn encode(&self, _entries: &[HttpEntry]) -> io::Result<Vec<u8>> {
+                Err(io::Error::other("unencodable"))
+            }
+        }
+
+        let metrics = Http::push_to("http://127.0.0.1:1/ingest")
+            .unwrap()
+            .encoder(FailingEncoder)
+            .buffered(crate::Buffering::Unlimited)
+            .metrics();
+        let marker = metrics.marker("m");
+        marker.mark();
+        assert!(metrics.flush().is_err());
+        assert!(read_lock!(metrics.entries).is_empty());
+    }
+
+    #[test]
+    fn push_unavailable() {
+        let _lock = TEST_SEQUENCE.lock().expect("Test Sequence");
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/ingest", listener.local_addr().unwrap());
+        let server = stand_in(listener, "503 Service Unavailable");
+
+        let metrics = Http::push_to(&url)
+            .unwrap()
+            .buffered(crate::Buffering::Unlimited)
+            .metrics();
+        let marker = metrics.marker("m");
+        marker.mark();
+        assert!(metrics.flush().is_err());
+        server.join().unwrap();
+
+        // values are kept for the next push
+        assert_eq!(1, read_lock!(metrics.entries).len());
+        write_lock!(metrics.entries).clear();
+    }
+
+    #[test]
+    fn pending_capped() {
+        let metrics = Http::push_to("http://127.0.0.1:1/ingest")
+            .unwrap()
+            .buffered(crate::Buffering::Unlimited)
+            .metrics();
+        let marker = metrics.marker("m");
+        for _ in 0..=MAX_PENDING_VALUES {
+            marker.mark();
+        }
+        assert_eq!(MAX_PENDING_VALUES, read_lock!(metrics.entries).len());
+        write_lock!(metrics.entries).clear();
+    }
+}
@@ -0,0 +1,474 @@
+//! Send metrics to an InfluxDB server using the line protocol.
+
+use crate::attributes::{
+    Attributes, Buffered, MAX_PENDING_BYTES, MetricId, OnFlush, Prefixed, WithAttributes,
+};
+use crate::input::InputKind;
+use crate::input::{Input, InputMetric, InputScope};
+use crate::label::Labels;
+use crate::metrics;
+use crate::name::MetricName;
+use crate::output::datagram::{DatagramBatch, Push};
+use crate::output::socket::{DatagramSocket, RetrySocket};
+use crate::{CachedInput, QueuedInput};
+use crate::{Flush, MetricValue};
+
+use std::fmt::Debug;
+use std::io::Write;
+use std::net::ToSocketAddrs;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[cfg(not(feature = "parking_lot"))]
+use std::sync::{RwLock, RwLockWriteGuard};
+
+#[cfg(feature = "parking_lot")]
+use parking_lot::{RwLock, RwLockWriteGuard};
+use std::io;
+
+/// Where the lines are sent.
+#[derive(Clone, Debug)]
+enum InfluxTarget {
+    Udp(Arc<DatagramSocket>),
+    Tcp(Arc<RwLock<RetrySocket>>),
+    Http(String),
+}
+
+/// Lines waiting to be sent.
+#[derive(Debug)]
+enum InfluxBuffer {
+    /// Lines packed into datagrams.
+    Datagram(DatagramBatch),
+    /// Lines written together over TCP.
+    Tcp(Arc<RwLock<RetrySocket>>, String),
+    /// Lines posted together to the URL.
+    Http(String, String),
+}
+
+/// Influx Input sends metrics to an InfluxDB server (or a Telegraf agent) using the line protocol.
+/// The transport is shared between scopes opened from the Input.
+#[derive(Clone, Debug)]
+pub struct Influx {
+    attributes: Attributes,
+    target: InfluxTarget,
+    max_payload: usize,
+}
+
+impl Influx {
+    /// Send metrics over UDP to an InfluxDB UDP listener at the address and port provided.
+    pub fn send_to_udp<ADDR: ToSocketAddrs>(address: ADDR) -> io::Result<Influx> {
+        let socket = DatagramSocket::udp(address)?;
+
+        Ok(Influx {
+            attributes: Attributes::default(),
+            max_payload: socket.max_payload(),
+            target: InfluxTarget::Udp(Arc::new(socket)),
+        })
+    }
+
+    /// Send metrics over TCP to a line protocol listener (e.g. Telegraf's `socket_listener`)
+    /// at the address and port provided. The connection is reestablished if it drops.
+    pub fn send_to_tcp<A: ToSocketAddrs + Debug + Clone>(address: A) -> io::Result<Influx> {
+        debug!("Connecting to influx {address:?}");
+        let socket = Arc::new(RwLock::new(RetrySocket::new(address)?));
+
+        Ok(Influx {
+            attributes: Attributes::default(),
+            target: InfluxTarget::Tcp(socket),
+            max_payload: 0,
+        })
+    }
+
+    /// POST metrics to the InfluxDB HTTP write endpoint at the URL provided,
+    /// including any query parameters the server requires,
+    /// e.g. `http://localhost:8086/write?db=metrics`
+    pub fn write_to(url: &str) -> io::Result<Influx> {
+        debug!("Pushing to influx {url:?}");
+
+        Ok(Influx {
+            attributes: Attributes::default(),
+            target: InfluxTarget::Http(url.to_string()),
+            max_payload: 0,
+        })
+    }
+
+    /// Send datagrams of up to the specified size from scopes opened afterwards.
+    /// Defaults to 576 bytes for UDP, which never gets fragmented.
+    /// Has no effect over TCP or HTTP.
+    pub fn max_payload(&self, bytes: usize) -> Self {
+        let mut cloned = self.clone();
+        cloned.max_payload = bytes;
+        cloned
+    }
+}
+
+impl Input for Influx {
+    type SCOPE = InfluxScope;
+
+    fn metrics(&self) -> Self::SCOPE {
+        let buffer = match &self.target {
+            InfluxTarget::Udp(socket) => {
+                // lines are newline terminated, no separator needed
+                InfluxBuffer::Datagram(DatagramBatch::new(socket.clone(), self.max_payload, ""))
+            }
+            InfluxTarget::Tcp(socket) => InfluxBuffer::Tcp(socket.clone(), String::new()),
+            InfluxTarget::Http(url) => InfluxBuffer::Http(url.clone(), String::new()),
+        };
+        InfluxScope {
+            attributes: self.attributes.clone(),
+            buffer: Arc::new(RwLock::new(buffer)),
+        }
+    }
+}
+
+impl WithAttributes for Influx {
+    fn get_attributes(&self) -> &Attributes {
+        &self.attributes
+    }
+    fn mut_attributes(&mut self) -> &mut Attributes {
+        &mut self.attributes
+    }
+}
+
+impl Buffered for Influx {}
+
+impl QueuedInput for Influx {}
+impl CachedInput for Influx {}
+
+/// Influx Input
+#[derive(Debug, Clone)]
+pub struct InfluxScope {
+    attributes: Attributes,
+    buffer: Arc<RwLock<InfluxBuffer>>,
+}
+
+impl InputScope for InfluxScope {
+    /// Define a metric of the specified type.
+    fn new_metric(&self, name: MetricName, kind: InputKind) -> InputMetric {
+        let measurement = escape(&self.prefix_prepend(name.clone()).join("."), ", ");
+
+        let scale = match kind {
+            // timers are in µs, but we give influx milliseconds
+            InputKind::Timer => 1000,
+            _ => 1,
+        };
+
+        let cloned = self.clone();
+        let metric = InfluxMetric { measurement, scale };
+        let metric_id = MetricId::forge("influx", name);
+
+        InputMetric::new(metric_id, move |value, labels| {
+            cloned.print(&metric, value, labels);
+        })
+    }
+}
+
+impl Flush for InfluxScope {
+    fn flush(&self) -> io::Result<()> {
+        self.notify_flush_listeners();
+        let buf = write_lock!(self.buffer);
+        self.flush_inner(buf)
+    }
+}
+
+impl InfluxScope {
+    fn print(&self, metric: &InfluxMetric, value: MetricValue, labels: Labels) {
+        let timestamp = match SystemTime::now().duration_since(UNIX_EPOCH) {
+            Ok(timestamp) => timestamp.as_nanos(),
+            Err(e) => {
+                warn!("Could not compute epoch timestamp. {e}");
+                return;
+            }
+        };
+        // values are always written as integer fields to prevent field type conflicts
+        let line = format!(
+            "{}{} value={}i {}\n",
+            metric.measurement,
+            format_tags(labels),
+            value / metric.scale,
+            timestamp
+        );
+
+        let mut buffer = write_lock!(self.buffer);
+        let full = match &mut *buffer {
+            InfluxBuffer::Datagram(batch) => match batch.push(&[&line]) {
+                Push::Oversize => {
+                    metrics::INFLUX_OVERSIZE.mark();
+                    debug!("Influx line too big to fit in a datagram, dropping {line}");
+                    return;
+                }
+                push => push == Push::Full,
+            },
+            InfluxBuffer::Tcp(_, lines) | InfluxBuffer::Http(_, lines) => {
+                if lines.len() + line.len() > MAX_PENDING_BYTES {
+                    metrics::INFLUX_PENDING_DROPPED.mark();
+                    debug!(
+                        "Influx pending lines exceed {MAX_PENDING_BYTES} bytes, dropping {line}"
+                    );
+                    // unbuffered scopes still try to send what is pending
+                    false
+                } else {
+                    lines.push_str(&line);
+                    self.is_buffer_full(lines.len())
+                }
+            }
+        };
+
+        if (!self.is_buffered() || full)
+            && let Err(e) = self.flush_inner(buffer)
+        {
+            debug!("Could not send to influx {e}")
+        }
+    }
+
+    fn flush_inner(&self, mut buffer: RwLockWriteGuard<InfluxBuffer>) -> io::Result<()> {
+        if buffer.is_empty() {
+            return Ok(());
+        }
+        match buffer.send() {
+            Ok(size) => {
+                metrics::INFLUX_SENT_BYTES.count(size);
+                trace!("Sent {size} bytes to influx");
+                Ok(())
+            }
+            Err(e) => {
+                metrics::INFLUX_SEND_ERR.mark();
+                debug!("Failed to send buffer to influx: {e}");
+                Err(e)
+            }
+        }
+    }
+}
+
+impl WithAttributes for InfluxScope {
+    fn get_attributes(&self) -> &Attributes {
+        &self.attributes
+    }
+    fn mut_attributes(&mut self) -> &mut Attributes {
+        &mut self.attributes
+    }
+}
+
+impl Buffered for InfluxScope {}
+
+impl InfluxBuffer {
+    fn is_empty(&self) -> bool {
+        match self {
+            InfluxBuffer::Datagram(batch) => batch.is_empty(),
+            InfluxBuffer::Tcp(_, lines) | InfluxBuffer::Http(_, lines) => lines.is_empty(),
+        }
+    }
+
+    /// Send the lines, returning the number of bytes sent.
+    /// Lines that could not be sent over TCP, or that the HTTP server may accept later,
+    /// are kept for the next attempt. Lines refused by the HTTP server are dropped.
+    fn send(&mut self) -> io::Result<usize> {
+        match self {
+            InfluxBuffer::Datagram(batch) => batch.send(),
+            InfluxBuffer::Tcp(socket, lines) => {
+                write_lock!(socket).write_all(lines.as_bytes())?;
+                let size = lines.len();
+                lines.clear();
+                Ok(size)
+            }
+            InfluxBuffer::Http(url, lines) => {
+                let response = minreq::post(url.as_str())
+                    .with_header("Content-Type", "text/plain; charset=utf-8")
+                    .with_body(lines.as_str())
+                    .send()
+                    .map_err(io::Error::other)?;
+                if !(200..300).contains(&response.status_code) {
+                    if response.status_code != 429 && response.status_code < 500 {
+                        // the server will never accept these lines, retrying would only block the next ones
+                        metrics::INFLUX_DROPPED.mark();
+                        lines.clear();
+                    }
+                    return Err(io::Error::other(format!(
+                        "InfluxDB write failed with status {} {}",
+                        response.status_code, response.reason_phrase
+                    )));
+                }
+                let size = lines.len();
+                lines.clear();
+                Ok(size)
+            }
+        }
+    }
+}
+
+/// Key of an influx metric.
+#[derive(Debug, Clone)]
+pub struct InfluxMetric {
+    measurement: String,
+    scale: isize,
+}
+
+/// Print labels sorted by key as `,key=value,...` tags, or nothing if there are no labels.
+fn format_tags(labels: Labels) -> String {
+    let mut labels: Vec<_> = labels.into_map().into_iter().collect();
+    // sorted tags are faster for the server to ingest
+    labels.sort();
+
+    let mut tags = String::new();
+    for (key, value) in labels {
+        // empty tag values are not allowed
+        if value.is_empty() {
+            continue;
+        }
+        tags.push(',');
+        tags.push_str(&escape(&key, ",= "));
+        tags.push('=');
+        tags.push_str(&escape(&value, ",= "));
+    }
+    tags
+}
+
+/// Escape the special characters of a line protocol element with a backslash.
+fn escape(element: &str, special: &str) -> String {
+    let mut escaped = String::with_capacity(element.len());
+    for c in element.chars() {
+        match c {
+            '\n' => escaped.push(' '),
+            c if special.contains(c) => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Any remaining buffered data is flushed on Drop.
+impl Drop for InfluxScope {
+    fn drop(&mut self) {
+        if let Err(err) = self.flush() {
+            warn!("Could not flush influx metrics upon Drop: {err}")
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::label::test::TEST_SEQUENCE;
+    use crate::output::socket::MAX_UDP_PAYLOAD;
+    use crate::output::stand_in::stand_in;
+    use std::net::{TcpListener, UdpSocket};
+
+    /// Strip the variable timestamps from received lines.
+    fn strip_timestamps(lines: &str) -> String {
+        lines
+            .lines()
+            .map(|line| &line[..line.rfind(' ').unwrap()])
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    #[test]
+    fn udp_line_protocol() {
+        let _lock = TEST_SEQUENCE.lock().expect("Test Sequence");
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let metrics = Influx::send_to_udp(receiver.local_addr().unwrap())
+            .unwrap()
+            .buffered(crate::Buffering::Unlimited)
+            .named("my app")
+            .metrics();
+
+        // dropped metrics flush their scope, keep them around
+        let counter = metrics.counter("counter,a");
+        let timer = metrics.timer("timer_a");
+        counter.write(3, labels!("host" => "a b", "k=1" => "x,y"));
+        timer.write(3000, labels!("empty" => ""));
+        metrics.flush().unwrap();
+
+        let mut buf = [0; MAX_UDP_PAYLOAD];
+        let len = receiver.recv(&mut buf).unwrap();
+        assert_eq!(
+            "my\\ app.counter\\,a,host=a\\ b,k\\=1=x\\,y value=3i\n\
+             my\\ app.timer_a value=3i",
+            strip_timestamps(&String::from_utf8_lossy(&buf[..len]))
+        );
+    }
+
+    #[test]
+    fn udp_oversize() {
+        let _lock = TEST_SEQUENCE.lock().expect("Test Sequence");
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let metrics = Influx::send_to_udp(receiver.local_addr().unwrap())
+            .unwrap()
+            .max_payload(64)
+            .buffered(crate::Buffering::Unlimited)
+            .metrics();
+
+        let gauge = metrics.gauge("gauge_a");
+        gauge.write(1, labels!("long" => "x".repeat(64)));
+        gauge.write(2, labels![]);
+        metrics.flush().unwrap();
+
+        let mut buf = [0; MAX_UDP_PAYLOAD];
+        let len = receiver.recv(&mut buf).unwrap();
+        assert_eq!(
+            "gauge_a value=2i",
+            strip_timestamps(&String::from_utf8_lossy(&buf[..len]))
+        );
+    }
+
+    #[test]
+    fn http_write() {
+        let _lock = TEST_SEQUENCE.lock().expect("Test Sequence");
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/write?db=test", listener.local_addr().unwrap());
+        let server = stand_in(listener, "204 No Content");
+
+        let metrics = Influx::write_to(&url).unwrap().metrics();
+        let gauge = metrics.gauge("gauge_a");
+        gauge.write(7, labels!("a" => "1"));
+
+        let (head, body) = server.join().unwrap();
+        assert!(head.starts_with("POST /write?db=test HTTP/1.1\r\n"));
+        let body = String::from_utf8(body).unwrap();
+        assert_eq!("gauge_a,a=1 value=7i", strip_timestamps(&body));
+    }
+
+    #[test]
+    fn http_rejected() {
+        let _lock = TEST_SEQUENCE.lock().expect("Test Sequence");
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/write?db=test", listener.local_addr().unwrap());
+        let server = stand_in(listener, "400 Bad Request");
+
+        let metrics = Influx::write_to(&url)
+            .unwrap()
+            .buffered(crate::Buffering::Unlimited)
+            .metrics();
+        let gauge = metrics.gauge("gauge_a");
+        gauge.write(7, labels![]);
+        assert!(metrics.flush().is_err());
+        server.join().unwrap();
+
+        // lines refused by the server are not sent again
+        let buffer = read_lock!(metrics.buffer);
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn http_deferred() {
+        let _lock = TEST_SEQUENCE.lock().expect("Test Sequence");
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/write?db=test", listener.local_addr().unwrap());
+        let server = stand_in(listener, "503 Service Unavailable");
+
+        let metrics = Influx::write_to(&url)
+            .unwrap()
+            .buffered(crate::Buffering::Unlimited)
+            .metrics();
+        let gauge = metrics.gauge("gauge_a");
+        gauge.write(7, labels![]);
+        assert!(metrics.flush().is_err());
+        server.join().unwrap();
+
+        let buffer = read_lock!(metrics.buffer);
+        assert!(!buffer.is_empty());
+    }
+}
@@ -2,7 +2,7 @@ use crate::Flush;
 use crate::attributes::{Attributes, Buffered, MetricId, OnFlush, Prefixed, WithAttributes};
 use crate::input::{Input, InputKind, InputMetric, InputScope};
 use crate::name::MetricName;
-use crate::output::format::{Formatting, LineFormat, SimpleFormat};
+use crate::output::format::{BatchValue, Formatting, LineFormat, SimpleFormat};
 use crate::{CachedInput, QueuedInput};
 
 use std::sync::Arc;
@@ -32,6 +32,7 @@ impl Input for Log {
         LogScope {
             attributes: self.attributes.clone(),
             entries: Arc::new(RwLock::new(Vec::new())),
+            values: Arc::new(RwLock::new(Vec::new())),
             log: self.clone(),
         }
     }
@@ -61,6 +62,7 @@ impl Formatting for Log {
 pub struct LogScope {
     attributes: Attributes,
     entries: Arc<RwLock<Vec<Vec<u8>>>>,
+    values: Arc<RwLock<Vec<BatchValue>>>,
     log: Log,
 }
 
@@ -113,11 +115,25 @@ impl InputScope for LogScope {
         let template = self.log.format.template(&name, kind);
         let entries = self.entries.clone();
 
-        if self.is_buffered() {
+        if self.is_buffered() && self.log.format.batch().is_some() {
+            // values are printed together on flush
+            let values = self.values.clone();
+            InputMetric::new(
+                MetricId::forge("log", name.clone()),
+                move |value, labels| {
+                    write_lock!(values).push(BatchValue {
+                        name: name.clone(),
+                        kind,
+                        value,
+                        labels,
+                    })
+                },
+            )
+        } else if self.is_buffered() {
             // buffered
             InputMetric::new(MetricId::forge("log", name), move |value, labels| {
                 let mut buffer = Vec::with_capacity(32);
-                match template.print(&mut buffer, value, |key| labels.lookup(key)) {
+                match template.print_labels(&mut buffer, value, &labels) {
                     Ok(()) => {
                         let mut entries = write_lock!(entries);
                         entries.push(buffer)
@@ -131,7 +147,7 @@ impl InputScope for LogScope {
             let target = self.log.target.clone();
             InputMetric::new(MetricId::forge("log", name), move |value, labels| {
                 let mut buffer = Vec::with_capacity(32);
-                match template.print(&mut buffer, value, |key| labels.lookup(key)) {
+                match template.print_labels(&mut buffer, value, &labels) {
                     Ok(()) => {
                         let str = String::from_utf8_lossy(&buffer);
                         if let Some(target) = &target {
@@ -150,6 +166,21 @@ impl InputScope for LogScope {
 impl Flush for LogScope {
     fn flush(&self) -> io::Result<()> {
         self.notify_flush_listeners();
+        let mut values = write_lock!(self.values);
+        if let Some(batch) = self.log.format.batch()
+            && !values.is_empty()
+        {
+            let mut buf: Vec<u8> = Vec::new();
+            batch.print_batch(&mut buf, &values)?;
+            values.clear();
+            let str = String::from_utf8_lossy(&buf);
+            let str = str.trim_end();
+            if let Some(target) = &self.log.target {
+                log!(target: target, self.log.level, "{str}")
+            } else {
+                log!(self.log.level, "{str}")
+            }
+        }
         let mut entries = write_lock!(self.entries);
         if !entries.is_empty() {
             let mut buf: Vec<u8> = Vec::with_capacity(32 * entries.len());
@@ -6,15 +6,39 @@ pub mod map;
 
 pub mod stream;
 
+pub mod rolling;
+
 pub mod log;
 
 pub mod socket;
 
+pub mod datagram;
+
 pub mod graphite;
 
 pub mod graphite_udp;
 
+pub mod influx;
+
+pub mod opentsdb;
+
 pub mod statsd;
 
+pub mod syslog;
+
 //#[cfg(feature="prometheus")]
 pub mod prometheus;
+
+#[cfg(feature = "remote_write")]
+pub mod remote_write;
+
+pub mod openmetrics;
+
+pub mod otlp;
+
+pub mod http;
+
+pub mod proto;
+
+#[cfg(test)]
+pub mod stand_in;
@@ -0,0 +1,629 @@
+//! Send metrics using the OpenMetrics text format.
+
+use crate::attributes::{
+    Attributes, Buffered, MAX_PENDING_VALUES, MetricId, OnFlush, Prefixed, WithAttributes,
+};
+use crate::input::InputKind;
+use crate::input::{Input, InputMetric, InputScope};
+use crate::label::Labels;
+use crate::metrics;
+use crate::name::MetricName;
+use crate::output::prometheus::{escape_help, format_label_pairs, sanitize_name};
+use crate::{CachedInput, QueuedInput};
+use crate::{Flush, MetricValue};
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering::*;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[cfg(feature = "prometheus_serve")]
+use std::net::ToSocketAddrs;
+#[cfg(feature = "prometheus_serve")]
+use std::thread;
+
+#[cfg(not(feature = "parking_lot"))]
+use std::sync::{RwLock, RwLockWriteGuard};
+
+#[cfg(feature = "parking_lot")]
+use parking_lot::{RwLock, RwLockWriteGuard};
+use std::io;
+
+/// Content type of the OpenMetrics text format.
+const CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+/// Exemplars whose label names and values add up to more characters than this are dropped,
+/// as mandated by the spec.
+const MAX_EXEMPLAR_LABELS_LEN: usize = 128;
+
+/// Samples of every metric family, keyed by family name.
+type Families = BTreeMap<String, Family>;
+
+/// Where the OpenMetrics metrics go.
+#[derive(Clone, Debug)]
+enum OpenMetricsTarget {
+    /// POST buffered metrics to a URL.
+    Push(String),
+    /// Keep values for a scraping endpoint to serve.
+    #[cfg(feature = "prometheus_serve")]
+    Serve(Arc<RwLock<Families>>),
+}
+
+/// OpenMetrics Input either pushes metrics to an URL
+/// or serves them from an embedded HTTP endpoint.
+/// The target is shared between scopes opened from the Input.
+#[derive(Clone, Debug)]
+pub struct OpenMetrics {
+    attributes: Attributes,
+    target: OpenMetricsTarget,
+    exemplar_keys: Arc<Vec<String>>,
+}
+
+impl Input for OpenMetrics {
+    type SCOPE = OpenMetricsScope;
+
+    fn metrics(&self) -> Self::SCOPE {
+        let families = match &self.target {
+            OpenMetricsTarget::Push(_) => Arc::new(RwLock::new(Families::new())),
+            #[cfg(feature = "prometheus_serve")]
+            OpenMetricsTarget::Serve(registry) => registry.clone(),
+        };
+        OpenMetricsScope {
+            attributes: self.attributes.clone(),
+            families,
+            unsent: Arc::new(AtomicBool::new(false)),
+            target: self.target.clone(),
+            exemplar_keys: self.exemplar_keys.clone(),
+        }
+    }
+}
+
+impl OpenMetrics {
+    /// Send metrics to an OpenMetrics receiver (e.g. a Prometheus push gateway) at the URL provided.
+    /// Every push sends all the series of the scope, counters and histograms with their running totals.
+    pub fn push_to(url: &str) -> io::Result<OpenMetrics> {
+        debug!("Pushing to OpenMetrics {url:?}");
+
+        Ok(OpenMetrics {
+            attributes: Attributes::default(),
+            target: OpenMetricsTarget::Push(url.to_string()),
+            exemplar_keys: Arc::new(Vec::new()),
+        })
+    }
+
+    /// Serve metrics to OpenMetrics scrapers from an HTTP endpoint at the address provided.
+    /// Every `GET /metrics` returns the total of values written to counters and histograms since start,
+    /// and the latest value written to gauges.
+    /// The HTTP listener runs on its own thread for the remainder of the process.
+    #[cfg(feature = "prometheus_serve")]
+    pub fn serve_on<A: ToSocketAddrs>(address: A) -> io::Result<OpenMetrics> {
+        let server = tiny_http::Server::http(address).map_err(io::Error::other)?;
+        Self::serve_with(server)
+    }
+
+    #[cfg(feature = "prometheus_serve")]
+    fn serve_with(server: tiny_http::Server) -> io::Result<OpenMetrics> {
+        debug!("Serving OpenMetrics on {:?}", server.server_addr());
+        let registry = Arc::new(RwLock::new(Families::new()));
+        let served = registry.clone();
+
+        thread::Builder::new()
+            .name("dipstick-openmetrics-serve".to_string())
+            .spawn(move || {
+                crate::output::prometheus::serve_loop(server, CONTENT_TYPE, || {
+                    render(&read_lock!(served))
+                })
+            })?;
+
+        Ok(OpenMetrics {
+            attributes: Attributes::default(),
+            target: OpenMetricsTarget::Serve(registry),
+            exemplar_keys: Arc::new(Vec::new()),
+        })
+    }
+
+    /// Use the values of these label keys as exemplar (e.g. a trace id) instead of series labels.
+    /// Exemplars are only printed for counters and timers.
+    pub fn exemplar_labels(&self, keys: &[&str]) -> Self {
+        let mut cloned = self.clone();
+        cloned.exemplar_keys = Arc::new(keys.iter().map(|key| key.to_string()).collect());
+        cloned
+    }
+}
+
+impl WithAttributes for OpenMetrics {
+    fn get_attributes(&self) -> &Attributes {
+        &self.attributes
+    }
+    fn mut_attributes(&mut self) -> &mut Attributes {
+        &mut self.attributes
+    }
+}
+
+impl Buffered for OpenMetrics {}
+
+/// OpenMetrics Input
+#[derive(Debug, Clone)]
+pub struct OpenMetricsScope {
+    attributes: Attributes,
+    families: Arc<RwLock<Families>>,
+    /// Values were written since the last successful push
+    unsent: Arc<AtomicBool>,
+    target: OpenMetricsTarget,
+    exemplar_keys: Arc<Vec<String>>,
+}
+
+impl InputScope for OpenMetricsScope {
+    /// Define a metric of the specified type.
+    fn new_metric(&self, name: MetricName, kind: InputKind) -> InputMetric {
+        let full_name = self.prefix_prepend(name.clone());
+        let mut family = sanitize_name(&full_name.join("_"), true);
+        let help = escape_help(&full_name.join("."));
+
+        let (metric_type, unit, scale) = match kind {
+            InputKind::Marker | InputKind::Counter => {
+                // the _total suffix is added to the sample, not the family
+                if let Some(stripped) = family.strip_suffix("_total") {
+                    family = stripped.to_string();
+                }
+                (MetricType::Counter, None, 1.0)
+            }
+            InputKind::Gauge | InputKind::Level | InputKind::Set => (MetricType::Gauge, None, 1.0),
+            InputKind::Histogram | InputKind::Distribution => (MetricType::Histogram, None, 1.0),
+            // timers are in µs, OpenMetrics wants seconds
+            InputKind::Timer => (MetricType::Histogram, Some("seconds"), 1_000_000.0),
+        };
+        if let Some(unit) = unit {
+            // family name must end with the unit
+            if !family.ends_with(unit) {
+                family.push('_');
+                family.push_str(unit);
+            }
+        }
+
+        let cloned = self.clone();
+        let metric = OpenMetricsMetric {
+            family,
+            help,
+            metric_type,
+            unit,
+            scale,
+        };
+
+        let metric_id = MetricId::forge("openmetrics", name);
+
+        InputMetric::new(metric_id, move |value, labels| {
+            cloned.print(&metric, value, labels);
+        })
+    }
+}
+
+impl Flush for OpenMetricsScope {
+    fn flush(&self) -> io::Result<()> {
+        self.notify_flush_listeners();
+        let families = write_lock!(self.families);
+        self.flush_inner(families)
+    }
+}
+
+impl OpenMetricsScope {
+    fn print(&self, metric: &OpenMetricsMetric, value: MetricValue, labels: Labels) {
+        let scaled_value = value as f64 / metric.scale;
+        let now = match SystemTime::now().duration_since(UNIX_EPOCH) {
+            Ok(timestamp) => timestamp.as_secs_f64(),
+            Err(e) => {
+                warn!("Could not compute epoch timestamp. {e}");
+                return;
+            }
+        };
+
+        let (exemplar_pairs, series_pairs): (Vec<_>, Vec<_>) = labels
+            .into_map()
+            .into_iter()
+            .partition(|(key, _)| self.exemplar_keys.contains(key));
+        let series = format_label_pairs(series_pairs);
+        let exemplar = if exemplar_pairs.is_empty() {
+            None
+        } else {
+            let len: usize = exemplar_pairs
+                .iter()
+                .map(|(key, value)| key.chars().count() + value.chars().count())
+                .sum();
+            let labels = format_label_pairs(exemplar_pairs);
+            if len > MAX_EXEMPLAR_LABELS_LEN {
+                debug!("Dropping exemplar {labels}, too long");
+                None
+            } else {
+                Some(Exemplar {
+                    labels,
+                    value: scaled_value,
+                    timestamp: now,
+                })
+            }
+        };
+
+        let mut families = write_lock!(self.families);
+        let series_count: usize = families.values().map(|f| f.series.len()).sum();
+        let family = families
+            .entry(metric.family.clone())
+            .or_insert_with(|| Family {
+                help: metric.help.clone(),
+                metric_type: metric.metric_type,
+                unit: metric.unit,
+                series: BTreeMap::new(),
+            });
+        if series_count >= MAX_PENDING_VALUES && !family.series.contains_key(&series) {
+            metrics::OPENMETRICS_PENDING_DROPPED.mark();
+            debug!(
+                "Too many OpenMetrics series, dropping {}{series}",
+                metric.family
+            );
+            return;
+        }
+        family.record(series, scaled_value, exemplar, now);
+        self.unsent.store(true, Release);
+
+        #[cfg(feature = "prometheus_serve")]
+        if let OpenMetricsTarget::Serve(_) = &self.target {
+            return;
+        }
+
+        if !self.is_buffered()
+            && let Err(e) = self.flush_inner(families)
+        {
+            debug!("Could not send to OpenMetrics {e}")
+        }
+    }
+
+    fn flush_inner(&self, families: RwLockWriteGuard<Families>) -> io::Result<()> {
+        if !self.unsent.load(Acquire) {
+            return Ok(());
+        }
+
+        #[allow(clippy::infallible_destructuring_match)]
+        let push_url = match &self.target {
+            OpenMetricsTarget::Push(url) => url,
+            // scraped values are never buffered
+            #[cfg(feature = "prometheus_serve")]
+            OpenMetricsTarget::Serve(_) => return Ok(()),
+        };
+
+        let body = render(&families);
+        match minreq::post(push_url.as_str())
+            .with_header("Content-Type", CONTENT_TYPE)
+            .with_body(body.as_str())
+            .send()
+        {
+            Ok(http_result) if (200..300).contains(&http_result.status_code) => {
+                metrics::OPENMETRICS_SENT_BYTES.count(body.len());
+                trace!(
+                    "Sent {} bytes to OpenMetrics (resp status code: {})",
+                    body.len(),
+                    http_result.status_code
+                );
+                // series are kept, their totals go on with the next push
+                self.unsent.store(false, Release);
+                Ok(())
+            }
+            Ok(http_result) => {
+                metrics::OPENMETRICS_SEND_ERR.mark();
+                debug!(
+                    "OpenMetrics push rejected: {} {}",
+                    http_result.status_code, http_result.reason_phrase
+                );
+                Err(io::Error::other(format!(
+                    "OpenMetrics push rejected with status {}",
+                    http_result.status_code
+                )))
+            }
+            Err(e) => {
+                metrics::OPENMETRICS_SEND_ERR.mark();
+                debug!("Failed to send buffer to OpenMetrics: {e}");
+                Err(io::Error::other(e))
+            }
+        }
+    }
+}
+
+impl WithAttributes for OpenMetricsScope {
+    fn get_attributes(&self) -> &Attributes {
+        &self.attributes
+    }
+    fn mut_attributes(&mut self) -> &mut Attributes {
+        &mut self.attributes
+    }
+}
+
+impl Buffered for OpenMetricsScope {}
+
+impl QueuedInput for OpenMetrics {}
+impl CachedInput for OpenMetrics {}
+
+/// Key of an OpenMetrics metric.
+#[derive(Debug, Clone)]
+pub struct OpenMetricsMetric {
+    family: String,
+    help: String,
+    metric_type: MetricType,
+    unit: Option<&'static str>,
+    scale: f64,
+}
+
+/// OpenMetrics metric types used to expose dipstick metrics.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MetricType {
+    Counter,
+    Gauge,
+    Histogram,
+}
+
+impl MetricType {
+    fn as_str(self) -> &'static str {
+        match self {
+            MetricType::Counter => "counter",
+            MetricType::Gauge => "gauge",
+            MetricType::Histogram => "histogram",
+        }
+    }
+}
+
+/// A sample value linked to an external reference, such as a trace.
+#[derive(Debug, Clone)]
+struct Exemplar {
+    labels: String,
+    value: f64,
+    timestamp: f64,
+}
+
+/// A single series value.
+/// Counters and histograms accumulate every observed value, gauges keep the latest one.
+#[derive(Debug, Clone, Copy)]
+enum Sample {
+    Value(f64),
+    Histogram { sum: f64, count: u64 },
+}
+
+#[derive(Debug, Clone)]
+struct Series {
+    created: f64,
+    sample: Sample,
+    exemplar: Option<Exemplar>,
+}
+
+/// All the series of a metric family, keyed by their printed labels.
+#[derive(Debug)]
+struct Family {
+    help: String,
+    metric_type: MetricType,
+    unit: Option<&'static str>,
+    series: BTreeMap<String, Series>,
+}
+
+impl Family {
+    /// Update the series with a new value.
+    fn record(&mut self, series: String, value: f64, exemplar: Option<Exemplar>, now: f64) {
+        let metric_type = self.metric_type;
+        let series = self.series.entry(series).or_insert_with(|| Series {
+            created: now,
+            sample: match metric_type {
+                MetricType::Histogram => Sample::Histogram { sum: 0.0, count: 0 },
+                _ => Sample::Value(0.0),
+            },
+            exemplar: None,
+        });
+        match &mut series.sample {
+            // dipstick counters write increments, OpenMetrics counters expose their total
+            Sample::Value(total) if metric_type == MetricType::Counter => *total += value,
+            Sample::Value(latest) => *latest = value,
+            Sample::Histogram { sum, count } => {
+                *sum += value;
+                *count += 1;
+            }
+        }
+        // gauges can not have exemplars
+        if exemplar.is_some() && metric_type != MetricType::Gauge {
+            series.exemplar = exemplar;
+        }
+    }
+}
+
+/// Append a label to printed labels.
+fn add_label(labels: &str, label: &str) -> String {
+    match labels.strip_suffix('}') {
+        Some(open) => format!("{open},{label}}}"),
+        None => format!("{{{label}}}"),
+    }
+}
+
+/// Print all families in the OpenMetrics text format.
+fn render(families: &Families) -> String {
+    let mut body = String::new();
+    for (name, family) in families {
+        let _ = writeln!(body, "# TYPE {name} {}", family.metric_type.as_str());
+        if let Some(unit) = family.unit {
+            let _ = writeln!(body, "# UNIT {name} {unit}");
+        }
+        let _ = writeln!(body, "# HELP {name} {}", family.help);
+        for (labels, series) in &family.series {
+            let exemplar = match &series.exemplar {
+                Some(ex) => format!(" # {} {} {}", ex.labels, ex.value, ex.timestamp),
+                None => String::new(),
+            };
+            match series.sample {
+                Sample::Value(value) if family.metric_type == MetricType::Counter => {
+                    let _ = writeln!(body, "{name}_total{labels} {value}{exemplar}");
+                    let _ = writeln!(body, "{name}_created{labels} {}", series.created);
+                }
+                Sample::Value(value) => {
+                    let _ = writeln!(body, "{name}{labels} {value}");
+                }
+                Sample::Histogram { sum, count } => {
+                    let bucket_labels = add_label(labels, "le=\"+Inf\"");
+                    let _ = writeln!(body, "{name}_bucket{bucket_labels} {count}{exemplar}");
+                    let _ = writeln!(body, "{name}_count{labels} {count}");
+                    let _ = writeln!(body, "{name}_sum{labels} {sum}");
+                    let _ = writeln!(body, "{name}_created{labels} {}", series.created);
+                }
+            }
+        }
+    }
+    body.push_str("# EOF\n");
+    body
+}
+
+/// Any remaining buffered data is flushed on Drop.
+impl Drop for OpenMetricsScope {
+    fn drop(&mut self) {
+        if let Err(err) = self.flush() {
+            warn!("Could not flush OpenMetrics metrics upon Drop: {err}")
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::label::test::TEST_SEQUENCE;
+    use crate::output::stand_in::stand_in;
+    use std::net::TcpListener;
+
+    #[test]
+    fn print_openmetrics_format() {
+        let _lock = TEST_SEQUENCE.lock().expect("Test Sequence");
+        // nothing listens there, values stay in the buffer
+        let metrics = OpenMetrics::push_to("http://127.0.0.1:1/metrics/job/test")
+            .unwrap()
+            .exemplar_labels(&["trace_id"])
+            .buffered(crate::Buffering::Unlimited)
+            .named("app")
+            .metrics();
+
+        metrics
+            .counter("requests_total")
+            .write(3, labels!("path" => "/a", "trace_id" => "abc"));
+        metrics
+            .gauge("gauge_a")
+            .write(4, labels!("trace_id" => "def"));
+        let timer = metrics.timer("latency");
+        timer.write(250_000, labels!("trace_id" => "ghi"));
+        timer.write(500_000, labels![]);
+
+        let mut families = write_lock!(metrics.families);
+        for series in families.values_mut().flat_map(|f| f.series.values_mut()) {
+            series.created = 1.5;
+            if let Some(exemplar) = &mut series.exemplar {
+                exemplar.timestamp = 2.5;
+            }
+        }
+
+        assert_eq!(
+            "# TYPE app_gauge_a gauge\n\
+             # HELP app_gauge_a app.gauge_a\n\
+             app_gauge_a 4\n\
+             # TYPE app_latency_seconds histogram\n\
+             # UNIT app_latency_seconds seconds\n\
+             # HELP app_latency_seconds app.latency\n\
+             app_latency_seconds_bucket{le=\"+Inf\"} 2 # {trace_id=\"ghi\"} 0.25 2.5\n\
+             app_latency_seconds_count 2\n\
+             app_latency_seconds_sum 0.75\n\
+             app_latency_seconds_created 1.5\n\
+             # TYPE app_requests counter\n\
+             # HELP app_requests app.requests_total\n\
+             app_requests_total{path=\"/a\"} 3 # {trace_id=\"abc\"} 3 2.5\n\
+             app_requests_created{path=\"/a\"} 1.5\n\
+             # EOF\n",
+            render(&families)
+        );
+        metrics.unsent.store(false, Release);
+    }
+
+    #[test]
+    fn push_totals() {
+        let _lock = TEST_SEQUENCE.lock().expect("Test Sequence");
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/metrics/job/test", listener.local_addr().unwrap());
+        let metrics = OpenMetrics::push_to(&url)
+            .unwrap()
+            .buffered(crate::Buffering::Unlimited)
+            .metrics();
+        let counter = metrics.counter("requests");
+
+        let server = stand_in(listener.try_clone().unwrap(), "200 OK");
+        counter.count(3);
+        metrics.flush().unwrap();
+        let first = String::from_utf8(server.join().unwrap().1).unwrap();
+        assert!(first.contains("requests_total 3\n"));
+
+        // nothing new to push
+        metrics.flush().unwrap();
+
+        let server = stand_in(listener.try_clone().unwrap(), "200 OK");
+        counter.count(4);
+        metrics.flush().unwrap();
+        let second = String::from_utf8(server.join().unwrap().1).unwrap();
+        assert!(second.contains("requests_total 7\n"));
+        let created = |body: &str| {
+            body.lines()
+                .find(|l| l.starts_with("requests_created"))
+                .map(str::to_string)
+        };
+        assert_eq!(created(&first), created(&second));
+
+        let server = stand_in(listener, "400 Bad Request");
+        counter.count(1);
+        assert!(metrics.flush().is_err());
+        server.join().unwrap();
+        metrics.unsent.store(false, Release);
+    }
+
+    #[test]
+    fn exemplar_length() {
+        let _lock = TEST_SEQUENCE.lock().expect("Test Sequence");
+        let metrics = OpenMetrics::push_to("http://127.0.0.1:1/metrics/job/test")
+            .unwrap()
+            .exemplar_labels(&["trace_id"])
+            .buffered(crate::Buffering::Unlimited)
+            .metrics();
+        let counter = metrics.counter("requests");
+
+        // 8 characters of name and 120 of value, braces, quotes and equal sign not counted
+        counter.write(1, labels!("trace_id" => "a".repeat(120)));
+        counter.write(1, labels!("trace_id" => "b".repeat(121)));
+
+        let families = read_lock!(metrics.families);
+        let exemplar = families["requests"].series[""].exemplar.as_ref().unwrap();
+        assert!(exemplar.labels.contains(&"a".repeat(120)));
+        drop(families);
+        metrics.unsent.store(false, Release);
+    }
+
+    #[test]
+    fn series_capped() {
+        let _lock = TEST_SEQUENCE.lock().expect("Test Sequence");
+        let metrics = OpenMetrics::push_to("http://127.0.0.1:1/metrics/job/test")
+            .unwrap()
+            .buffered(crate::Buffering::Unlimited)
+            .metrics();
+        let counter = metrics.counter("requests");
+        for i in 0..=MAX_PENDING_VALUES {
+            counter.write(1, labels!("id" => i.to_string()));
+        }
+        // known series are still updated
+        counter.write(1, labels!("id" => "0"));
+
+        let families = read_lock!(metrics.families);
+        let series = &families["requests"].series;
+        assert_eq!(MAX_PENDING_VALUES, series.len());
+        assert!(matches!(series["{id=\"0\"}"].sample, Sample::Value(2.0)));
+        drop(families);
+        metrics.unsent.store(false, Release);
+    }
+
+    #[test]
+    fn bucket_labels() {
+        assert_eq!("{le=\"1\"}", add_label("", "le=\"1\""));
+        assert_eq!("{a=\"b\",le=\"1\"}", add_label("{a=\"b\"}", "le=\"1\""));
+    }
+}
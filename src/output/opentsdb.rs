@@ -0,0 +1,486 @@
+//! Send metrics to an OpenTSDB server.
+
+use crate::attributes::{
+    Attributes, Buffered, MAX_PENDING_BYTES, MetricId, OnFlush, Prefixed, WithAttributes,
+};
+use crate::input::InputKind;
+use crate::input::{Input, InputMetric, InputScope};
+use crate::label::Labels;
+use crate::metrics;
+use crate::name::MetricName;
+use crate::output::socket::RetrySocket;
+use crate::{CachedInput, QueuedInput};
+use crate::{Flush, MetricValue};
+
+use std::collections::BTreeMap;
+use std::fmt::{Debug, Write as _};
+use std::io::Write;
+use std::net::ToSocketAddrs;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[cfg(not(feature = "parking_lot"))]
+use std::sync::{RwLock, RwLockWriteGuard};
+
+#[cfg(feature = "parking_lot")]
+use parking_lot::{RwLock, RwLockWriteGuard};
+use std::io;
+
+/// Where the data points are sent.
+#[derive(Clone, Debug)]
+enum OpenTsdbTarget {
+    /// `put` lines over the telnet-style protocol.
+    Tcp(Arc<RwLock<RetrySocket>>),
+    /// JSON arrays POSTed to the `/api/put` endpoint.
+    Http(String),
+}
+
+/// OpenTSDB's default `tsd.storage.max_tags`.
+const DEFAULT_MAX_TAGS: usize = 8;
+
+/// OpenTsdb Input sends metrics to an OpenTSDB server, with labels as tags.
+/// OpenTSDB rejects data points without tags or with too many tags,
+/// default tags are added to every data point and values left without any tag
+/// or with more tags than the limit are dropped.
+/// The transport is shared between scopes opened from the Input.
+#[derive(Clone, Debug)]
+pub struct OpenTsdb {
+    attributes: Attributes,
+    target: OpenTsdbTarget,
+    default_tags: BTreeMap<String, String>,
+    max_tags: usize,
+}
+
+impl OpenTsdb {
+    fn new(target: OpenTsdbTarget) -> OpenTsdb {
+        OpenTsdb {
+            attributes: Attributes::default(),
+            target,
+            default_tags: BTreeMap::new(),
+            max_tags: DEFAULT_MAX_TAGS,
+        }
+    }
+
+    /// Send `put` lines over TCP to an OpenTSDB server at the address and port provided.
+    /// The connection is reestablished if it drops.
+    pub fn send_to<A: ToSocketAddrs + Debug + Clone>(address: A) -> io::Result<OpenTsdb> {
+        debug!("Connecting to OpenTSDB {address:?}");
+        let socket = Arc::new(RwLock::new(RetrySocket::new(address)?));
+        Ok(OpenTsdb::new(OpenTsdbTarget::Tcp(socket)))
+    }
+
+    /// POST data points as JSON to the OpenTSDB HTTP API at the URL provided,
+    /// e.g. `http://localhost:4242/api/put`
+    pub fn put_to(url: &str) -> io::Result<OpenTsdb> {
+        debug!("Pushing to OpenTSDB {url:?}");
+        Ok(OpenTsdb::new(OpenTsdbTarget::Http(url.to_string())))
+    }
+
+    /// Add a tag to every data point of scopes opened afterwards, e.g. `host`.
+    /// Labels with the same key take precedence over default tags.
+    pub fn default_tag(&self, key: &str, value: &str) -> Self {
+        let mut cloned = self.clone();
+        cloned.default_tags.insert(sanitize(key), sanitize(value));
+        cloned
+    }
+
+    /// Drop data points with more tags than the specified count from scopes opened afterwards.
+    /// Defaults to 8, matching the server's default `tsd.storage.max_tags`.
+    pub fn max_tags(&self, max_tags: usize) -> Self {
+        let mut cloned = self.clone();
+        cloned.max_tags = max_tags;
+        cloned
+    }
+}
+
+impl Input for OpenTsdb {
+    type SCOPE = OpenTsdbScope;
+
+    fn metrics(&self) -> Self::SCOPE {
+        OpenTsdbScope {
+            attributes: self.attributes.clone(),
+            buffer: Arc::new(RwLock::new(String::new())),
+            target: self.target.clone(),
+            default_tags: self.default_tags.clone(),
+            max_tags: self.max_tags,
+        }
+    }
+}
+
+impl WithAttributes for OpenTsdb {
+    fn get_attributes(&self) -> &Attributes {
+        &self.attributes
+    }
+    fn mut_attributes(&mut self) -> &mut Attributes {
+        &mut self.attributes
+    }
+}
+
+impl Buffered for OpenTsdb {}
+
+impl QueuedInput for OpenTsdb {}
+impl CachedInput for OpenTsdb {}
+
+/// OpenTsdb Input
+#[derive(Debug, Clone)]
+pub struct OpenTsdbScope {
+    attributes: Attributes,
+    buffer: Arc<RwLock<String>>,
+    target: OpenTsdbTarget,
+    default_tags: BTreeMap<String, String>,
+    max_tags: usize,
+}
+
+impl InputScope for OpenTsdbScope {
+    /// Define a metric of the specified type.
+    fn new_metric(&self, name: MetricName, kind: InputKind) -> InputMetric {
+        let metric_name = sanitize(&self.prefix_prepend(name.clone()).join("."));
+
+        let scale = match kind {
+            // timers are in µs, but we give OpenTSDB milliseconds
+            InputKind::Timer => 1000,
+            _ => 1,
+        };
+
+        let cloned = self.clone();
+        let metric = OpenTsdbMetric { metric_name, scale };
+        let metric_id = MetricId::forge("opentsdb", name);
+
+        InputMetric::new(metric_id, move |value, labels| {
+            cloned.print(&metric, value, labels);
+        })
+    }
+}
+
+impl Flush for OpenTsdbScope {
+    fn flush(&self) -> io::Result<()> {
+        self.notify_flush_listeners();
+        let buf = write_lock!(self.buffer);
+        self.flush_inner(buf)
+    }
+}
+
+impl OpenTsdbScope {
+    fn print(&self, metric: &OpenTsdbMetric, value: MetricValue, labels: Labels) {
+        let mut tags = self.default_tags.clone();
+        for (key, value) in labels.into_map() {
+            tags.insert(sanitize(&key), sanitize(&value));
+        }
+        // empty tag values are not allowed either
+        tags.retain(|key, value| !key.is_empty() && !value.is_empty());
+        if tags.is_empty() {
+            metrics::OPENTSDB_UNTAGGED.mark();
+            debug!(
+                "OpenTSDB requires at least one tag, dropping {}",
+                metric.metric_name
+            );
+            return;
+        }
+        if tags.len() > self.max_tags {
+            // the server would reject the whole batch
+            metrics::OPENTSDB_TOO_MANY_TAGS.mark();
+            debug!(
+                "OpenTSDB accepts at most {} tags, dropping {}",
+                self.max_tags, metric.metric_name
+            );
+            return;
+        }
+
+        let timestamp = match SystemTime::now().duration_since(UNIX_EPOCH) {
+            Ok(timestamp) => timestamp.as_secs(),
+            Err(e) => {
+                warn!("Could not compute epoch timestamp. {e}");
+                return;
+            }
+        };
+        let value = value / metric.scale;
+
+        let mut point = String::new();
+        match self.target {
+            OpenTsdbTarget::Tcp(_) => {
+                let _ = write!(point, "put {} {timestamp} {value}", metric.metric_name);
+                for (key, value) in &tags {
+                    let _ = write!(point, " {key}={value}");
+                }
+                point.push('\n');
+            }
+            OpenTsdbTarget::Http(_) => {
+                let _ = write!(
+                    point,
+                    "{{\"metric\":\"{}\",\"timestamp\":{timestamp},\"value\":{value},\"tags\":{{",
+                    metric.metric_name
+                );
+                for (i, (key, value)) in tags.iter().enumerate() {
+                    if i > 0 {
+                        point.push(',');
+                    }
+                    // sanitized tags never need escaping
+                    let _ = write!(point, "\"{key}\":\"{value}\"");
+                }
+                point.push_str("}}");
+            }
+        }
+
+        let mut buffer = write_lock!(self.buffer);
+        if buffer.len() + point.len() > MAX_PENDING_BYTES {
+            metrics::OPENTSDB_PENDING_DROPPED.mark();
+            debug!(
+                "OpenTSDB pending data points exceed {MAX_PENDING_BYTES} bytes, dropping {}",
+                metric.metric_name
+            );
+        } else {
+            if matches!(self.target, OpenTsdbTarget::Http(_)) && !buffer.is_empty() {
+                buffer.push(',');
+            }
+            buffer.push_str(&point);
+        }
+
+        if self.is_buffer_full(buffer.len()) {
+            let _ = self.flush_inner(buffer);
+            buffer = write_lock!(self.buffer);
+        }
+
+        if !self.is_buffered()
+            && let Err(e) = self.flush_inner(buffer)
+        {
+            debug!("Could not send to OpenTSDB {e}")
+        }
+    }
+
+    fn flush_inner(&self, mut buf: RwLockWriteGuard<String>) -> io::Result<()> {
+        if buf.is_empty() {
+            return Ok(());
+        }
+
+        let (result, sent) = match &self.target {
+            OpenTsdbTarget::Tcp(socket) => {
+                (write_lock!(socket).write_all(buf.as_bytes()), buf.len())
+            }
+            OpenTsdbTarget::Http(url) => {
+                let body = format!("[{}]", buf.as_str());
+                let result = match minreq::post(url.as_str())
+                    .with_header("Content-Type", "application/json")
+                    .with_body(body.as_str())
+                    .send()
+                {
+                    Ok(response) if (200..300).contains(&response.status_code) => Ok(()),
+                    Ok(response) => {
+                        if response.status_code != 429 && response.status_code < 500 {
+                            // the server will never accept these data points, retrying would only block the next ones
+                            metrics::OPENTSDB_DROPPED.mark();
+                            buf.clear();
+                        }
+                        Err(io::Error::other(format!(
+                            "OpenTSDB put failed with status {} {}",
+                            response.status_code, response.reason_phrase
+                        )))
+                    }
+                    Err(e) => Err(io::Error::other(e)),
+                };
+                (result, body.len())
+            }
+        };
+
+        match result {
+            Ok(()) => {
+                metrics::OPENTSDB_SENT_BYTES.count(sent);
+                trace!("Sent {sent} bytes to OpenTSDB");
+                buf.clear();
+                Ok(())
+            }
+            Err(e) => {
+                metrics::OPENTSDB_SEND_ERR.mark();
+                debug!("Failed to send buffer to OpenTSDB: {e}");
+                Err(e)
+            }
+        }
+    }
+}
+
+impl WithAttributes for OpenTsdbScope {
+    fn get_attributes(&self) -> &Attributes {
+        &self.attributes
+    }
+    fn mut_attributes(&mut self) -> &mut Attributes {
+        &mut self.attributes
+    }
+}
+
+impl Buffered for OpenTsdbScope {}
+
+/// Key of an OpenTSDB metric.
+#[derive(Debug, Clone)]
+pub struct OpenTsdbMetric {
+    metric_name: String,
+    scale: isize,
+}
+
+/// Replace characters not allowed in metric names and tags by an underscore.
+/// OpenTSDB accepts letters, digits, `-`, `_`, `.` and `/`.
+fn sanitize(element: &str) -> String {
+    element
+        .chars()
+        .map(|c| match c {
+            c if c.is_alphanumeric() => c,
+            '-' | '_' | '.' | '/' => c,
+            _ => '_',
+        })
+        .collect()
+}
+
+/// Any remaining buffered data is flushed on Drop.
+impl Drop for OpenTsdbScope {
+    fn drop(&mut self) {
+        if let Err(err) = self.flush() {
+            warn!("Could not flush OpenTSDB metrics upon Drop: {err}")
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::label::test::TEST_SEQUENCE;
+    use crate::output::stand_in::stand_in;
+    use std::io::{BufRead, BufReader};
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::Duration;
+
+    /// Strip the variable timestamp from a `put` line.
+    fn strip_timestamp(line: &str) -> String {
+        let parts: Vec<_> = line.split(' ').collect();
+        [&parts[..2], &parts[3..]].concat().join(" ")
+    }
+
+    #[test]
+    fn telnet_put() {
+        let _lock = TEST_SEQUENCE.lock().expect("Test Sequence");
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let metrics = OpenTsdb::send_to(listener.local_addr().unwrap())
+            .unwrap()
+            .default_tag("host", "web 01")
+            .buffered(crate::Buffering::Unlimited)
+            .named("app")
+            .metrics();
+
+        let counter = metrics.counter("counter a");
+        let timer = metrics.timer("timer_a");
+        counter.write(3, labels!("path" => "/a=b", "host" => "web02"));
+        timer.write(3000, labels![]);
+        // the socket connects once its initial reconnect delay expires, lines are kept until then
+        while metrics.flush().is_err() {
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        let (stream, _) = listener.accept().unwrap();
+        let lines: Vec<_> = BufReader::new(stream)
+            .lines()
+            .take(2)
+            .map(|line| strip_timestamp(&line.unwrap()))
+            .collect();
+        assert_eq!(
+            vec![
+                "put app.counter_a 3 host=web02 path=/a_b",
+                "put app.timer_a 3 host=web_01"
+            ],
+            lines
+        );
+    }
+
+    #[test]
+    fn http_put() {
+        let _lock = TEST_SEQUENCE.lock().expect("Test Sequence");
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/api/put", listener.local_addr().unwrap());
+        let server = stand_in(listener, "204 No Content");
+
+        let metrics = OpenTsdb::put_to(&url).unwrap().metrics();
+        let gauge = metrics.gauge("gauge_a");
+        gauge.write(7, labels!("a" => "1"));
+
+        let (head, body) = server.join().unwrap();
+        assert!(head.starts_with("POST /api/put HTTP/1.1\r\n"));
+        let body = String::from_utf8(body).unwrap();
+        let (head, tail) = body.split_once(",\"value\"").unwrap();
+        assert!(head.starts_with("[{\"metric\":\"gauge_a\",\"timestamp\":"));
+        assert_eq!(":7,\"tags\":{\"a\":\"1\"}}]", tail);
+    }
+
+    #[test]
+    fn http_rejected() {
+        let _lock = TEST_SEQUENCE.lock().expect("Test Sequence");
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/api/put", listener.local_addr().unwrap());
+        let server = stand_in(listener, "400 Bad Request");
+
+        let metrics = OpenTsdb::put_to(&url)
+            .unwrap()
+            .buffered(crate::Buffering::Unlimited)
+            .metrics();
+        let gauge = metrics.gauge("gauge_a");
+        gauge.write(7, labels!("a" => "1"));
+        assert!(metrics.flush().is_err());
+        server.join().unwrap();
+
+        // the data points would never be accepted, they are dropped
+        assert!(read_lock!(metrics.buffer).is_empty());
+    }
+
+    #[test]
+    fn pending_capped() {
+        let _lock = TEST_SEQUENCE.lock().expect("Test Sequence");
+        // nothing listens there, values stay in the buffer
+        let metrics = OpenTsdb::put_to("http://127.0.0.1:1/api/put")
+            .unwrap()
+            .buffered(crate::Buffering::Unlimited)
+            .metrics();
+        let gauge = metrics.gauge("gauge_a");
+        let mut pending = 0;
+        loop {
+            gauge.write(7, labels!("a" => "1"));
+            let len = read_lock!(metrics.buffer).len();
+            if len == pending {
+                break;
+            }
+            pending = len;
+        }
+        assert!(pending <= MAX_PENDING_BYTES);
+        assert!(pending > MAX_PENDING_BYTES - 100);
+        write_lock!(metrics.buffer).clear();
+    }
+
+    #[test]
+    fn untagged_dropped() {
+        let _lock = TEST_SEQUENCE.lock().expect("Test Sequence");
+        // nothing listens there, values stay in the buffer
+        let metrics = OpenTsdb::put_to("http://127.0.0.1:1/api/put")
+            .unwrap()
+            .buffered(crate::Buffering::Unlimited)
+            .metrics();
+        let gauge = metrics.gauge("gauge_a");
+        gauge.write(7, labels![]);
+        assert!(read_lock!(metrics.buffer).is_empty());
+        gauge.write(7, labels!("a" => "1"));
+        assert!(!read_lock!(metrics.buffer).is_empty());
+        write_lock!(metrics.buffer).clear();
+    }
+
+    #[test]
+    fn too_many_tags_dropped() {
+        let _lock = TEST_SEQUENCE.lock().expect("Test Sequence");
+        // nothing listens there, values stay in the buffer
+        let metrics = OpenTsdb::put_to("http://127.0.0.1:1/api/put")
+            .unwrap()
+            .default_tag("host", "h1")
+            .max_tags(2)
+            .buffered(crate::Buffering::Unlimited)
+            .metrics();
+        let gauge = metrics.gauge("gauge_a");
+        gauge.write(7, labels!("a" => "1", "b" => "2"));
+        assert!(read_lock!(metrics.buffer).is_empty());
+        gauge.write(7, labels!("host" => "h2", "a" => "1"));
+        assert!(!read_lock!(metrics.buffer).is_empty());
+        write_lock!(metrics.buffer).clear();
+    }
+}
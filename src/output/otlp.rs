@@ -0,0 +1,741 @@
+//! Send metrics to an OpenTelemetry collector using OTLP over HTTP.
+
+use crate::attributes::{
+    Attributes, Buffered, MAX_PENDING_VALUES, MetricId, OnFlush, Prefixed, WithAttributes,
+};
+use crate::input::InputKind;
+use crate::input::{Input, InputMetric, InputScope};
+use crate::label::{AppLabel, Labels};
+use crate::metrics;
+use crate::name::MetricName;
+use crate::output::format::escape_json;
+use crate::output::proto;
+use crate::{CachedInput, QueuedInput};
+use crate::{Flush, MetricValue};
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[cfg(not(feature = "parking_lot"))]
+use std::sync::{RwLock, RwLockWriteGuard};
+
+#[cfg(feature = "parking_lot")]
+use parking_lot::{RwLock, RwLockWriteGuard};
+use std::io;
+
+/// Name of the instrumentation scope reported with every batch.
+const SCOPE_NAME: &str = "dipstick";
+
+/// OTLP `AGGREGATION_TEMPORALITY_DELTA`, values are reset on every flush.
+const DELTA: u64 = 1;
+
+/// Sorted attribute pairs identifying a data point.
+type Attrs = Vec<(String, Arc<String>)>;
+
+/// Encoding of the exported batches.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub enum OtlpEncoding {
+    /// Binary protobuf encoding, `application/x-protobuf`.
+    #[default]
+    Protobuf,
+    /// JSON encoding, `application/json`.
+    Json,
+}
+
+impl OtlpEncoding {
+    fn content_type(self) -> &'static str {
+        match self {
+            OtlpEncoding::Protobuf => "application/x-protobuf",
+            OtlpEncoding::Json => "application/json",
+        }
+    }
+}
+
+/// Otlp Input POSTs metrics to an OpenTelemetry collector's OTLP/HTTP metrics endpoint.
+/// Application labels are sent as resource attributes, other labels as data point attributes.
+#[derive(Clone, Debug)]
+pub struct Otlp {
+    attributes: Attributes,
+    url: String,
+    encoding: OtlpEncoding,
+}
+
+impl Otlp {
+    /// Send metrics to the OTLP/HTTP endpoint at the URL provided.
+    /// For example `http://localhost:4318/v1/metrics`
+    pub fn push_to(url: &str) -> io::Result<Otlp> {
+        debug!("Pushing to OTLP {url:?}");
+
+        Ok(Otlp {
+            attributes: Attributes::default(),
+            url: url.to_string(),
+            encoding: OtlpEncoding::default(),
+        })
+    }
+
+    /// Use the specified encoding for scopes opened afterwards.
+    pub fn encoding(&self, encoding: OtlpEncoding) -> Self {
+        let mut cloned = self.clone();
+        cloned.encoding = encoding;
+        cloned
+    }
+}
+
+impl Input for Otlp {
+    type SCOPE = OtlpScope;
+
+    fn metrics(&self) -> Self::SCOPE {
+        OtlpScope {
+            attributes: self.attributes.clone(),
+            batch: Arc::new(RwLock::new(Batch {
+                start_time: now_nanos(),
+                streams: BTreeMap::new(),
+            })),
+            url: self.url.clone(),
+            encoding: self.encoding,
+        }
+    }
+}
+
+impl WithAttributes for Otlp {
+    fn get_attributes(&self) -> &Attributes {
+        &self.attributes
+    }
+    fn mut_attributes(&mut self) -> &mut Attributes {
+        &mut self.attributes
+    }
+}
+
+impl Buffered for Otlp {}
+
+impl QueuedInput for Otlp {}
+impl CachedInput for Otlp {}
+
+/// Otlp Input
+#[derive(Debug, Clone)]
+pub struct OtlpScope {
+    attributes: Attributes,
+    batch: Arc<RwLock<Batch>>,
+    url: String,
+    encoding: OtlpEncoding,
+}
+
+impl InputScope for OtlpScope {
+    /// Define a metric of the specified type.
+    fn new_metric(&self, name: MetricName, kind: InputKind) -> InputMetric {
+        let (unit, scale) = match kind {
+            // timers are in µs, but we give OTLP milliseconds
+            InputKind::Timer => ("ms", 1000.0),
+            _ => ("", 1.0),
+        };
+
+        let cloned = self.clone();
+        let metric = OtlpMetric {
+            name: self.prefix_prepend(name.clone()).join("."),
+            data: kind.into(),
+            unit,
+            scale,
+        };
+        let metric_id = MetricId::forge("otlp", name);
+
+        InputMetric::new(metric_id, move |value, labels| {
+            cloned.print(&metric, value, labels);
+        })
+    }
+}
+
+impl Flush for OtlpScope {
+    fn flush(&self) -> io::Result<()> {
+        self.notify_flush_listeners();
+        let batch = write_lock!(self.batch);
+        self.flush_inner(batch)
+    }
+}
+
+impl OtlpScope {
+    fn print(&self, metric: &OtlpMetric, value: MetricValue, labels: Labels) {
+        let mut attrs: Attrs = labels.into_map().into_iter().collect();
+        attrs.sort();
+
+        let mut batch = write_lock!(self.batch);
+        let new_point = !batch
+            .streams
+            .get(&metric.name)
+            .is_some_and(|stream| stream.points.contains_key(&attrs));
+        let point_count: usize = batch.streams.values().map(|s| s.points.len()).sum();
+        if new_point && point_count >= MAX_PENDING_VALUES {
+            metrics::OTLP_PENDING_DROPPED.mark();
+            debug!(
+                "OTLP pending points exceed {MAX_PENDING_VALUES}, dropping {}",
+                metric.name
+            );
+        } else {
+            Self::record(&mut batch, metric, value, attrs);
+            if new_point && self.is_buffer_full(point_count + 1) {
+                let _ = self.flush_inner(batch);
+                batch = write_lock!(self.batch);
+            }
+        }
+
+        if !self.is_buffered()
+            && let Err(e) = self.flush_inner(batch)
+        {
+            debug!("Could not send to OTLP {e}")
+        }
+    }
+
+    /// Aggregate the value into the point of its metric and attributes.
+    fn record(batch: &mut Batch, metric: &OtlpMetric, value: MetricValue, attrs: Attrs) {
+        let stream = batch
+            .streams
+            .entry(metric.name.clone())
+            .or_insert_with(|| Stream {
+                data: metric.data,
+                unit: metric.unit,
+                points: BTreeMap::new(),
+            });
+        let point = stream.points.entry(attrs).or_insert(match metric.data {
+            DataType::Histogram => Point::Histogram {
+                count: 0,
+                sum: 0.0,
+                min: f64::MAX,
+                max: f64::MIN,
+            },
+            _ => Point::Number(0),
+        });
+        match point {
+            Point::Number(number) => match metric.data {
+                DataType::Gauge => *number = value as i64,
+                _ => *number += value as i64,
+            },
+            Point::Histogram {
+                count,
+                sum,
+                min,
+                max,
+            } => {
+                let value = value as f64 / metric.scale;
+                *count += 1;
+                *sum += value;
+                *min = min.min(value);
+                *max = max.max(value);
+            }
+        }
+    }
+
+    fn flush_inner(&self, mut batch: RwLockWriteGuard<Batch>) -> io::Result<()> {
+        if batch.streams.is_empty() {
+            return Ok(());
+        }
+
+        let mut resource = HashMap::new();
+        AppLabel::collect(&mut resource);
+        let now = now_nanos();
+        let body = match self.encoding {
+            OtlpEncoding::Protobuf => encode_protobuf(&batch, &resource, now),
+            OtlpEncoding::Json => encode_json(&batch, &resource, now).into_bytes(),
+        };
+        let body_len = body.len();
+
+        match minreq::post(self.url.as_str())
+            .with_header("Content-Type", self.encoding.content_type())
+            .with_body(body)
+            .send()
+        {
+            Ok(response) if (200..300).contains(&response.status_code) => {
+                metrics::OTLP_SENT_BYTES.count(body_len);
+                trace!("Sent {body_len} bytes to OTLP");
+                batch.streams.clear();
+                batch.start_time = now;
+                Ok(())
+            }
+            Ok(response) if is_retryable(response.status_code) => {
+                metrics::OTLP_SEND_ERR.mark();
+                debug!("OTLP export deferred: {}", response.status_code);
+                Err(io::Error::other(format!(
+                    "OTLP export failed with status {} {}",
+                    response.status_code, response.reason_phrase
+                )))
+            }
+            Ok(response) => {
+                // the collector will never accept this batch, retrying would only block the next ones
+                metrics::OTLP_DROPPED.mark();
+                debug!("OTLP export rejected: {}", response.status_code);
+                batch.streams.clear();
+                batch.start_time = now;
+                Err(io::Error::other(format!(
+                    "OTLP export failed with status {} {}",
+                    response.status_code, response.reason_phrase
+                )))
+            }
+            Err(e) => {
+                metrics::OTLP_SEND_ERR.mark();
+                debug!("Failed to send buffer to OTLP: {e}");
+                Err(io::Error::other(e))
+            }
+        }
+    }
+}
+
+/// Returns true if an export failing with the status code may succeed later,
+/// as defined by the OTLP/HTTP specification.
+fn is_retryable(status_code: i32) -> bool {
+    matches!(status_code, 429 | 502 | 503 | 504)
+}
+
+impl WithAttributes for OtlpScope {
+    fn get_attributes(&self) -> &Attributes {
+        &self.attributes
+    }
+    fn mut_attributes(&mut self) -> &mut Attributes {
+        &mut self.attributes
+    }
+}
+
+impl Buffered for OtlpScope {}
+
+/// Key of an OTLP metric.
+#[derive(Debug, Clone)]
+pub struct OtlpMetric {
+    name: String,
+    data: DataType,
+    unit: &'static str,
+    scale: f64,
+}
+
+/// OTLP data types used to export dipstick metrics.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DataType {
+    Sum { monotonic: bool },
+    Gauge,
+    Histogram,
+}
+
+impl From<InputKind> for DataType {
+    fn from(kind: InputKind) -> Self {
+        match kind {
+            InputKind::Marker | InputKind::Counter => DataType::Sum { monotonic: true },
+            InputKind::Level => DataType::Sum { monotonic: false },
+            InputKind::Gauge | InputKind::Set => DataType::Gauge,
+            InputKind::Timer | InputKind::Histogram | InputKind::Distribution => {
+                DataType::Histogram
+            }
+        }
+    }
+}
+
+/// A single data point.
+/// Sums and histograms accumulate every observed value, gauges keep the latest one.
+#[derive(Debug, Clone, Copy)]
+enum Point {
+    Number(i64),
+    Histogram {
+        count: u64,
+        sum: f64,
+        min: f64,
+        max: f64,
+    },
+}
+
+/// All the data points of a metric, keyed by their attributes.
+#[derive(Debug)]
+struct Stream {
+    data: DataType,
+    unit: &'static str,
+    points: BTreeMap<Attrs, Point>,
+}
+
+/// Metrics recorded since the last successful export.
+#[derive(Debug)]
+struct Batch {
+    start_time: u64,
+    streams: BTreeMap<String, Stream>,
+}
+
+fn now_nanos() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or_default()
+}
+
+/// Data point attributes, without the labels already sent as resource attributes.
+fn point_attributes<'a>(
+    attrs: &'a Attrs,
+    resource: &'a HashMap<String, Arc<String>>,
+) -> impl Iterator<Item = &'a (String, Arc<String>)> {
+    attrs
+        .iter()
+        .filter(move |(key, value)| resource.get(key) != Some(value))
+}
+
+/// Print the batch as an OTLP/JSON `ExportMetricsServiceRequest`.
+fn encode_json(batch: &Batch, resource: &HashMap<String, Arc<String>>, now: u64) -> String {
+    let mut resource_attrs: Attrs = resource
+        .iter()
+        .map(|(k, v)| (k.clone(), v.clone()))
+        .collect();
+    resource_attrs.sort();
+
+    let mut json = String::from("{\"resourceMetrics\":[{\"resource\":{\"attributes\":");
+    json_attributes(&mut json, resource_attrs.iter());
+    let _ = write!(
+        json,
+        "}},\"scopeMetrics\":[{{\"scope\":{{\"name\":\"{SCOPE_NAME}\",\"version\":\"{}\"}},\"metrics\":[",
+        env!("CARGO_PKG_VERSION")
+    );
+    for (i, (name, stream)) in batch.streams.iter().enumerate() {
+        if i > 0 {
+            json.push(',');
+        }
+        let _ = write!(
+            json,
+            "{{\"name\":\"{}\",\"unit\":\"{}\",",
+            escape_json(name),
+            stream.unit
+        );
+        json.push_str(match stream.data {
+            DataType::Sum { .. } => "\"sum\"",
+            DataType::Gauge => "\"gauge\"",
+            DataType::Histogram => "\"histogram\"",
+        });
+        json.push_str(":{\"dataPoints\":[");
+        for (j, (attrs, point)) in stream.points.iter().enumerate() {
+            if j > 0 {
+                json.push(',');
+            }
+            json.push_str("{\"attributes\":");
+            json_attributes(&mut json, point_attributes(attrs, resource));
+            if stream.data != DataType::Gauge {
+                let _ = write!(json, ",\"startTimeUnixNano\":\"{}\"", batch.start_time);
+            }
+            let _ = write!(json, ",\"timeUnixNano\":\"{now}\",");
+            match point {
+                Point::Number(value) => {
+                    let _ = write!(json, "\"asInt\":\"{value}\"}}");
+                }
+                Point::Histogram {
+                    count,
+                    sum,
+                    min,
+                    max,
+                } => {
+                    let _ = write!(
+                        json,
+                        "\"count\":\"{count}\",\"sum\":{sum},\"bucketCounts\":[\"{count}\"],\
+                         \"explicitBounds\":[],\"min\":{min},\"max\":{max}}}"
+                    );
+                }
+            }
+        }
+        json.push(']');
+        match stream.data {
+            DataType::Sum { monotonic } => {
+                let _ = write!(
+                    json,
+                    ",\"aggregationTemporality\":{DELTA},\"isMonotonic\":{monotonic}"
+                );
+            }
+            DataType::Histogram => {
+                let _ = write!(json, ",\"aggregationTemporality\":{DELTA}");
+            }
+            DataType::Gauge => {}
+        }
+        json.push_str("}}");
+    }
+    json.push_str("]}]}]}");
+    json
+}
+
+fn json_attributes<'a>(json: &mut String, attrs: impl Iterator<Item = &'a (String, Arc<String>)>) {
+    json.push('[');
+    for (i, (key, value)) in attrs.enumerate() {
+        if i > 0 {
+            json.push(',');
+        }
+        let _ = write!(
+            json,
+            "{{\"key\":\"{}\",\"value\":{{\"stringValue\":\"{}\"}}}}",
+            escape_json(key),
+            escape_json(value)
+        );
+    }
+    json.push(']');
+}
+
+/// Encode the batch as an OTLP protobuf `ExportMetricsServiceRequest`.
+/// Field numbers are those of `opentelemetry/proto/metrics/v1/metrics.proto`.
+fn encode_protobuf(batch: &Batch, resource: &HashMap<String, Arc<String>>, now: u64) -> Vec<u8> {
+    let mut resource_attrs: Attrs = resource
+        .iter()
+        .map(|(k, v)| (k.clone(), v.clone()))
+        .collect();
+    resource_attrs.sort();
+
+    // Resource
+    let mut res = Vec::new();
+    for attr in &resource_attrs {
+        proto::message(&mut res, 1, &proto_attribute(attr));
+    }
+
+    // InstrumentationScope
+    let mut scope = Vec::new();
+    proto::string(&mut scope, 1, SCOPE_NAME);
+    proto::string(&mut scope, 2, env!("CARGO_PKG_VERSION"));
+
+    // ScopeMetrics
+    let mut scope_metrics = Vec::new();
+    proto::message(&mut scope_metrics, 1, &scope);
+    for (name, stream) in &batch.streams {
+        let mut data = Vec::new();
+        for (attrs, point) in &stream.points {
+            let mut dp = Vec::new();
+            let attributes = point_attributes(attrs, resource).map(proto_attribute);
+            match point {
+                // NumberDataPoint
+                Point::Number(value) => {
+                    if stream.data != DataType::Gauge {
+                        proto::fixed64(&mut dp, 2, batch.start_time);
+                    }
+                    proto::fixed64(&mut dp, 3, now);
+                    proto::fixed64(&mut dp, 6, *value as u64);
+                    for attr in attributes {
+                        proto::message(&mut dp, 7, &attr);
+                    }
+                }
+                // HistogramDataPoint
+                Point::Histogram {
+                    count,
+                    sum,
+                    min,
+                    max,
+                } => {
+                    proto::fixed64(&mut dp, 2, batch.start_time);
+                    proto::fixed64(&mut dp, 3, now);
+                    proto::fixed64(&mut dp, 4, *count);
+                    proto::fixed64(&mut dp, 5, sum.to_bits());
+                    // packed single bucket, no explicit bounds
+                    proto::message(&mut dp, 6, &count.to_le_bytes());
+                    for attr in attributes {
+                        proto::message(&mut dp, 9, &attr);
+                    }
+                    proto::fixed64(&mut dp, 11, min.to_bits());
+                    proto::fixed64(&mut dp, 12, max.to_bits());
+                }
+            }
+            proto::message(&mut data, 1, &dp);
+        }
+        if let DataType::Sum { .. } | DataType::Histogram = stream.data {
+            proto::varint_field(&mut data, 2, DELTA);
+        }
+        if let DataType::Sum { monotonic } = stream.data {
+            proto::varint_field(&mut data, 3, monotonic as u64);
+        }
+
+        // Metric
+        let mut metric = Vec::new();
+        proto::string(&mut metric, 1, name);
+        proto::string(&mut metric, 3, stream.unit);
+        let data_field = match stream.data {
+            DataType::Gauge => 5,
+            DataType::Sum { .. } => 7,
+            DataType::Histogram => 9,
+        };
+        proto::message(&mut metric, data_field, &data);
+        proto::message(&mut scope_metrics, 2, &metric);
+    }
+
+    // ResourceMetrics
+    let mut resource_metrics = Vec::new();
+    proto::message(&mut resource_metrics, 1, &res);
+    proto::message(&mut resource_metrics, 2, &scope_metrics);
+
+    // ExportMetricsServiceRequest
+    let mut request = Vec::new();
+    proto::message(&mut request, 1, &resource_metrics);
+    request
+}
+
+/// Encode a `KeyValue` with a string `AnyValue`.
+fn proto_attribute((key, value): &(String, Arc<String>)) -> Vec<u8> {
+    let mut any_value = Vec::new();
+    proto::string(&mut any_value, 1, value);
+    let mut key_value = Vec::new();
+    proto::string(&mut key_value, 1, key);
+    proto::message(&mut key_value, 2, &any_value);
+    key_value
+}
+
+/// Any remaining buffered data is flushed on Drop.
+impl Drop for OtlpScope {
+    fn drop(&mut self) {
+        if let Err(err) = self.flush() {
+            warn!("Could not flush OTLP metrics upon Drop: {err}")
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::label::test::TEST_SEQUENCE;
+    use crate::output::stand_in::stand_in;
+    use std::net::TcpListener;
+
+    /// Replace the variable timestamps by zeros.
+    fn zero_timestamps(json: &str) -> String {
+        let mut zeroed = String::new();
+        let mut rest = json;
+        while let Some(pos) = rest.find("UnixNano\":\"") {
+            let start = pos + "UnixNano\":\"".len();
+            zeroed.push_str(&rest[..start]);
+            zeroed.push('0');
+            rest = &rest[start..];
+            rest = &rest[rest.find('"').unwrap()..];
+        }
+        zeroed.push_str(rest);
+        zeroed
+    }
+
+    #[test]
+    fn export_json() {
+        let _lock = TEST_SEQUENCE.lock().expect("Test Sequence");
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/v1/metrics", listener.local_addr().unwrap());
+        let server = stand_in(listener, "200 OK");
+
+        AppLabel::set("service.name", "test");
+        let metrics = Otlp::push_to(&url)
+            .unwrap()
+            .encoding(OtlpEncoding::Json)
+            .buffered(crate::Buffering::Unlimited)
+            .named("app")
+            .metrics();
+
+        let counter = metrics.counter("counter_a");
+        let level = metrics.level("level_a");
+        let timer = metrics.timer("timer_a");
+        counter.write(3, labels!("path" => "/\"a\""));
+        counter.write(4, labels!("path" => "/\"a\""));
+        level.adjust(-2);
+        timer.interval_us(2000);
+        timer.interval_us(5000);
+        metrics.flush().unwrap();
+        AppLabel::unset("service.name");
+
+        let (head, body) = server.join().unwrap();
+        assert!(head.starts_with("POST /v1/metrics HTTP/1.1\r\n"));
+        assert!(head.contains("Content-Type: application/json"));
+        assert_eq!(
+            format!(
+                "{{\"resourceMetrics\":[{{\"resource\":{{\"attributes\":[\
+                 {{\"key\":\"service.name\",\"value\":{{\"stringValue\":\"test\"}}}}]}},\
+                 \"scopeMetrics\":[{{\"scope\":{{\"name\":\"dipstick\",\"version\":\"{}\"}},\"metrics\":[\
+                 {{\"name\":\"app.counter_a\",\"unit\":\"\",\"sum\":{{\"dataPoints\":[\
+                 {{\"attributes\":[{{\"key\":\"path\",\"value\":{{\"stringValue\":\"/\\\"a\\\"\"}}}}],\
+                 \"startTimeUnixNano\":\"0\",\"timeUnixNano\":\"0\",\"asInt\":\"7\"}}],\
+                 \"aggregationTemporality\":1,\"isMonotonic\":true}}}},\
+                 {{\"name\":\"app.level_a\",\"unit\":\"\",\"sum\":{{\"dataPoints\":[\
+                 {{\"attributes\":[],\"startTimeUnixNano\":\"0\",\"timeUnixNano\":\"0\",\"asInt\":\"-2\"}}],\
+                 \"aggregationTemporality\":1,\"isMonotonic\":false}}}},\
+                 {{\"name\":\"app.timer_a\",\"unit\":\"ms\",\"histogram\":{{\"dataPoints\":[\
+                 {{\"attributes\":[],\"startTimeUnixNano\":\"0\",\"timeUnixNano\":\"0\",\
+                 \"count\":\"2\",\"sum\":7,\"bucketCounts\":[\"2\"],\"explicitBounds\":[],\"min\":2,\"max\":5}}],\
+                 \"aggregationTemporality\":1}}}}]}}]}}]}}",
+                env!("CARGO_PKG_VERSION")
+            ),
+            zero_timestamps(&String::from_utf8(body).unwrap())
+        );
+    }
+
+    #[test]
+    fn export_protobuf() {
+        let _lock = TEST_SEQUENCE.lock().expect("Test Sequence");
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/v1/metrics", listener.local_addr().unwrap());
+        let server = stand_in(listener, "200 OK");
+
+        let metrics = Otlp::push_to(&url).unwrap().metrics();
+        let gauge = metrics.gauge("g");
+        gauge.value(5);
+
+        let (head, body) = server.join().unwrap();
+        assert!(head.contains("Content-Type: application/x-protobuf"));
+
+        let mut dp = Vec::new();
+        proto::fixed64(&mut dp, 6, 5);
+        let mut metric = Vec::new();
+        proto::string(&mut metric, 1, "g");
+        // gauge / data point / time
+        metric.extend_from_slice(&[5 << 3 | 2, 2 + 9 + 9, 1 << 3 | 2, 9 + 9, 3 << 3 | 1]);
+        assert!(body.ends_with(&dp));
+        assert!(body.windows(metric.len()).any(|w| w == metric.as_slice()));
+    }
+
+    #[test]
+    fn export_rejected() {
+        let _lock = TEST_SEQUENCE.lock().expect("Test Sequence");
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/v1/metrics", listener.local_addr().unwrap());
+        let server = stand_in(listener, "400 Bad Request");
+
+        let metrics = Otlp::push_to(&url)
+            .unwrap()
+            .buffered(crate::Buffering::Unlimited)
+            .metrics();
+        let marker = metrics.marker("m");
+        marker.mark();
+        assert!(metrics.flush().is_err());
+        server.join().unwrap();
+
+        // the batch would never be accepted, values are dropped
+        assert!(read_lock!(metrics.batch).streams.is_empty());
+    }
+
+    #[test]
+    fn export_unavailable() {
+        let _lock = TEST_SEQUENCE.lock().expect("Test Sequence");
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/v1/metrics", listener.local_addr().unwrap());
+        let server = stand_in(listener, "503 Service Unavailable");
+
+        let metrics = Otlp::push_to(&url)
+            .unwrap()
+            .buffered(crate::Buffering::Unlimited)
+            .metrics();
+        let marker = metrics.marker("m");
+        marker.mark();
+        assert!(metrics.flush().is_err());
+        server.join().unwrap();
+
+        // values are kept for the next export
+        assert_eq!(1, read_lock!(metrics.batch).streams.len());
+        write_lock!(metrics.batch).streams.clear();
+    }
+
+    #[test]
+    fn points_capped() {
+        let _lock = TEST_SEQUENCE.lock().expect("Test Sequence");
+        let metrics = Otlp::push_to("http://127.0.0.1:1/v1/metrics")
+            .unwrap()
+            .buffered(crate::Buffering::Unlimited)
+            .metrics();
+        let counter = metrics.counter("requests");
+        for i in 0..=MAX_PENDING_VALUES {
+            counter.write(1, labels!("id" => i.to_string()));
+        }
+        // known points are still updated
+        counter.write(1, labels!("id" => "0"));
+
+        let mut batch = write_lock!(metrics.batch);
+        let points = &batch.streams["requests"].points;
+        assert_eq!(MAX_PENDING_VALUES, points.len());
+        let first = points
+            .values()
+            .find(|point| matches!(point, Point::Number(2)));
+        assert!(first.is_some());
+        batch.streams.clear();
+    }
+}
@@ -1,15 +1,26 @@
 //! Send metrics to a Prometheus server.
 
-use crate::attributes::{Attributes, Buffered, MetricId, OnFlush, Prefixed, WithAttributes};
+use crate::attributes::{
+    Attributes, Buffered, MAX_PENDING_VALUES, MetricId, OnFlush, Prefixed, WithAttributes,
+};
 use crate::input::InputKind;
-use crate::input::{Input, InputMetric, InputScope};
+use crate::input::{BucketsMetric, HistogramBuckets, Input, InputMetric, InputScope};
 use crate::label::Labels;
 use crate::metrics;
 use crate::name::MetricName;
 use crate::{CachedInput, QueuedInput};
 use crate::{Flush, MetricValue};
 
+use std::collections::BTreeMap;
+use std::fmt::Write;
 use std::sync::Arc;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering::*;
+
+#[cfg(feature = "prometheus_serve")]
+use std::net::ToSocketAddrs;
+#[cfg(feature = "prometheus_serve")]
+use std::thread;
 
 #[cfg(not(feature = "parking_lot"))]
 use std::sync::{RwLock, RwLockWriteGuard};
@@ -18,22 +29,45 @@ use std::sync::{RwLock, RwLockWriteGuard};
 use parking_lot::{RwLock, RwLockWriteGuard};
 use std::io;
 
-/// Prometheus Input holds a socket to a Prometheus server.
-/// The socket is shared between scopes opened from the Input.
+/// Content type of the Prometheus text exposition format.
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// Samples of every metric family, keyed by family name.
+type Families = BTreeMap<String, Family>;
+
+/// Where the Prometheus metrics go.
+#[derive(Clone, Debug)]
+enum PrometheusTarget {
+    /// POST buffered metrics to a push gateway URL.
+    Push(String),
+    /// Keep values for a scraping endpoint to serve.
+    #[cfg(feature = "prometheus_serve")]
+    Serve(Arc<RwLock<Families>>),
+}
+
+/// Prometheus Input either pushes metrics to a Prometheus push gateway
+/// or serves them from an embedded HTTP endpoint.
+/// The target is shared between scopes opened from the Input.
 #[derive(Clone, Debug)]
 pub struct Prometheus {
     attributes: Attributes,
-    push_url: String,
+    target: PrometheusTarget,
 }
 
 impl Input for Prometheus {
     type SCOPE = PrometheusScope;
 
     fn metrics(&self) -> Self::SCOPE {
+        let families = match &self.target {
+            PrometheusTarget::Push(_) => Arc::new(RwLock::new(Families::new())),
+            #[cfg(feature = "prometheus_serve")]
+            PrometheusTarget::Serve(registry) => registry.clone(),
+        };
         PrometheusScope {
             attributes: self.attributes.clone(),
-            buffer: Arc::new(RwLock::new(String::new())),
-            push_url: self.push_url.clone(),
+            families,
+            unsent: Arc::new(AtomicUsize::new(0)),
+            target: self.target.clone(),
         }
     }
 }
@@ -43,14 +77,65 @@ impl Prometheus {
     /// URL path must include group identifier labels `job`
     /// as shown in https://github.com/prometheus/pushgateway#command-line
     /// For example `http://pushgateway.example.org:9091/metrics/job/some_job`
+    /// Every push sends all the series of the scope, counters and summaries with their running totals.
     pub fn push_to(url: &str) -> io::Result<Prometheus> {
         debug!("Pushing to Prometheus {url:?}");
 
         Ok(Prometheus {
             attributes: Attributes::default(),
-            push_url: url.to_string(),
+            target: PrometheusTarget::Push(url.to_string()),
         })
     }
+
+    /// Serve metrics to Prometheus scrapers from an HTTP endpoint at the address provided.
+    /// Every `GET /metrics` returns the total of values written to counters and summaries since start,
+    /// and the latest value written to gauges.
+    /// The HTTP listener runs on its own thread for the remainder of the process.
+    #[cfg(feature = "prometheus_serve")]
+    pub fn serve_on<A: ToSocketAddrs>(address: A) -> io::Result<Prometheus> {
+        let server = tiny_http::Server::http(address).map_err(io::Error::other)?;
+        Self::serve_with(server)
+    }
+
+    #[cfg(feature = "prometheus_serve")]
+    fn serve_with(server: tiny_http::Server) -> io::Result<Prometheus> {
+        debug!("Serving Prometheus metrics on {:?}", server.server_addr());
+        let registry = Arc::new(RwLock::new(Families::new()));
+        let served = registry.clone();
+
+        thread::Builder::new()
+            .name("dipstick-prometheus-serve".to_string())
+            .spawn(move || serve_loop(server, CONTENT_TYPE, || render(&read_lock!(served))))?;
+
+        Ok(Prometheus {
+            attributes: Attributes::default(),
+            target: PrometheusTarget::Serve(registry),
+        })
+    }
+}
+
+/// Answer scrape requests with the rendered metrics until the server shuts down.
+#[cfg(feature = "prometheus_serve")]
+pub(crate) fn serve_loop<F: Fn() -> String>(
+    server: tiny_http::Server,
+    content_type: &str,
+    render: F,
+) {
+    let content_type = tiny_http::Header::from_bytes("Content-Type", content_type).unwrap();
+    for request in server.incoming_requests() {
+        let response = match (request.method(), request.url()) {
+            (tiny_http::Method::Get, "/metrics") => {
+                let body = render();
+                metrics::PROMETHEUS_SENT_BYTES.count(body.len());
+                tiny_http::Response::from_string(body).with_header(content_type.clone())
+            }
+            _ => tiny_http::Response::from_string("Not Found").with_status_code(404),
+        };
+        if let Err(e) = request.respond(response) {
+            metrics::PROMETHEUS_SEND_ERR.mark();
+            debug!("Failed to answer Prometheus scrape: {e}");
+        }
+    }
 }
 
 impl WithAttributes for Prometheus {
@@ -68,15 +153,15 @@ impl Buffered for Prometheus {}
 #[derive(Debug, Clone)]
 pub struct PrometheusScope {
     attributes: Attributes,
-    buffer: Arc<RwLock<String>>,
-    push_url: String,
+    families: Arc<RwLock<Families>>,
+    /// Number of values written since the last successful push.
+    unsent: Arc<AtomicUsize>,
+    target: PrometheusTarget,
 }
 
 impl InputScope for PrometheusScope {
     /// Define a metric of the specified type.
     fn new_metric(&self, name: MetricName, kind: InputKind) -> InputMetric {
-        let prefix = self.prefix_prepend(name.clone()).join("_");
-
         let scale = match kind {
             // timers are in µs, but we give Prometheus milliseconds
             InputKind::Timer => 1000,
@@ -84,92 +169,150 @@ impl InputScope for PrometheusScope {
         };
 
         let cloned = self.clone();
-        let metric = PrometheusMetric { prefix, scale };
-
+        let metric = self.define(&name, kind.into(), scale);
         let metric_id = MetricId::forge("prometheus", name);
 
         InputMetric::new(metric_id, move |value, labels| {
             cloned.print(&metric, value, labels);
         })
     }
+
+    /// Define a histogram exposing the buckets of values aggregated upstream.
+    fn new_buckets(&self, name: MetricName) -> Option<BucketsMetric> {
+        let cloned = self.clone();
+        let metric = self.define(&name, MetricType::Histogram, 1);
+        let metric_id = MetricId::forge("prometheus", name);
+
+        Some(BucketsMetric::new(metric_id, move |buckets, labels| {
+            cloned.print_buckets(&metric, buckets, labels);
+        }))
+    }
 }
 
 impl Flush for PrometheusScope {
     fn flush(&self) -> io::Result<()> {
         self.notify_flush_listeners();
-        let buf = write_lock!(self.buffer);
-        self.flush_inner(buf)
+        let families = write_lock!(self.families);
+        self.flush_inner(families)
     }
 }
 
 impl PrometheusScope {
+    fn define(&self, name: &MetricName, metric_type: MetricType, scale: isize) -> PrometheusMetric {
+        let full_name = self.prefix_prepend(name.clone());
+        PrometheusMetric {
+            family: sanitize_name(&full_name.join("_"), true),
+            help: escape_help(&full_name.join(".")),
+            metric_type,
+            scale,
+        }
+    }
+
     fn print(&self, metric: &PrometheusMetric, value: MetricValue, labels: Labels) {
         let scaled_value = value / metric.scale;
-        let value_str = scaled_value.to_string();
-
-        let mut strbuf = String::new();
-        // prometheus format be like `http_requests_total{method="post",code="200"} 1027 1395066363000`
-        strbuf.push_str(&metric.prefix);
-
-        let labels_map = labels.into_map();
-        if !labels_map.is_empty() {
-            strbuf.push('{');
-            let mut i = labels_map.into_iter();
-            let mut next = i.next();
-            while let Some((k, v)) = next {
-                strbuf.push_str(&k);
-                strbuf.push_str("=\"");
-                strbuf.push_str(&v);
-                next = i.next();
-                if next.is_some() {
-                    strbuf.push_str("\",");
-                } else {
-                    strbuf.push('"');
-                }
+        self.update(metric, labels, |family, series| {
+            family.record(series, scaled_value)
+        })
+    }
+
+    fn print_buckets(&self, metric: &PrometheusMetric, buckets: &HistogramBuckets, labels: Labels) {
+        self.update(metric, labels, |family, series| {
+            family.record_buckets(series, buckets)
+        })
+    }
+
+    /// Update the labeled series of the metric's family, flushing if required.
+    fn update(
+        &self,
+        metric: &PrometheusMetric,
+        labels: Labels,
+        record: impl FnOnce(&mut Family, String),
+    ) {
+        let series = format_label_pairs(labels.into_map().into_iter().collect());
+
+        let mut families = write_lock!(self.families);
+        let known = families
+            .get(&metric.family)
+            .is_some_and(|family| family.series.contains_key(&series));
+        if !known {
+            let series_count: usize = families.values().map(|f| f.series.len()).sum();
+            if series_count >= MAX_PENDING_VALUES {
+                metrics::PROMETHEUS_PENDING_DROPPED.mark();
+                debug!(
+                    "Too many Prometheus series, dropping {}{series}",
+                    metric.family
+                );
+                return;
             }
-            strbuf.push_str("} ");
-        } else {
-            strbuf.push(' ');
         }
-        strbuf.push_str(&value_str);
-        strbuf.push('\n');
-
-        let mut buffer = write_lock!(self.buffer);
-        if strbuf.len() + buffer.len() > BUFFER_FLUSH_THRESHOLD {
-            metrics::PROMETHEUS_OVERFLOW.mark();
-            warn!("Prometheus Buffer Size Exceeded: {BUFFER_FLUSH_THRESHOLD}");
-            let _ = self.flush_inner(buffer);
-            buffer = write_lock!(self.buffer);
+        let family = families
+            .entry(metric.family.clone())
+            .or_insert_with(|| Family {
+                help: metric.help.clone(),
+                metric_type: metric.metric_type,
+                series: BTreeMap::new(),
+            });
+        record(family, series);
+        let unsent = self.unsent.fetch_add(1, AcqRel) + 1;
+
+        #[cfg(feature = "prometheus_serve")]
+        if let PrometheusTarget::Serve(_) = &self.target {
+            return;
         }
 
-        buffer.push_str(&strbuf);
+        if self.is_buffer_full(unsent) {
+            let _ = self.flush_inner(families);
+            families = write_lock!(self.families);
+        }
 
-        if !self.is_buffered() {
-            if let Err(e) = self.flush_inner(buffer) {
-                debug!("Could not send to statsd {e}")
-            }
+        if !self.is_buffered()
+            && let Err(e) = self.flush_inner(families)
+        {
+            debug!("Could not send to prometheus {e}")
         }
     }
 
-    fn flush_inner(&self, mut buf: RwLockWriteGuard<String>) -> io::Result<()> {
-        if buf.is_empty() {
+    fn flush_inner(&self, families: RwLockWriteGuard<Families>) -> io::Result<()> {
+        if self.unsent.load(Acquire) == 0 || families.is_empty() {
             return Ok(());
         }
 
-        match minreq::post(self.push_url.as_str())
-            .with_body(buf.as_str())
+        #[allow(clippy::infallible_destructuring_match)]
+        let push_url = match &self.target {
+            PrometheusTarget::Push(url) => url,
+            // scraped values are never buffered
+            #[cfg(feature = "prometheus_serve")]
+            PrometheusTarget::Serve(_) => return Ok(()),
+        };
+
+        let body = render(&families);
+        match minreq::post(push_url.as_str())
+            .with_header("Content-Type", CONTENT_TYPE)
+            .with_body(body.as_str())
             .send()
         {
-            Ok(http_result) => {
-                metrics::PROMETHEUS_SENT_BYTES.count(buf.len());
+            Ok(http_result) if (200..300).contains(&http_result.status_code) => {
+                metrics::PROMETHEUS_SENT_BYTES.count(body.len());
                 trace!(
                     "Sent {} bytes to Prometheus (resp status code: {})",
-                    buf.len(),
+                    body.len(),
                     http_result.status_code
                 );
-                buf.clear();
+                // series are kept, their totals go on with the next push
+                self.unsent.store(0, Release);
                 Ok(())
             }
+            Ok(http_result) => {
+                metrics::PROMETHEUS_SEND_ERR.mark();
+                debug!(
+                    "Prometheus push rejected: {} {}",
+                    http_result.status_code, http_result.reason_phrase
+                );
+                Err(io::Error::other(format!(
+                    "Prometheus push rejected with status {}",
+                    http_result.status_code
+                )))
+            }
             Err(e) => {
                 metrics::PROMETHEUS_SEND_ERR.mark();
                 debug!("Failed to send buffer to Prometheus: {e}");
@@ -193,17 +336,210 @@ impl Buffered for PrometheusScope {}
 impl QueuedInput for Prometheus {}
 impl CachedInput for Prometheus {}
 
-/// Its hard to see how a single scope could get more metrics than this.
-// TODO make configurable?
-const BUFFER_FLUSH_THRESHOLD: usize = 65_536;
-
 /// Key of a Prometheus metric.
 #[derive(Debug, Clone)]
 pub struct PrometheusMetric {
-    prefix: String,
+    family: String,
+    help: String,
+    metric_type: MetricType,
     scale: isize,
 }
 
+/// Prometheus metric types used to expose dipstick metrics.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MetricType {
+    Counter,
+    Gauge,
+    Summary,
+    Histogram,
+}
+
+impl From<InputKind> for MetricType {
+    fn from(kind: InputKind) -> Self {
+        match kind {
+            InputKind::Marker | InputKind::Counter => MetricType::Counter,
+            InputKind::Gauge | InputKind::Level | InputKind::Set => MetricType::Gauge,
+            InputKind::Timer | InputKind::Histogram | InputKind::Distribution => {
+                MetricType::Summary
+            }
+        }
+    }
+}
+
+impl MetricType {
+    fn as_str(self) -> &'static str {
+        match self {
+            MetricType::Counter => "counter",
+            MetricType::Gauge => "gauge",
+            MetricType::Summary => "summary",
+            MetricType::Histogram => "histogram",
+        }
+    }
+}
+
+/// A single series value.
+/// Counters, summaries and histograms accumulate every observed value, gauges keep the latest one.
+#[derive(Debug, Clone)]
+enum Sample {
+    Value(MetricValue),
+    Summary {
+        sum: MetricValue,
+        count: usize,
+    },
+    Histogram {
+        sum: MetricValue,
+        buckets: BTreeMap<MetricValue, usize>,
+    },
+}
+
+/// All the series of a metric family, keyed by their printed labels.
+#[derive(Debug)]
+struct Family {
+    help: String,
+    metric_type: MetricType,
+    series: BTreeMap<String, Sample>,
+}
+
+impl Family {
+    /// Update the series with a new value.
+    fn record(&mut self, series: String, value: MetricValue) {
+        if self.metric_type == MetricType::Histogram {
+            // only buckets can be added to a histogram
+            return;
+        }
+        let metric_type = self.metric_type;
+        let sample = self.series.entry(series).or_insert(match metric_type {
+            MetricType::Summary => Sample::Summary { sum: 0, count: 0 },
+            _ => Sample::Value(0),
+        });
+        match sample {
+            // dipstick counters write increments, Prometheus counters expose their total
+            Sample::Value(total) if metric_type == MetricType::Counter => *total += value,
+            Sample::Value(latest) => *latest = value,
+            Sample::Summary { sum, count } => {
+                *sum += value;
+                *count += 1;
+            }
+            Sample::Histogram { .. } => {}
+        }
+    }
+
+    /// Add the counts and sum of a histogram's buckets to the series.
+    fn record_buckets(&mut self, series: String, histogram: &HistogramBuckets) {
+        if self.metric_type != MetricType::Histogram {
+            // buckets can not be added to a plain metric of the same name
+            return;
+        }
+        let sample = self
+            .series
+            .entry(series)
+            .or_insert_with(|| Sample::Histogram {
+                sum: 0,
+                buckets: BTreeMap::new(),
+            });
+        if let Sample::Histogram { sum, buckets } = sample {
+            for &(bound, count) in &histogram.counts {
+                *buckets.entry(bound).or_insert(0) += count;
+            }
+            *sum += histogram.sum;
+        }
+    }
+}
+
+/// Print all families in the Prometheus text exposition format.
+fn render(families: &Families) -> String {
+    let mut body = String::new();
+    for (name, family) in families {
+        let _ = writeln!(body, "# HELP {name} {}", family.help);
+        let _ = writeln!(body, "# TYPE {name} {}", family.metric_type.as_str());
+        for (labels, sample) in &family.series {
+            match sample {
+                Sample::Value(value) => {
+                    let _ = writeln!(body, "{name}{labels} {value}");
+                }
+                Sample::Summary { sum, count } => {
+                    let _ = writeln!(body, "{name}_sum{labels} {sum}");
+                    let _ = writeln!(body, "{name}_count{labels} {count}");
+                }
+                Sample::Histogram { sum, buckets } => {
+                    for (bound, count) in buckets {
+                        let le = if *bound == MetricValue::MAX {
+                            "+Inf".to_string()
+                        } else {
+                            bound.to_string()
+                        };
+                        let bucket_labels = match labels.strip_suffix('}') {
+                            Some(labels) => format!("{labels},le=\"{le}\"}}"),
+                            None => format!("{{le=\"{le}\"}}"),
+                        };
+                        let _ = writeln!(body, "{name}_bucket{bucket_labels} {count}");
+                    }
+                    // the unbounded bucket counts all values
+                    let count = buckets.values().last().copied().unwrap_or(0);
+                    let _ = writeln!(body, "{name}_sum{labels} {sum}");
+                    let _ = writeln!(body, "{name}_count{labels} {count}");
+                }
+            }
+        }
+    }
+    body
+}
+
+/// Print label pairs sorted by key as `{key="value",...}`, or nothing if there are no pairs.
+pub(crate) fn format_label_pairs(mut labels: Vec<(String, Arc<String>)>) -> String {
+    // sort labels so that a series is always printed the same way
+    labels.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let mut printed = String::new();
+    for (key, value) in labels {
+        printed.push(if printed.is_empty() { '{' } else { ',' });
+        printed.push_str(&sanitize_name(&key, false));
+        printed.push_str("=\"");
+        printed.push_str(&escape_label_value(&value));
+        printed.push('"');
+    }
+    if !printed.is_empty() {
+        printed.push('}');
+    }
+    printed
+}
+
+/// Replace any character that is not allowed in a metric name (or label name) by an underscore.
+/// Metric names match `[a-zA-Z_:][a-zA-Z0-9_:]*`, label names match `[a-zA-Z_][a-zA-Z0-9_]*`.
+pub(crate) fn sanitize_name(name: &str, allow_colon: bool) -> String {
+    let mut sanitized = String::with_capacity(name.len() + 1);
+    if name.starts_with(|c: char| c.is_ascii_digit()) || name.is_empty() {
+        sanitized.push('_');
+    }
+    for c in name.chars() {
+        if c.is_ascii_alphanumeric() || c == '_' || (allow_colon && c == ':') {
+            sanitized.push(c)
+        } else {
+            sanitized.push('_')
+        }
+    }
+    sanitized
+}
+
+/// Escape backslashes, double quotes and newlines.
+pub(crate) fn escape_label_value(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\n' => escaped.push_str("\\n"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Escape backslashes and newlines.
+pub(crate) fn escape_help(help: &str) -> String {
+    help.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
 /// Any remaining buffered data is flushed on Drop.
 impl Drop for PrometheusScope {
     fn drop(&mut self) {
@@ -212,3 +548,295 @@ impl Drop for PrometheusScope {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::label::test::TEST_SEQUENCE;
+    use crate::output::stand_in::stand_in;
+    use std::net::TcpListener;
+
+    fn buffered_scope() -> PrometheusScope {
+        // nothing listens there, values stay in the buffer
+        Prometheus::push_to("http://127.0.0.1:1/metrics/job/test")
+            .unwrap()
+            .buffered(crate::Buffering::Unlimited)
+            .named("app")
+            .metrics()
+    }
+
+    #[test]
+    fn print_exposition_format() {
+        let _lock = TEST_SEQUENCE.lock().expect("Test Sequence");
+        let metrics = buffered_scope();
+
+        metrics.marker("marker_a").write(3, labels!("path" => "/a"));
+        metrics.marker("marker_a").write(4, labels!("path" => "/b"));
+        metrics.level("level-b").write(-2, labels![]);
+        let timer = metrics.timer("timer_a");
+        timer.interval_us(2000);
+        timer.interval_us(3000);
+
+        assert_eq!(
+            "# HELP app_level_b app.level-b\n\
+             # TYPE app_level_b gauge\n\
+             app_level_b -2\n\
+             # HELP app_marker_a app.marker_a\n\
+             # TYPE app_marker_a counter\n\
+             app_marker_a{path=\"/a\"} 3\n\
+             app_marker_a{path=\"/b\"} 4\n\
+             # HELP app_timer_a app.timer_a\n\
+             # TYPE app_timer_a summary\n\
+             app_timer_a_sum 5\n\
+             app_timer_a_count 2\n",
+            render(&read_lock!(metrics.families))
+        );
+        write_lock!(metrics.families).clear();
+    }
+
+    #[test]
+    fn print_aggregated_histogram() {
+        let _lock = TEST_SEQUENCE.lock().expect("Test Sequence");
+        let metrics = buffered_scope();
+        let bucket = crate::AtomicBucket::new();
+        bucket.stats(crate::stats_buckets);
+
+        let histogram = bucket.histogram_buckets("size", &[10, 100]);
+        histogram.value(5);
+        histogram.value(50);
+        histogram.value(60);
+        // push fails, values stay in the buffer
+        assert!(bucket.flush_to(&metrics).is_err());
+        histogram.value(500);
+        assert!(bucket.flush_to(&metrics).is_err());
+
+        assert_eq!(
+            "# HELP app_size app.size\n\
+             # TYPE app_size histogram\n\
+             app_size_bucket{le=\"10\"} 1\n\
+             app_size_bucket{le=\"100\"} 3\n\
+             app_size_bucket{le=\"+Inf\"} 4\n\
+             app_size_sum 615\n\
+             app_size_count 4\n",
+            render(&read_lock!(metrics.families))
+        );
+        write_lock!(metrics.families).clear();
+    }
+
+    #[test]
+    fn print_proxied_histogram() {
+        let _lock = TEST_SEQUENCE.lock().expect("Test Sequence");
+        let metrics = buffered_scope();
+        let bucket = crate::AtomicBucket::new();
+        bucket.stats(crate::stats_buckets);
+
+        // the bounds go through the proxy to the bucket
+        let input = crate::Proxy::new();
+        input.target(bucket.clone());
+        let histogram = input.histogram_buckets("size", &[10, 100]);
+        histogram.value(5);
+        histogram.value(50);
+        // and the buckets through the proxy to the output
+        let output = crate::Proxy::new();
+        output.target(metrics.clone());
+        assert!(bucket.flush_to(&output).is_err());
+
+        assert_eq!(
+            "# HELP app_size app.size\n\
+             # TYPE app_size histogram\n\
+             app_size_bucket{le=\"10\"} 1\n\
+             app_size_bucket{le=\"100\"} 2\n\
+             app_size_bucket{le=\"+Inf\"} 2\n\
+             app_size_sum 55\n\
+             app_size_count 2\n",
+            render(&read_lock!(metrics.families))
+        );
+        write_lock!(metrics.families).clear();
+    }
+
+    #[test]
+    fn print_labeled_aggregates() {
+        let _lock = TEST_SEQUENCE.lock().expect("Test Sequence");
+        let metrics = buffered_scope();
+        let bucket = crate::AtomicBucket::new();
+        bucket.stats(crate::stats_buckets);
+        bucket.max_label_sets(10);
+
+        let counter = bucket.counter("hits");
+        counter.count(1);
+        counter.write(2, labels!("path" => "/a"));
+        counter.write(3, labels!("path" => "/a"));
+        let histogram = bucket.histogram_buckets("size", &[10]);
+        histogram.write(5, labels!("path" => "/b"));
+        // push fails, values stay in the buffer
+        assert!(bucket.flush_to(&metrics).is_err());
+
+        assert_eq!(
+            "# HELP app_hits app.hits\n\
+             # TYPE app_hits counter\n\
+             app_hits 1\n\
+             app_hits{path=\"/a\"} 5\n\
+             # HELP app_size app.size\n\
+             # TYPE app_size histogram\n\
+             app_size_bucket{path=\"/b\",le=\"10\"} 1\n\
+             app_size_bucket{path=\"/b\",le=\"+Inf\"} 1\n\
+             app_size_sum{path=\"/b\"} 5\n\
+             app_size_count{path=\"/b\"} 1\n",
+            render(&read_lock!(metrics.families))
+        );
+        write_lock!(metrics.families).clear();
+    }
+
+    #[test]
+    fn summary_keeps_le_label() {
+        let _lock = TEST_SEQUENCE.lock().expect("Test Sequence");
+        let metrics = buffered_scope();
+
+        let histogram = metrics.histogram("latency");
+        histogram.write(5, labels!("le" => "10"));
+        histogram.write(7, labels!("le" => "10"));
+
+        assert_eq!(
+            "# HELP app_latency app.latency\n\
+             # TYPE app_latency summary\n\
+             app_latency_sum{le=\"10\"} 12\n\
+             app_latency_count{le=\"10\"} 2\n",
+            render(&read_lock!(metrics.families))
+        );
+        write_lock!(metrics.families).clear();
+    }
+
+    #[test]
+    fn push_keeps_totals() {
+        let _lock = TEST_SEQUENCE.lock().expect("Test Sequence");
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/metrics/job/test", listener.local_addr().unwrap());
+        let metrics = Prometheus::push_to(&url)
+            .unwrap()
+            .buffered(crate::Buffering::Unlimited)
+            .metrics();
+        let counter = metrics.counter("hits");
+
+        for total in [2, 4] {
+            let server = stand_in(listener.try_clone().unwrap(), "200 OK");
+            counter.count(2);
+            metrics.flush().unwrap();
+            let (_, body) = server.join().unwrap();
+            assert!(
+                String::from_utf8(body)
+                    .unwrap()
+                    .ends_with(&format!("\nhits {total}\n"))
+            );
+        }
+
+        // nothing was written since the last push
+        metrics.flush().unwrap();
+    }
+
+    #[test]
+    fn push_rejected() {
+        let _lock = TEST_SEQUENCE.lock().expect("Test Sequence");
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/metrics/job/test", listener.local_addr().unwrap());
+        let server = stand_in(listener, "400 Bad Request");
+
+        let metrics = Prometheus::push_to(&url)
+            .unwrap()
+            .buffered(crate::Buffering::Unlimited)
+            .metrics();
+        metrics.gauge("g").value(1);
+        assert!(metrics.flush().is_err());
+        server.join().unwrap();
+        write_lock!(metrics.families).clear();
+    }
+
+    #[test]
+    fn push_full_buffer() {
+        let _lock = TEST_SEQUENCE.lock().expect("Test Sequence");
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/metrics/job/test", listener.local_addr().unwrap());
+        let server = stand_in(listener, "200 OK");
+
+        let metrics = Prometheus::push_to(&url)
+            .unwrap()
+            .buffered(crate::Buffering::BufferSize(2))
+            .metrics();
+        let counter = metrics.counter("hits");
+        counter.count(1);
+        counter.count(2);
+        assert_eq!(2, metrics.unsent.load(Acquire));
+        counter.count(3);
+
+        let (_, body) = server.join().unwrap();
+        assert!(String::from_utf8(body).unwrap().ends_with("\nhits 6\n"));
+        assert_eq!(0, metrics.unsent.load(Acquire));
+    }
+
+    #[test]
+    fn series_capped() {
+        let _lock = TEST_SEQUENCE.lock().expect("Test Sequence");
+        let metrics = buffered_scope();
+        let counter = metrics.counter("requests");
+        for i in 0..=MAX_PENDING_VALUES {
+            counter.write(1, labels!("id" => i.to_string()));
+        }
+        // known series are still updated
+        counter.write(1, labels!("id" => "0"));
+        metrics.gauge("other").value(1);
+
+        let mut families = write_lock!(metrics.families);
+        let series = &families["app_requests"].series;
+        assert_eq!(MAX_PENDING_VALUES, series.len());
+        assert!(matches!(series["{id=\"0\"}"], Sample::Value(2)));
+        assert!(!families.contains_key("app_other"));
+        families.clear();
+    }
+
+    #[test]
+    fn escape_labels() {
+        let _lock = TEST_SEQUENCE.lock().expect("Test Sequence");
+        let labels = labels!("1st.key" => "a\"b\\c\nd");
+        assert_eq!(
+            r#"{_1st_key="a\"b\\c\nd"}"#,
+            format_label_pairs(labels.into_map().into_iter().collect())
+        );
+    }
+
+    #[test]
+    fn sanitize_names() {
+        assert_eq!("a_b:c", sanitize_name("a.b:c", true));
+        assert_eq!("a_b_c", sanitize_name("a.b:c", false));
+        assert_eq!("_9lives", sanitize_name("9lives", true));
+    }
+
+    #[cfg(feature = "prometheus_serve")]
+    #[test]
+    fn serve_scraped_values() {
+        let _lock = TEST_SEQUENCE.lock().expect("Test Sequence");
+        let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/metrics", server.server_addr());
+        let metrics = Prometheus::serve_with(server)
+            .unwrap()
+            .named("app")
+            .metrics();
+
+        let counter = metrics.counter("counter_a");
+        counter.count(4);
+        counter.count(5);
+        metrics
+            .gauge("gauge_a")
+            .write(7, labels!("b" => "2", "a" => "1"));
+
+        let response = minreq::get(url).send().unwrap();
+        assert_eq!(200, response.status_code);
+        assert_eq!(
+            "# HELP app_counter_a app.counter_a\n\
+             # TYPE app_counter_a counter\n\
+             app_counter_a 9\n\
+             # HELP app_gauge_a app.gauge_a\n\
+             # TYPE app_gauge_a gauge\n\
+             app_gauge_a{a=\"1\",b=\"2\"} 7\n",
+            response.as_str().unwrap()
+        );
+    }
+}
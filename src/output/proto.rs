@@ -0,0 +1,39 @@
+//! Minimal protobuf wire format encoding.
+
+const VARINT: u64 = 0;
+const FIXED64: u64 = 1;
+const LENGTH_DELIMITED: u64 = 2;
+
+pub fn varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn key(buf: &mut Vec<u8>, field: u64, wire_type: u64) {
+    varint(buf, (field << 3) | wire_type)
+}
+
+pub fn varint_field(buf: &mut Vec<u8>, field: u64, value: u64) {
+    key(buf, field, VARINT);
+    varint(buf, value);
+}
+
+pub fn fixed64(buf: &mut Vec<u8>, field: u64, value: u64) {
+    key(buf, field, FIXED64);
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+pub fn message(buf: &mut Vec<u8>, field: u64, bytes: &[u8]) {
+    key(buf, field, LENGTH_DELIMITED);
+    varint(buf, bytes.len() as u64);
+    buf.extend_from_slice(bytes);
+}
+
+pub fn string(buf: &mut Vec<u8>, field: u64, value: &str) {
+    if !value.is_empty() {
+        message(buf, field, value.as_bytes())
+    }
+}
@@ -0,0 +1,488 @@
+//! Send metrics to a Prometheus remote-write receiver.
+
+use crate::attributes::{
+    Attributes, Buffered, MAX_PENDING_VALUES, MetricId, OnFlush, Prefixed, WithAttributes,
+};
+use crate::input::InputKind;
+use crate::input::{Input, InputMetric, InputScope};
+use crate::label::Labels;
+use crate::metrics;
+use crate::name::MetricName;
+use crate::output::prometheus::sanitize_name;
+use crate::output::proto;
+use crate::{CachedInput, QueuedInput};
+use crate::{Flush, MetricValue};
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[cfg(not(feature = "parking_lot"))]
+use std::sync::{RwLock, RwLockWriteGuard};
+
+#[cfg(feature = "parking_lot")]
+use parking_lot::{RwLock, RwLockWriteGuard};
+use std::io;
+
+/// Sorted label pairs identifying a series, including the `__name__` label.
+type SeriesLabels = Vec<(String, String)>;
+
+/// RemoteWrite Input POSTs snappy-compressed protobuf `WriteRequest`s
+/// to a Prometheus remote-write endpoint (Prometheus, Mimir, Thanos, VictoriaMetrics, etc.)
+/// Each series is sent as a single sample per flush, stamped with the flush time.
+#[derive(Clone, Debug)]
+pub struct RemoteWrite {
+    attributes: Attributes,
+    url: String,
+}
+
+impl RemoteWrite {
+    /// Send metrics to the remote-write endpoint at the URL provided.
+    /// For example `http://localhost:9009/api/v1/push`
+    pub fn push_to(url: &str) -> io::Result<RemoteWrite> {
+        debug!("Pushing to Prometheus remote-write {url:?}");
+
+        Ok(RemoteWrite {
+            attributes: Attributes::default(),
+            url: url.to_string(),
+        })
+    }
+}
+
+impl Input for RemoteWrite {
+    type SCOPE = RemoteWriteScope;
+
+    fn metrics(&self) -> Self::SCOPE {
+        RemoteWriteScope {
+            attributes: self.attributes.clone(),
+            batch: Arc::new(RwLock::new(Batch::default())),
+            url: self.url.clone(),
+        }
+    }
+}
+
+impl WithAttributes for RemoteWrite {
+    fn get_attributes(&self) -> &Attributes {
+        &self.attributes
+    }
+    fn mut_attributes(&mut self) -> &mut Attributes {
+        &mut self.attributes
+    }
+}
+
+impl Buffered for RemoteWrite {}
+
+impl QueuedInput for RemoteWrite {}
+impl CachedInput for RemoteWrite {}
+
+/// RemoteWrite Input
+#[derive(Debug, Clone)]
+pub struct RemoteWriteScope {
+    attributes: Attributes,
+    batch: Arc<RwLock<Batch>>,
+    url: String,
+}
+
+impl InputScope for RemoteWriteScope {
+    /// Define a metric of the specified type.
+    fn new_metric(&self, name: MetricName, kind: InputKind) -> InputMetric {
+        let family = sanitize_name(&self.prefix_prepend(name.clone()).join("_"), true);
+
+        let scale = match kind {
+            // timers are in µs, but we give Prometheus milliseconds
+            InputKind::Timer => 1000.0,
+            _ => 1.0,
+        };
+
+        let cloned = self.clone();
+        let metric = RemoteWriteMetric {
+            family,
+            metric_type: kind.into(),
+            scale,
+        };
+        let metric_id = MetricId::forge("remote_write", name);
+
+        InputMetric::new(metric_id, move |value, labels| {
+            cloned.print(&metric, value, labels);
+        })
+    }
+}
+
+impl Flush for RemoteWriteScope {
+    fn flush(&self) -> io::Result<()> {
+        self.notify_flush_listeners();
+        let batch = write_lock!(self.batch);
+        self.flush_inner(batch)
+    }
+}
+
+impl RemoteWriteScope {
+    fn print(&self, metric: &RemoteWriteMetric, value: MetricValue, labels: Labels) {
+        let labels: SeriesLabels = labels
+            .into_map()
+            .into_iter()
+            .map(|(key, value)| (sanitize_name(&key, false), value.to_string()))
+            .collect();
+        let value = value as f64 / metric.scale;
+
+        let mut batch = write_lock!(self.batch);
+        batch
+            .metadata
+            .entry(metric.family.clone())
+            .or_insert(metric.metric_type);
+        match metric.metric_type {
+            MetricType::Counter | MetricType::Level => {
+                batch.record(series(&metric.family, labels), value, true)
+            }
+            MetricType::Gauge => batch.record(series(&metric.family, labels), value, false),
+            MetricType::Summary => {
+                let sum = series(&format!("{}_sum", metric.family), labels.clone());
+                batch.record(sum, value, true);
+                let count = series(&format!("{}_count", metric.family), labels);
+                batch.record(count, 1.0, true);
+            }
+        }
+
+        if self.is_buffer_full(batch.series.len()) {
+            let _ = self.flush_inner(batch);
+            batch = write_lock!(self.batch);
+        }
+
+        if !self.is_buffered()
+            && let Err(e) = self.flush_inner(batch)
+        {
+            debug!("Could not send to Prometheus remote-write {e}")
+        }
+    }
+
+    fn flush_inner(&self, mut batch: RwLockWriteGuard<Batch>) -> io::Result<()> {
+        if batch.series.is_empty() {
+            return Ok(());
+        }
+
+        let now = match SystemTime::now().duration_since(UNIX_EPOCH) {
+            Ok(now) => now.as_millis() as i64,
+            Err(e) => {
+                warn!("Could not compute epoch timestamp. {e}");
+                return Err(io::Error::other(e));
+            }
+        };
+        // a sample stamped like the previous one would be rejected as a duplicate
+        let timestamp = now.max(batch.last_timestamp + 1);
+        let body = snap::raw::Encoder::new()
+            .compress_vec(&encode(&batch, timestamp))
+            .map_err(io::Error::other)?;
+        let body_len = body.len();
+
+        match minreq::post(self.url.as_str())
+            .with_header("Content-Type", "application/x-protobuf")
+            .with_header("Content-Encoding", "snappy")
+            .with_header("X-Prometheus-Remote-Write-Version", "0.1.0")
+            .with_body(body)
+            .send()
+        {
+            Ok(response) if (200..300).contains(&response.status_code) => {
+                metrics::REMOTE_WRITE_SENT_BYTES.count(body_len);
+                trace!("Sent {body_len} bytes to Prometheus remote-write");
+                batch.series.clear();
+                batch.last_timestamp = timestamp;
+                Ok(())
+            }
+            Ok(response) if response.status_code == 429 || response.status_code >= 500 => {
+                metrics::REMOTE_WRITE_SEND_ERR.mark();
+                debug!("Remote-write deferred: {}", response.status_code);
+                Err(io::Error::other(format!(
+                    "Remote-write failed with status {} {}",
+                    response.status_code, response.reason_phrase
+                )))
+            }
+            Ok(response) => {
+                // the receiver will never accept these samples, retrying would only block the next ones
+                metrics::REMOTE_WRITE_DROPPED.mark();
+                debug!("Remote-write rejected: {}", response.status_code);
+                batch.series.clear();
+                Err(io::Error::other(format!(
+                    "Remote-write failed with status {} {}",
+                    response.status_code, response.reason_phrase
+                )))
+            }
+            Err(e) => {
+                metrics::REMOTE_WRITE_SEND_ERR.mark();
+                debug!("Failed to send buffer to Prometheus remote-write: {e}");
+                Err(io::Error::other(e))
+            }
+        }
+    }
+}
+
+impl WithAttributes for RemoteWriteScope {
+    fn get_attributes(&self) -> &Attributes {
+        &self.attributes
+    }
+    fn mut_attributes(&mut self) -> &mut Attributes {
+        &mut self.attributes
+    }
+}
+
+impl Buffered for RemoteWriteScope {}
+
+/// Key of a remote-write metric.
+#[derive(Debug, Clone)]
+pub struct RemoteWriteMetric {
+    family: String,
+    metric_type: MetricType,
+    scale: f64,
+}
+
+/// Kinds of series sent for dipstick metrics.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MetricType {
+    /// Running total of counted values.
+    Counter,
+    /// Latest value.
+    Gauge,
+    /// Running total of adjustments, which may go down.
+    Level,
+    /// Running `_sum` and `_count` of observed values.
+    Summary,
+}
+
+impl From<InputKind> for MetricType {
+    fn from(kind: InputKind) -> Self {
+        match kind {
+            InputKind::Marker | InputKind::Counter => MetricType::Counter,
+            InputKind::Gauge | InputKind::Set => MetricType::Gauge,
+            InputKind::Level => MetricType::Level,
+            InputKind::Timer | InputKind::Histogram | InputKind::Distribution => {
+                MetricType::Summary
+            }
+        }
+    }
+}
+
+impl MetricType {
+    /// Value of the remote-write `MetricMetadata.MetricType` enum.
+    fn proto_value(self) -> u64 {
+        match self {
+            MetricType::Counter => 1,
+            MetricType::Gauge | MetricType::Level => 2,
+            MetricType::Summary => 5,
+        }
+    }
+}
+
+/// Latest value of the series written since the last successful write.
+/// Running totals are kept across writes so that counters never reset.
+#[derive(Debug, Default)]
+struct Batch {
+    series: BTreeMap<SeriesLabels, f64>,
+    totals: HashMap<SeriesLabels, f64>,
+    metadata: BTreeMap<String, MetricType>,
+    last_timestamp: i64,
+}
+
+impl Batch {
+    /// Set the sample of the series, adding the value to its running total if cumulative.
+    /// New series are dropped once `MAX_PENDING_VALUES` series are pending.
+    fn record(&mut self, series: SeriesLabels, value: f64, cumulative: bool) {
+        if self.series.len() >= MAX_PENDING_VALUES && !self.series.contains_key(&series) {
+            metrics::REMOTE_WRITE_PENDING_DROPPED.mark();
+            debug!("Remote-write pending series exceed {MAX_PENDING_VALUES}, dropping {series:?}");
+            return;
+        }
+        let value = if cumulative {
+            let total = self.totals.entry(series.clone()).or_default();
+            *total += value;
+            *total
+        } else {
+            value
+        };
+        self.series.insert(series, value);
+    }
+}
+
+/// Add the `__name__` label and sort the labels as required by the protocol.
+fn series(name: &str, mut labels: SeriesLabels) -> SeriesLabels {
+    labels.push(("__name__".to_string(), name.to_string()));
+    labels.sort();
+    labels
+}
+
+/// Encode the batch as a remote-write protobuf `WriteRequest` with samples stamped at the timestamp.
+/// Field numbers are those of `prometheus/prompb/remote.proto` and `types.proto`.
+fn encode(batch: &Batch, timestamp: i64) -> Vec<u8> {
+    let mut request = Vec::new();
+    for (labels, value) in &batch.series {
+        // TimeSeries
+        let mut series = Vec::new();
+        for (name, value) in labels {
+            let mut label = Vec::new();
+            proto::string(&mut label, 1, name);
+            proto::string(&mut label, 2, value);
+            proto::message(&mut series, 1, &label);
+        }
+        let mut sample = Vec::new();
+        proto::fixed64(&mut sample, 1, value.to_bits());
+        proto::varint_field(&mut sample, 2, timestamp as u64);
+        proto::message(&mut series, 2, &sample);
+        proto::message(&mut request, 1, &series);
+    }
+    for (family, metric_type) in &batch.metadata {
+        // MetricMetadata
+        let mut metadata = Vec::new();
+        proto::varint_field(&mut metadata, 1, metric_type.proto_value());
+        proto::string(&mut metadata, 2, family);
+        proto::message(&mut request, 3, &metadata);
+    }
+    request
+}
+
+/// Any remaining buffered data is flushed on Drop.
+impl Drop for RemoteWriteScope {
+    fn drop(&mut self) {
+        if let Err(err) = self.flush() {
+            warn!("Could not flush Prometheus remote-write metrics upon Drop: {err}")
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::label::test::TEST_SEQUENCE;
+    use crate::output::stand_in::stand_in;
+    use std::net::TcpListener;
+
+    #[test]
+    fn write_request() {
+        let _lock = TEST_SEQUENCE.lock().expect("Test Sequence");
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/api/v1/push", listener.local_addr().unwrap());
+        let server = stand_in(listener, "204 No Content");
+
+        let metrics = RemoteWrite::push_to(&url)
+            .unwrap()
+            .buffered(crate::Buffering::Unlimited)
+            .named("app")
+            .metrics();
+        let counter = metrics.counter("counter.a");
+        counter.write(3, labels!("path" => "/a"));
+        counter.write(4, labels!("path" => "/a"));
+        metrics.flush().unwrap();
+
+        let (head, body) = server.join().unwrap();
+        assert!(head.starts_with("POST /api/v1/push HTTP/1.1\r\n"));
+        assert!(head.contains("Content-Encoding: snappy"));
+        assert!(head.contains("X-Prometheus-Remote-Write-Version: 0.1.0"));
+        let request = snap::raw::Decoder::new().decompress_vec(&body).unwrap();
+
+        let mut series = Vec::new();
+        for (name, value) in [("__name__", "app_counter_a"), ("path", "/a")] {
+            let mut label = Vec::new();
+            proto::string(&mut label, 1, name);
+            proto::string(&mut label, 2, value);
+            proto::message(&mut series, 1, &label);
+        }
+        // a single sample holds the running total
+        let mut sample = Vec::new();
+        proto::fixed64(&mut sample, 1, 7.0f64.to_bits());
+        assert!(request.starts_with(&[1 << 3 | 2]));
+        assert!(
+            request
+                .windows(series.len())
+                .any(|w| w == series.as_slice())
+        );
+        assert!(
+            request
+                .windows(sample.len())
+                .any(|w| w == sample.as_slice())
+        );
+        let mut first = Vec::new();
+        proto::fixed64(&mut first, 1, 3.0f64.to_bits());
+        assert!(!request.windows(first.len()).any(|w| w == first.as_slice()));
+
+        let mut metadata = Vec::new();
+        proto::varint_field(&mut metadata, 1, 1);
+        proto::string(&mut metadata, 2, "app_counter_a");
+        let mut expected = Vec::new();
+        proto::message(&mut expected, 3, &metadata);
+        assert!(request.ends_with(&expected));
+    }
+
+    #[test]
+    fn summary_series() {
+        let mut batch = Batch::default();
+        for value in [2.0, 5.0] {
+            batch.record(series("t_sum", vec![]), value, true);
+            batch.record(series("t_count", vec![]), 1.0, true);
+        }
+        batch.record(series("g", vec![]), 9.0, false);
+        batch.record(series("g", vec![]), 8.0, false);
+
+        let sample = |batch: &Batch, name: &str| batch.series[&series(name, vec![])];
+        assert_eq!(7.0, sample(&batch, "t_sum"));
+        assert_eq!(2.0, sample(&batch, "t_count"));
+        assert_eq!(8.0, sample(&batch, "g"));
+        assert_eq!(3, batch.series.len());
+
+        // totals carry over to the next write
+        batch.series.clear();
+        batch.record(series("t_count", vec![]), 1.0, true);
+        assert_eq!(3.0, sample(&batch, "t_count"));
+    }
+
+    #[test]
+    fn series_capped() {
+        let mut batch = Batch::default();
+        for i in 0..=MAX_PENDING_VALUES {
+            batch.record(series("g", vec![("id".into(), i.to_string())]), 1.0, false);
+        }
+        assert_eq!(MAX_PENDING_VALUES, batch.series.len());
+
+        // pending series are still updated
+        let first = series("g", vec![("id".into(), "0".into())]);
+        batch.record(first.clone(), 2.0, false);
+        assert_eq!(2.0, batch.series[&first]);
+    }
+
+    #[test]
+    fn write_rejected() {
+        let _lock = TEST_SEQUENCE.lock().expect("Test Sequence");
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/api/v1/push", listener.local_addr().unwrap());
+        let server = stand_in(listener, "400 Bad Request");
+
+        let metrics = RemoteWrite::push_to(&url)
+            .unwrap()
+            .buffered(crate::Buffering::Unlimited)
+            .metrics();
+        let gauge = metrics.gauge("g");
+        gauge.value(1);
+        assert!(metrics.flush().is_err());
+        server.join().unwrap();
+
+        // the samples would never be accepted, they are dropped
+        assert!(read_lock!(metrics.batch).series.is_empty());
+    }
+
+    #[test]
+    fn write_unavailable() {
+        let _lock = TEST_SEQUENCE.lock().expect("Test Sequence");
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/api/v1/push", listener.local_addr().unwrap());
+        let server = stand_in(listener, "503 Service Unavailable");
+
+        let metrics = RemoteWrite::push_to(&url)
+            .unwrap()
+            .buffered(crate::Buffering::Unlimited)
+            .metrics();
+        let gauge = metrics.gauge("g");
+        gauge.value(1);
+        assert!(metrics.flush().is_err());
+        server.join().unwrap();
+
+        // samples are kept for the next write
+        assert_eq!(1, read_lock!(metrics.batch).series.len());
+        write_lock!(metrics.batch).series.clear();
+    }
+}
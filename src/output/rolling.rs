@@ -0,0 +1,306 @@
+//! A file writer that rotates its file by size or age.
+
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+/// How often the file path is checked for external rotation.
+const EXTERNAL_CHECK_PERIOD: Duration = Duration::from_secs(1);
+
+/// A handle requesting a `RollingFile` to reopen its path before the next write.
+/// Reopening is a single atomic store, the handle can be used from a signal handler (e.g. on SIGHUP).
+#[derive(Debug, Clone)]
+pub struct ReopenHandle(Arc<AtomicBool>);
+
+impl ReopenHandle {
+    /// Reopen the file before the next write.
+    pub fn reopen(&self) {
+        self.0.store(true, Ordering::SeqCst)
+    }
+}
+
+/// Appends to a file, rolling it over to numbered archives when it grows too big or too old.
+/// The current file is renamed `path.1`, previous archives are shifted (`path.1` to `path.2`, etc.)
+/// and the oldest archive is deleted.
+///
+/// If the file is moved or deleted by an external tool (e.g. logrotate), the path is reopened.
+/// Use with `Stream::write_to(RollingFile::new(path)?.max_size(1_000_000))`.
+pub struct RollingFile {
+    path: PathBuf,
+    max_size: Option<u64>,
+    period: Option<Duration>,
+    archives: usize,
+    #[cfg(feature = "gzip")]
+    compress: bool,
+    file: File,
+    size: u64,
+    next_roll: Option<Instant>,
+    next_check: Instant,
+    reopen: Arc<AtomicBool>,
+}
+
+impl fmt::Debug for RollingFile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "RollingFile({:?}, {} bytes)", self.path, self.size)
+    }
+}
+
+impl RollingFile {
+    /// Append to the file at the path provided, creating it if needed.
+    /// By default, the file is never rolled and five archives are kept.
+    pub fn new<P: AsRef<Path>>(path: P) -> io::Result<RollingFile> {
+        let path = path.as_ref().to_path_buf();
+        let file = open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(RollingFile {
+            path,
+            max_size: None,
+            period: None,
+            archives: 5,
+            #[cfg(feature = "gzip")]
+            compress: false,
+            file,
+            size,
+            next_roll: None,
+            next_check: Instant::now() + EXTERNAL_CHECK_PERIOD,
+            reopen: Arc::new(AtomicBool::new(false)),
+        })
+    }
+
+    /// Roll the file before it grows beyond the specified size in bytes.
+    pub fn max_size(mut self, bytes: u64) -> Self {
+        self.max_size = Some(bytes);
+        self
+    }
+
+    /// Roll the file after it has been written to for the specified period.
+    pub fn period(mut self, period: Duration) -> Self {
+        self.period = Some(period);
+        self.next_roll = Some(Instant::now() + period);
+        self
+    }
+
+    /// Keep the specified number of archives, deleting older ones.
+    /// With zero archives, the file is simply truncated when rolled.
+    pub fn archives(mut self, count: usize) -> Self {
+        self.archives = count;
+        self
+    }
+
+    /// Compress archives with gzip, adding a `.gz` extension to their name.
+    #[cfg(feature = "gzip")]
+    pub fn compress(mut self, compress: bool) -> Self {
+        self.compress = compress;
+        self
+    }
+
+    /// Returns a handle used to make the file reopen its path,
+    /// after it was moved by an external rotation tool.
+    pub fn reopen_handle(&self) -> ReopenHandle {
+        ReopenHandle(self.reopen.clone())
+    }
+
+    fn archive_path(&self, index: usize) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{index}"));
+        #[cfg(feature = "gzip")]
+        if self.compress {
+            name.push(".gz");
+        }
+        PathBuf::from(name)
+    }
+
+    /// Reopen the path if requested or if the file was moved away.
+    fn check_external(&mut self) -> io::Result<()> {
+        let now = Instant::now();
+        let mut reopen = self.reopen.swap(false, Ordering::SeqCst);
+        if !reopen && now >= self.next_check {
+            self.next_check = now + EXTERNAL_CHECK_PERIOD;
+            reopen = !same_file(&self.file, &self.path);
+        }
+        if reopen {
+            debug!("Reopening metrics file {:?}", self.path);
+            self.file = open(&self.path)?;
+            self.size = self.file.metadata()?.len();
+        }
+        Ok(())
+    }
+
+    fn should_roll(&self, incoming: usize) -> bool {
+        if self.size == 0 {
+            return false;
+        }
+        let too_big = self
+            .max_size
+            .is_some_and(|max| self.size + incoming as u64 > max);
+        let too_old = self.next_roll.is_some_and(|next| Instant::now() >= next);
+        too_big || too_old
+    }
+
+    fn roll(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        if self.archives == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            let _ = fs::remove_file(self.archive_path(self.archives));
+            for index in (1..self.archives).rev() {
+                let from = self.archive_path(index);
+                if from.exists() {
+                    fs::rename(&from, self.archive_path(index + 1))?;
+                }
+            }
+            self.archive(&self.archive_path(1))?;
+        }
+        self.file = open(&self.path)?;
+        self.size = 0;
+        if let Some(period) = self.period {
+            self.next_roll = Some(Instant::now() + period);
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "gzip")]
+    fn archive(&self, archive: &Path) -> io::Result<()> {
+        if !self.compress {
+            return fs::rename(&self.path, archive);
+        }
+        let mut encoder =
+            flate2::write::GzEncoder::new(File::create(archive)?, flate2::Compression::default());
+        io::copy(&mut File::open(&self.path)?, &mut encoder)?;
+        encoder.finish()?;
+        fs::remove_file(&self.path)
+    }
+
+    #[cfg(not(feature = "gzip"))]
+    fn archive(&self, archive: &Path) -> io::Result<()> {
+        fs::rename(&self.path, archive)
+    }
+}
+
+impl Write for RollingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.check_external()?;
+        if self.should_roll(buf.len()) {
+            self.roll()?;
+        }
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+fn open(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+/// Returns false if the path does not exist anymore or now points to another file.
+#[cfg(unix)]
+fn same_file(file: &File, path: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    match (file.metadata(), fs::metadata(path)) {
+        (Ok(open), Ok(current)) => open.dev() == current.dev() && open.ino() == current.ino(),
+        _ => false,
+    }
+}
+
+/// Returns false if the path does not exist anymore.
+#[cfg(not(unix))]
+fn same_file(_file: &File, path: &Path) -> bool {
+    path.exists()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::thread;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("dipstick-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn read(path: &Path) -> String {
+        fs::read_to_string(path).unwrap()
+    }
+
+    #[test]
+    fn roll_by_size() {
+        let dir = test_dir("size");
+        let path = dir.join("metrics.log");
+        let mut file = RollingFile::new(&path).unwrap().max_size(10).archives(2);
+        for line in [
+            "aaaa\n", "bbbb\n", "cccc\n", "dddd\n", "eeee\n", "ffff\n", "gggg\n",
+        ] {
+            file.write_all(line.as_bytes()).unwrap();
+        }
+
+        assert_eq!("gggg\n", read(&path));
+        assert_eq!("eeee\nffff\n", read(&dir.join("metrics.log.1")));
+        assert_eq!("cccc\ndddd\n", read(&dir.join("metrics.log.2")));
+        assert!(!dir.join("metrics.log.3").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn roll_by_period() {
+        let dir = test_dir("period");
+        let path = dir.join("metrics.log");
+        let mut file = RollingFile::new(&path)
+            .unwrap()
+            .period(Duration::from_millis(20));
+        file.write_all(b"old\n").unwrap();
+        thread::sleep(Duration::from_millis(30));
+        file.write_all(b"new\n").unwrap();
+
+        assert_eq!("new\n", read(&path));
+        assert_eq!("old\n", read(&dir.join("metrics.log.1")));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn reopen_after_external_rotation() {
+        let dir = test_dir("reopen");
+        let path = dir.join("metrics.log");
+        let mut file = RollingFile::new(&path).unwrap();
+        let handle = file.reopen_handle();
+        file.write_all(b"before\n").unwrap();
+
+        fs::rename(&path, dir.join("rotated.log")).unwrap();
+        handle.reopen();
+        file.write_all(b"after\n").unwrap();
+
+        assert_eq!("after\n", read(&path));
+        assert_eq!("before\n", read(&dir.join("rotated.log")));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn compress_archives() {
+        use std::io::Read;
+
+        let dir = test_dir("gzip");
+        let path = dir.join("metrics.log");
+        let mut file = RollingFile::new(&path).unwrap().max_size(5).compress(true);
+        file.write_all(b"aaaa\n").unwrap();
+        file.write_all(b"bbbb\n").unwrap();
+
+        let mut archived = String::new();
+        flate2::read::GzDecoder::new(File::open(dir.join("metrics.log.1.gz")).unwrap())
+            .read_to_string(&mut archived)
+            .unwrap();
+        assert_eq!("aaaa\n", archived);
+        assert_eq!("bbbb\n", read(&path));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,10 +1,14 @@
-//! A TCP Socket wrapper that reconnects automatically.
+//! A TCP Socket wrapper that reconnects automatically, and a datagram socket over UDP or Unix sockets.
 
 use std::fmt;
 use std::io;
 use std::io::Write;
-use std::net::TcpStream;
 use std::net::{SocketAddr, ToSocketAddrs};
+use std::net::{TcpStream, UdpSocket};
+#[cfg(unix)]
+use std::os::unix::net::UnixDatagram;
+#[cfg(unix)]
+use std::path::Path;
 use std::time::{Duration, Instant};
 
 const MIN_RECONNECT_DELAY_MS: u64 = 50;
@@ -98,6 +102,128 @@ impl RetrySocket {
     }
 }
 
+/// Use a safe maximum size for UDP to prevent fragmentation.
+pub const MAX_UDP_PAYLOAD: usize = 576;
+
+/// Local datagrams are not fragmented, use the default DogStatsD socket buffer size.
+pub const MAX_UNIX_PAYLOAD: usize = 8192;
+
+/// A connected datagram socket.
+#[derive(Debug)]
+pub enum DatagramSocket {
+    /// A UDP socket bound to any local port.
+    Udp(UdpSocket),
+    /// An unbound Unix domain datagram socket.
+    #[cfg(unix)]
+    Unix(UnixDatagram),
+}
+
+impl DatagramSocket {
+    /// Open a non-blocking UDP socket sending to the address provided.
+    pub fn udp<A: ToSocketAddrs>(address: A) -> io::Result<Self> {
+        let socket = UdpSocket::bind("0.0.0.0:0")?;
+        socket.set_nonblocking(true)?;
+        socket.connect(address)?;
+        Ok(DatagramSocket::Udp(socket))
+    }
+
+    /// Open a non-blocking Unix datagram socket sending to the path provided.
+    #[cfg(unix)]
+    pub fn unix<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let socket = UnixDatagram::unbound()?;
+        socket.connect(path)?;
+        socket.set_nonblocking(true)?;
+        Ok(DatagramSocket::Unix(socket))
+    }
+
+    /// The largest payload that should be sent in a single datagram.
+    pub fn max_payload(&self) -> usize {
+        match self {
+            DatagramSocket::Udp(_) => MAX_UDP_PAYLOAD,
+            #[cfg(unix)]
+            DatagramSocket::Unix(_) => MAX_UNIX_PAYLOAD,
+        }
+    }
+
+    /// Send a single datagram.
+    pub fn send(&self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            DatagramSocket::Udp(socket) => socket.send(buf),
+            #[cfg(unix)]
+            DatagramSocket::Unix(socket) => socket.send(buf),
+        }
+    }
+
+    /// Send each packet as a datagram, returning the total number of bytes sent.
+    /// Sending stops at the first error.
+    #[cfg(not(target_os = "linux"))]
+    pub fn send_batch<P: AsRef<[u8]>>(&self, packets: &[P]) -> io::Result<usize> {
+        let mut sent = 0;
+        for packet in packets {
+            sent += self.send(packet.as_ref())?;
+        }
+        Ok(sent)
+    }
+
+    /// Send each packet as a datagram, returning the total number of bytes sent.
+    /// Packets are handed to the kernel in as few `sendmmsg` calls as possible.
+    /// Sending stops at the first error.
+    #[cfg(target_os = "linux")]
+    pub fn send_batch<P: AsRef<[u8]>>(&self, packets: &[P]) -> io::Result<usize> {
+        use std::os::fd::AsRawFd;
+
+        let fd = match self {
+            DatagramSocket::Udp(socket) => socket.as_raw_fd(),
+            DatagramSocket::Unix(socket) => socket.as_raw_fd(),
+        };
+        let mut iovecs: Vec<libc::iovec> = packets
+            .iter()
+            .map(|packet| libc::iovec {
+                iov_base: packet.as_ref().as_ptr() as *mut libc::c_void,
+                iov_len: packet.as_ref().len(),
+            })
+            .collect();
+        let mut messages: Vec<libc::mmsghdr> = iovecs
+            .iter_mut()
+            .map(|iovec| {
+                // SAFETY: mmsghdr is a plain C struct for which all zeroes is a valid value
+                let mut message: libc::mmsghdr = unsafe { std::mem::zeroed() };
+                message.msg_hdr.msg_iov = iovec;
+                message.msg_hdr.msg_iovlen = 1;
+                message
+            })
+            .collect();
+
+        let mut sent = 0;
+        let mut offset = 0;
+        while offset < messages.len() {
+            let remaining = &mut messages[offset..];
+            // SAFETY: every message points to a single iovec, each referencing a live packet
+            let count = unsafe {
+                libc::sendmmsg(
+                    fd,
+                    remaining.as_mut_ptr(),
+                    remaining.len() as libc::c_uint,
+                    0,
+                )
+            };
+            if count < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            if count == 0 {
+                return Err(io::Error::from(io::ErrorKind::WouldBlock));
+            }
+            let count = count as usize;
+            sent += remaining[..count]
+                .iter()
+                .map(|message| message.msg_len as usize)
+                .sum::<usize>();
+            offset += count;
+        }
+        Ok(sent)
+    }
+}
+
 impl Write for RetrySocket {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
         self.with_socket(|sock| sock.write(buf))
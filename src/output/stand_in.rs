@@ -0,0 +1,39 @@
+//! Stand-in HTTP server for testing outputs pushing over HTTP.
+
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::thread;
+use std::time::Duration;
+
+/// Accept a single HTTP request, answer it with the status provided and return its head and body.
+/// Panics if the connection is closed or stalls before the whole request is received.
+pub fn stand_in(
+    listener: TcpListener,
+    status: &'static str,
+) -> thread::JoinHandle<(String, Vec<u8>)> {
+    thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut request = Vec::new();
+        let mut buf = [0; 4096];
+        loop {
+            let len = stream.read(&mut buf).unwrap();
+            assert_ne!(0, len, "connection closed before the end of the request");
+            request.extend_from_slice(&buf[..len]);
+            if let Some(head_len) = request.windows(4).position(|w| w == b"\r\n\r\n") {
+                let head = String::from_utf8_lossy(&request[..head_len]).to_string();
+                let content_length: usize = head
+                    .lines()
+                    .find_map(|line| line.strip_prefix("Content-Length: "))
+                    .map(|len| len.parse().unwrap())
+                    .unwrap_or_default();
+                if request.len() >= head_len + 4 + content_length {
+                    let _ = write!(stream, "HTTP/1.1 {status}\r\nContent-Length: 0\r\n\r\n");
+                    return (head, request[head_len + 4..].to_vec());
+                }
+            }
+        }
+    })
+}
@@ -1,19 +1,25 @@
 //! Send metrics to a statsd server.
 
 use crate::attributes::{
-    Attributes, Buffered, MetricId, OnFlush, Prefixed, Sampled, Sampling, WithAttributes,
+    Attributes, Buffered, MAX_PENDING_BYTES, MetricId, OnFlush, Prefixed, Sampled, Sampling,
+    WithAttributes,
 };
 use crate::input::InputKind;
 use crate::input::{Input, InputMetric, InputScope};
+use crate::label::Labels;
 use crate::metrics;
 use crate::name::MetricName;
+use crate::output::datagram::{DatagramBatch, Push};
+use crate::output::socket::{DatagramSocket, RetrySocket};
 use crate::pcg32;
 use crate::{CachedInput, QueuedInput};
 use crate::{Flush, MetricValue};
-use std::fmt::Write;
+use std::fmt::{Debug, Write as _};
+use std::io::Write;
 
 use std::net::ToSocketAddrs;
-use std::net::UdpSocket;
+#[cfg(unix)]
+use std::path::Path;
 use std::sync::Arc;
 
 #[cfg(not(feature = "parking_lot"))]
@@ -23,30 +29,112 @@ use std::sync::{RwLock, RwLockWriteGuard};
 use parking_lot::{RwLock, RwLockWriteGuard};
 use std::io;
 
-/// Use a safe maximum size for UDP to prevent fragmentation.
-// TODO make configurable?
-const MAX_UDP_PAYLOAD: usize = 576;
+/// Variants of the statsd protocol.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub enum StatsdDialect {
+    /// The original statsd protocol, metric labels are not sent.
+    #[default]
+    Etsy,
+    /// Datadog's statsd extensions, metric labels are sent as `|#key:value,...` tags.
+    DogStatsd,
+}
+
+/// Status of a DogStatsD service check.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ServiceCheckStatus {
+    /// The service is healthy.
+    Ok = 0,
+    /// The service is degraded.
+    Warning = 1,
+    /// The service is failing.
+    Critical = 2,
+    /// The service state could not be determined.
+    Unknown = 3,
+}
+
+/// Where the entries are sent.
+#[derive(Clone, Debug)]
+enum StatsdTarget {
+    Datagram(Arc<DatagramSocket>),
+    Tcp(Arc<RwLock<RetrySocket>>),
+}
+
+/// Entries waiting to be sent.
+#[derive(Debug)]
+enum StatsdBuffer {
+    /// Entries packed into datagrams.
+    Datagram(DatagramBatch),
+    /// Newline terminated entries.
+    Tcp(Arc<RwLock<RetrySocket>>, String),
+}
 
-/// Statsd Input holds a datagram (UDP) socket to a statsd server.
+/// Statsd Input holds a datagram (UDP or Unix) socket or a TCP connection to a statsd server.
 /// The socket is shared between scopes opened from the Input.
 #[derive(Clone, Debug)]
 pub struct Statsd {
     attributes: Attributes,
-    socket: Arc<UdpSocket>,
+    target: StatsdTarget,
+    max_payload: usize,
+    dialect: StatsdDialect,
 }
 
 impl Statsd {
     /// Send metrics to a statsd server at the address and port provided.
     pub fn send_to<ADDR: ToSocketAddrs>(address: ADDR) -> io::Result<Statsd> {
-        let socket = Arc::new(UdpSocket::bind("0.0.0.0:0")?);
-        socket.set_nonblocking(true)?;
-        socket.connect(address)?;
+        let socket = DatagramSocket::udp(address)?;
+
+        Ok(Statsd {
+            attributes: Attributes::default(),
+            max_payload: socket.max_payload(),
+            target: StatsdTarget::Datagram(Arc::new(socket)),
+            dialect: StatsdDialect::default(),
+        })
+    }
+
+    /// Send metrics over TCP to a statsd server at the address and port provided.
+    /// Entries are newline terminated. The connection is reestablished if it drops.
+    pub fn send_to_tcp<A: ToSocketAddrs + Debug + Clone>(address: A) -> io::Result<Statsd> {
+        debug!("Connecting to statsd {address:?}");
+        let socket = Arc::new(RwLock::new(RetrySocket::new(address)?));
+
+        Ok(Statsd {
+            attributes: Attributes::default(),
+            max_payload: 0,
+            target: StatsdTarget::Tcp(socket),
+            dialect: StatsdDialect::default(),
+        })
+    }
+
+    /// Send metrics to a statsd agent listening on the Unix datagram socket at the path provided.
+    /// Unix sockets do not drop packets and allow larger payloads than UDP.
+    #[cfg(unix)]
+    pub fn send_to_unix<P: AsRef<Path>>(path: P) -> io::Result<Statsd> {
+        let socket = DatagramSocket::unix(path)?;
 
         Ok(Statsd {
             attributes: Attributes::default(),
-            socket,
+            max_payload: socket.max_payload(),
+            target: StatsdTarget::Datagram(Arc::new(socket)),
+            dialect: StatsdDialect::default(),
         })
     }
+
+    /// Use the specified protocol variant for scopes opened afterwards.
+    pub fn dialect(&self, dialect: StatsdDialect) -> Self {
+        let mut cloned = self.clone();
+        cloned.dialect = dialect;
+        cloned
+    }
+
+    /// Send datagrams of up to the specified size from scopes opened afterwards.
+    /// Defaults to 576 bytes for UDP, which never gets fragmented.
+    /// Networks with a known MTU can use larger payloads, e.g. 1432 bytes for Ethernet
+    /// or 8932 bytes for jumbo frames. Has no effect over TCP.
+    pub fn max_payload(&self, bytes: usize) -> Self {
+        let mut cloned = self.clone();
+        cloned.max_payload = bytes;
+        cloned
+    }
 }
 
 impl Buffered for Statsd {}
@@ -63,8 +151,15 @@ impl Input for Statsd {
     fn metrics(&self) -> Self::SCOPE {
         StatsdScope {
             attributes: self.attributes.clone(),
-            buffer: Arc::new(RwLock::new(String::with_capacity(MAX_UDP_PAYLOAD))),
-            socket: self.socket.clone(),
+            buffer: Arc::new(RwLock::new(match &self.target {
+                StatsdTarget::Datagram(socket) => StatsdBuffer::Datagram(DatagramBatch::new(
+                    socket.clone(),
+                    self.max_payload,
+                    "\n",
+                )),
+                StatsdTarget::Tcp(socket) => StatsdBuffer::Tcp(socket.clone(), String::new()),
+            })),
+            dialect: self.dialect,
         }
     }
 }
@@ -82,8 +177,8 @@ impl WithAttributes for Statsd {
 #[derive(Debug, Clone)]
 pub struct StatsdScope {
     attributes: Attributes,
-    buffer: Arc<RwLock<String>>,
-    socket: Arc<UdpSocket>,
+    buffer: Arc<RwLock<StatsdBuffer>>,
+    dialect: StatsdDialect,
 }
 
 impl Sampled for StatsdScope {}
@@ -96,10 +191,15 @@ impl InputScope for StatsdScope {
 
         let mut suffix = String::with_capacity(16);
         suffix.push('|');
-        suffix.push_str(match kind {
-            InputKind::Marker | InputKind::Counter => "c",
-            InputKind::Gauge | InputKind::Level => "g",
-            InputKind::Timer => "ms",
+        suffix.push_str(match (kind, self.dialect) {
+            (InputKind::Marker | InputKind::Counter, _) => "c",
+            (InputKind::Gauge | InputKind::Level, _) => "g",
+            (InputKind::Timer, _) => "ms",
+            (InputKind::Set, _) => "s",
+            (InputKind::Histogram, StatsdDialect::DogStatsd) => "h",
+            (InputKind::Distribution, StatsdDialect::DogStatsd) => "d",
+            // plain statsd has no histograms, timers are aggregated the same way
+            (InputKind::Histogram | InputKind::Distribution, StatsdDialect::Etsy) => "ms",
         });
 
         let scale = match kind {
@@ -112,7 +212,7 @@ impl InputScope for StatsdScope {
         let metric_id = MetricId::forge("statsd", name);
 
         if let Sampling::Random(float_rate) = self.get_sampling() {
-            let _ = writeln!(suffix, "|@{float_rate}");
+            let _ = write!(suffix, "|@{float_rate}");
             let int_sampling_rate = pcg32::to_int_rate(float_rate);
             let metric = StatsdMetric {
                 prefix,
@@ -120,20 +220,19 @@ impl InputScope for StatsdScope {
                 scale,
             };
 
-            InputMetric::new(metric_id, move |value, _labels| {
+            InputMetric::new(metric_id, move |value, labels| {
                 if pcg32::accept_sample(int_sampling_rate) {
-                    cloned.print(&metric, value)
+                    cloned.print(&metric, value, labels)
                 }
             })
         } else {
-            suffix.push('\n');
             let metric = StatsdMetric {
                 prefix,
                 suffix,
                 scale,
             };
-            InputMetric::new(metric_id, move |value, _labels| {
-                cloned.print(&metric, value)
+            InputMetric::new(metric_id, move |value, labels| {
+                cloned.print(&metric, value, labels)
             })
         }
     }
@@ -142,60 +241,119 @@ impl InputScope for StatsdScope {
 impl Flush for StatsdScope {
     fn flush(&self) -> io::Result<()> {
         self.notify_flush_listeners();
-        let buf = write_lock!(self.buffer);
-        self.flush_inner(buf)
+        let buffer = write_lock!(self.buffer);
+        self.flush_inner(buffer)
     }
 }
 
 impl StatsdScope {
-    fn print(&self, metric: &StatsdMetric, value: MetricValue) {
-        let scaled_value = value / metric.scale;
-        let value_str = scaled_value.to_string();
-        let entry_len = metric.prefix.len() + value_str.len() + metric.suffix.len();
-
-        let mut buffer = write_lock!(self.buffer);
-        if entry_len > buffer.capacity() {
-            // TODO report entry too big to fit in buffer (!?)
+    /// Send a DogStatsD event with the specified title and text.
+    /// The event is tagged with the labels provided.
+    /// Events are not supported by the `Etsy` dialect and are dropped.
+    pub fn event(&self, title: &str, text: &str, labels: Labels) {
+        if self.dialect != StatsdDialect::DogStatsd {
+            debug!("Statsd events require the DogStatsd dialect, dropping event {title}");
             return;
         }
+        let title = self.prefix_prepend(title).join(".");
+        let text = text.replace('\n', "\\n");
+        let mut entry = format!("_e{{{},{}}}:{title}|{text}", title.len(), text.len());
+        entry.push_str(&format_tags(labels));
+        self.push_entry(&[&entry])
+    }
 
-        let available = buffer.capacity() - buffer.len();
-        if entry_len + 1 > available {
-            // buffer is nearly full, make room
-            let _ = self.flush_inner(buffer);
-            buffer = write_lock!(self.buffer);
-        } else {
-            if !buffer.is_empty() {
-                // separate from previous entry
-                buffer.push('\n')
-            }
-            buffer.push_str(&metric.prefix);
-            buffer.push_str(&value_str);
-            buffer.push_str(&metric.suffix);
+    /// Send a DogStatsD service check with the specified status and optional message.
+    /// The service check is tagged with the labels provided.
+    /// Service checks are not supported by the `Etsy` dialect and are dropped.
+    pub fn service_check(
+        &self,
+        name: &str,
+        status: ServiceCheckStatus,
+        message: Option<&str>,
+        labels: Labels,
+    ) {
+        if self.dialect != StatsdDialect::DogStatsd {
+            debug!("Statsd service checks require the DogStatsd dialect, dropping check {name}");
+            return;
         }
-
-        if !self.is_buffered() {
-            if let Err(e) = self.flush_inner(buffer) {
-                debug!("Could not send to statsd {e}")
-            }
+        let name = self.prefix_prepend(name).join(".");
+        let mut entry = format!("_sc|{name}|{}", status as u8);
+        entry.push_str(&format_tags(labels));
+        if let Some(message) = message {
+            // message must be the last field
+            entry.push_str("|m:");
+            entry.push_str(&message.replace('\n', "\\n"));
         }
+        self.push_entry(&[&entry])
+    }
+
+    fn print(&self, metric: &StatsdMetric, value: MetricValue, labels: Labels) {
+        let scaled_value = value / metric.scale;
+        let value_str = scaled_value.to_string();
+        let tags = match self.dialect {
+            StatsdDialect::Etsy => String::new(),
+            StatsdDialect::DogStatsd => format_tags(labels),
+        };
+        self.push_entry(&[&metric.prefix, &value_str, &metric.suffix, &tags])
     }
 
-    fn flush_inner(&self, mut buffer: RwLockWriteGuard<String>) -> io::Result<()> {
-        if !buffer.is_empty() {
-            match self.socket.send(buffer.as_bytes()) {
-                Ok(size) => {
-                    metrics::STATSD_SENT_BYTES.count(size);
-                    trace!("Sent {} bytes to statsd", buffer.len());
+    /// Append an entry made of the parts provided to the buffer, sending it if required.
+    fn push_entry(&self, parts: &[&str]) {
+        let mut buffer = write_lock!(self.buffer);
+        let full = match &mut *buffer {
+            StatsdBuffer::Datagram(batch) => match batch.push(parts) {
+                Push::Oversize => {
+                    metrics::STATSD_OVERSIZE.mark();
+                    debug!(
+                        "Statsd entry too big to fit in a datagram, dropping {}",
+                        parts[0]
+                    );
+                    return;
                 }
-                Err(e) => {
-                    metrics::STATSD_SEND_ERR.mark();
-                    return Err(e);
+                push => push == Push::Full,
+            },
+            StatsdBuffer::Tcp(_, entries) => {
+                let len: usize = parts.iter().map(|part| part.len()).sum();
+                if entries.len() + len + 1 > MAX_PENDING_BYTES {
+                    metrics::STATSD_PENDING_DROPPED.mark();
+                    debug!(
+                        "Statsd pending entries exceed {MAX_PENDING_BYTES} bytes, dropping {}",
+                        parts[0]
+                    );
+                    // unbuffered scopes still try to send what is pending
+                    false
+                } else {
+                    for part in parts {
+                        entries.push_str(part);
+                    }
+                    entries.push('\n');
+                    self.is_buffer_full(entries.len())
                 }
-            };
-            buffer.clear();
+            }
+        };
+
+        if (!self.is_buffered() || full)
+            && let Err(e) = self.flush_inner(buffer)
+        {
+            debug!("Could not send to statsd {e}")
+        }
+    }
+
+    fn flush_inner(&self, mut buffer: RwLockWriteGuard<StatsdBuffer>) -> io::Result<()> {
+        if buffer.is_empty() {
+            return Ok(());
+        }
+        match buffer.send() {
+            Ok(size) => {
+                metrics::STATSD_SENT_BYTES.count(size);
+                trace!("Sent {size} bytes to statsd");
+                Ok(())
+            }
+            Err(e) => {
+                metrics::STATSD_SEND_ERR.mark();
+                Err(e)
+            }
         }
-        Ok(())
     }
 }
 
@@ -210,6 +368,63 @@ impl WithAttributes for StatsdScope {
 
 impl Buffered for StatsdScope {}
 
+impl StatsdBuffer {
+    fn is_empty(&self) -> bool {
+        match self {
+            StatsdBuffer::Datagram(batch) => batch.is_empty(),
+            StatsdBuffer::Tcp(_, buffer) => buffer.is_empty(),
+        }
+    }
+
+    /// Entries not yet sent.
+    #[cfg(test)]
+    fn pending(&self) -> &str {
+        match self {
+            StatsdBuffer::Datagram(batch) => batch.current_packet(),
+            StatsdBuffer::Tcp(_, buffer) => buffer,
+        }
+    }
+
+    /// Send the entries, returning the number of bytes sent.
+    /// Entries that could not be sent over TCP are kept for the next attempt.
+    fn send(&mut self) -> io::Result<usize> {
+        match self {
+            StatsdBuffer::Datagram(batch) => batch.send(),
+            StatsdBuffer::Tcp(socket, buffer) => {
+                write_lock!(socket).write_all(buffer.as_bytes())?;
+                let size = buffer.len();
+                buffer.clear();
+                Ok(size)
+            }
+        }
+    }
+}
+
+/// Print labels sorted by key as DogStatsD `|#key:value,...` tags, or nothing if there are no labels.
+fn format_tags(labels: Labels) -> String {
+    let mut labels: Vec<_> = labels.into_map().into_iter().collect();
+    labels.sort();
+
+    let mut tags = String::new();
+    for (key, value) in labels {
+        tags.push_str(if tags.is_empty() { "|#" } else { "," });
+        push_tag_part(&mut tags, &key);
+        tags.push(':');
+        push_tag_part(&mut tags, &value);
+    }
+    tags
+}
+
+/// Replace characters that would break the datagram format by an underscore.
+fn push_tag_part(tags: &mut String, part: &str) {
+    for c in part.chars() {
+        match c {
+            '|' | ',' | '#' | '\n' => tags.push('_'),
+            c => tags.push(c),
+        }
+    }
+}
+
 /// Key of a statsd metric.
 #[derive(Debug, Clone)]
 pub struct StatsdMetric {
@@ -263,6 +478,244 @@ impl Drop for StatsdScope {
 //     }
 // }
 
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::label::test::TEST_SEQUENCE;
+    use crate::output::socket::MAX_UDP_PAYLOAD;
+    use std::net::UdpSocket;
+
+    fn receive(metrics: &StatsdScope, receiver: &UdpSocket) -> String {
+        metrics.flush().unwrap();
+        let mut buf = [0; MAX_UDP_PAYLOAD];
+        let len = receiver.recv(&mut buf).unwrap();
+        String::from_utf8_lossy(&buf[..len]).to_string()
+    }
+
+    #[test]
+    fn dogstatsd_tags() {
+        let _lock = TEST_SEQUENCE.lock().expect("Test Sequence");
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let metrics = Statsd::send_to(receiver.local_addr().unwrap())
+            .unwrap()
+            .dialect(StatsdDialect::DogStatsd)
+            .buffered(crate::Buffering::Unlimited)
+            .sampled(Sampling::Random(0.9999999))
+            .metrics();
+
+        // sample until both values make it
+        let counter = metrics.counter("counter_a");
+        while write_lock!(metrics.buffer).is_empty() {
+            counter.write(3, labels!("b" => "2", "a" => "1|x"));
+        }
+        let marker = metrics.marker("marker_a");
+        while !write_lock!(metrics.buffer).pending().contains('\n') {
+            marker.write(1, labels![]);
+        }
+
+        assert_eq!(
+            "counter_a:3|c|@0.9999999|#a:1_x,b:2\nmarker_a:1|c|@0.9999999",
+            receive(&metrics, &receiver)
+        );
+    }
+
+    #[test]
+    fn etsy_no_tags() {
+        let _lock = TEST_SEQUENCE.lock().expect("Test Sequence");
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let metrics = Statsd::send_to(receiver.local_addr().unwrap())
+            .unwrap()
+            .buffered(crate::Buffering::Unlimited)
+            .metrics();
+
+        // dropped metrics flush their scope, keep them around
+        let timer = metrics.timer("timer_a");
+        let gauge = metrics.gauge("gauge_a");
+        timer.write(3000, labels!("a" => "1"));
+        gauge.write(4, labels![]);
+
+        assert_eq!("timer_a:3|ms\ngauge_a:4|g", receive(&metrics, &receiver));
+    }
+
+    #[test]
+    fn dogstatsd_kinds() {
+        let _lock = TEST_SEQUENCE.lock().expect("Test Sequence");
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let metrics = Statsd::send_to(receiver.local_addr().unwrap())
+            .unwrap()
+            .dialect(StatsdDialect::DogStatsd)
+            .buffered(crate::Buffering::Unlimited)
+            .metrics();
+
+        let set = metrics.set("set_a");
+        let histogram = metrics.histogram("histo_a");
+        let distribution = metrics.distribution("distro_a");
+        set.insert(42);
+        histogram.value(7);
+        distribution.value(9);
+
+        assert_eq!(
+            "set_a:42|s\nhisto_a:7|h\ndistro_a:9|d",
+            receive(&metrics, &receiver)
+        );
+    }
+
+    #[test]
+    fn dogstatsd_events_and_checks() {
+        let _lock = TEST_SEQUENCE.lock().expect("Test Sequence");
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let metrics = Statsd::send_to(receiver.local_addr().unwrap())
+            .unwrap()
+            .dialect(StatsdDialect::DogStatsd)
+            .buffered(crate::Buffering::Unlimited)
+            .metrics()
+            .named("app");
+
+        metrics.event("deployed", "line1\nline2", labels!("env" => "prod"));
+        metrics.service_check(
+            "db",
+            ServiceCheckStatus::Critical,
+            Some("unreachable"),
+            labels![],
+        );
+
+        assert_eq!(
+            "_e{12,12}:app.deployed|line1\\nline2|#env:prod\n_sc|app.db|2|m:unreachable",
+            receive(&metrics, &receiver)
+        );
+    }
+
+    #[test]
+    fn etsy_kinds() {
+        let _lock = TEST_SEQUENCE.lock().expect("Test Sequence");
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let metrics = Statsd::send_to(receiver.local_addr().unwrap())
+            .unwrap()
+            .buffered(crate::Buffering::Unlimited)
+            .metrics();
+
+        let histogram = metrics.histogram("histo_a");
+        histogram.value(7);
+        // events are dropped
+        metrics.event("deployed", "text", labels![]);
+        let distribution = metrics.distribution("distro_a");
+        distribution.value(9);
+
+        assert_eq!("histo_a:7|ms\ndistro_a:9|ms", receive(&metrics, &receiver));
+    }
+
+    #[test]
+    fn max_payload() {
+        let _lock = TEST_SEQUENCE.lock().expect("Test Sequence");
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let metrics = Statsd::send_to(receiver.local_addr().unwrap())
+            .unwrap()
+            .max_payload(27)
+            .buffered(crate::Buffering::Unlimited)
+            .metrics();
+
+        let counter = metrics.counter("counter_a");
+        let oversize = metrics.counter("counter_with_a_very_long_name");
+        counter.count(1);
+        counter.count(2);
+        oversize.count(3);
+        counter.count(4);
+
+        assert_eq!("counter_a:1|c\ncounter_a:2|c", receive(&metrics, &receiver));
+        let mut buf = [0; MAX_UDP_PAYLOAD];
+        let len = receiver.recv(&mut buf).unwrap();
+        assert_eq!(b"counter_a:4|c", &buf[..len]);
+    }
+
+    #[test]
+    fn tcp_lines() {
+        use std::io::{BufRead, BufReader};
+        use std::net::TcpListener;
+
+        let _lock = TEST_SEQUENCE.lock().expect("Test Sequence");
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let metrics = Statsd::send_to_tcp(listener.local_addr().unwrap())
+            .unwrap()
+            .buffered(crate::Buffering::Unlimited)
+            .metrics();
+
+        let counter = metrics.counter("counter_a");
+        let gauge = metrics.gauge("gauge_a");
+        counter.count(3);
+        gauge.value(5);
+        // the socket connects once its initial reconnect delay expires, entries are kept until then
+        while metrics.flush().is_err() {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+
+        let (stream, _) = listener.accept().unwrap();
+        let lines: Vec<_> = BufReader::new(stream)
+            .lines()
+            .take(2)
+            .map(|line| line.unwrap())
+            .collect();
+        assert_eq!(vec!["counter_a:3|c", "gauge_a:5|g"], lines);
+    }
+
+    #[test]
+    fn tcp_pending_capped() {
+        let _lock = TEST_SEQUENCE.lock().expect("Test Sequence");
+        // nothing listens there, entries stay in the buffer
+        let metrics = Statsd::send_to_tcp("127.0.0.1:1")
+            .unwrap()
+            .buffered(crate::Buffering::Unlimited)
+            .metrics();
+        let gauge = metrics.gauge("gauge_a");
+
+        let mut pending = 0;
+        loop {
+            gauge.value(5);
+            let len = read_lock!(metrics.buffer).pending().len();
+            if len == pending {
+                break;
+            }
+            pending = len;
+        }
+        assert!(pending <= MAX_PENDING_BYTES);
+        assert!(pending > MAX_PENDING_BYTES - 100);
+        if let StatsdBuffer::Tcp(_, entries) = &mut *write_lock!(metrics.buffer) {
+            entries.clear();
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unix_socket() {
+        use std::os::unix::net::UnixDatagram;
+
+        let _lock = TEST_SEQUENCE.lock().expect("Test Sequence");
+        let path = std::env::temp_dir().join(format!("dipstick-statsd-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let receiver = UnixDatagram::bind(&path).unwrap();
+        let metrics = Statsd::send_to_unix(&path)
+            .unwrap()
+            .buffered(crate::Buffering::Unlimited)
+            .metrics();
+
+        // more than would fit in a single UDP datagram
+        let counters: Vec<_> = (0..100)
+            .map(|i| metrics.counter(&format!("counter_{i:02}")))
+            .collect();
+        for counter in &counters {
+            counter.count(1);
+        }
+        metrics.flush().unwrap();
+
+        let mut buf = [0; 8192];
+        let len = receiver.recv(&mut buf).unwrap();
+        let payload = String::from_utf8_lossy(&buf[..len]);
+        assert!(len > MAX_UDP_PAYLOAD);
+        assert_eq!(100, payload.lines().count());
+        assert_eq!(Some("counter_99:1|c"), payload.lines().last());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
+
 #[cfg(feature = "bench")]
 mod bench {
     use super::*;
@@ -6,6 +6,7 @@ use crate::Flush;
 use crate::attributes::{Attributes, Buffered, MetricId, OnFlush, Prefixed, WithAttributes};
 use crate::input::InputKind;
 use crate::name::MetricName;
+use crate::output::format::BatchValue;
 use crate::{CachedInput, QueuedInput};
 
 use std::fs::{File, OpenOptions};
@@ -143,6 +144,7 @@ impl<W: Write + Send + Sync + 'static> Input for Stream<W> {
         TextScope {
             attributes: self.attributes.clone(),
             entries: Arc::new(RwLock::new(Vec::new())),
+            values: Arc::new(RwLock::new(Vec::new())),
             input: self.clone(),
         }
     }
@@ -152,6 +154,7 @@ impl<W: Write + Send + Sync + 'static> Input for Stream<W> {
 pub struct TextScope<W: Write + Send + Sync + 'static> {
     attributes: Attributes,
     entries: Arc<RwLock<Vec<Vec<u8>>>>,
+    values: Arc<RwLock<Vec<BatchValue>>>,
     input: Stream<W>,
 }
 
@@ -160,6 +163,7 @@ impl<W: Write + Send + Sync + 'static> Clone for TextScope<W> {
         TextScope {
             attributes: self.attributes.clone(),
             entries: self.entries.clone(),
+            values: self.values.clone(),
             input: self.input.clone(),
         }
     }
@@ -182,12 +186,23 @@ impl<W: Write + Send + Sync + 'static> InputScope for TextScope<W> {
         let template = self.input.format.template(&name, kind);
 
         let entries = self.entries.clone();
-        let metric_id = MetricId::forge("stream", name);
+        let metric_id = MetricId::forge("stream", name.clone());
 
-        if self.is_buffered() {
+        if self.is_buffered() && self.input.format.batch().is_some() {
+            // values are printed together on flush
+            let values = self.values.clone();
+            InputMetric::new(metric_id, move |value, labels| {
+                write_lock!(values).push(BatchValue {
+                    name: name.clone(),
+                    kind,
+                    value,
+                    labels,
+                })
+            })
+        } else if self.is_buffered() {
             InputMetric::new(metric_id, move |value, labels| {
                 let mut buffer = Vec::with_capacity(32);
-                match template.print(&mut buffer, value, |key| labels.lookup(key)) {
+                match template.print_labels(&mut buffer, value, &labels) {
                     Ok(()) => {
                         let mut entries = write_lock!(entries);
                         entries.push(buffer)
@@ -200,7 +215,7 @@ impl<W: Write + Send + Sync + 'static> InputScope for TextScope<W> {
             let input = self.input.clone();
             InputMetric::new(metric_id, move |value, labels| {
                 let mut buffer = Vec::with_capacity(32);
-                match template.print(&mut buffer, value, |key| labels.lookup(key)) {
+                match template.print_labels(&mut buffer, value, &labels) {
                     Ok(()) => {
                         let mut input = write_lock!(input.inner);
                         if let Err(e) = input.write_all(&buffer).and_then(|_| input.flush()) {
@@ -217,6 +232,15 @@ impl<W: Write + Send + Sync + 'static> InputScope for TextScope<W> {
 impl<W: Write + Send + Sync + 'static> Flush for TextScope<W> {
     fn flush(&self) -> io::Result<()> {
         self.notify_flush_listeners();
+        let mut values = write_lock!(self.values);
+        if let Some(batch) = self.input.format.batch()
+            && !values.is_empty()
+        {
+            let mut input = write_lock!(self.input.inner);
+            batch.print_batch(&mut *input, &values)?;
+            values.clear();
+            input.flush()?;
+        }
         let mut entries = write_lock!(self.entries);
         if !entries.is_empty() {
             let mut input = write_lock!(self.input.inner);
@@ -249,4 +273,25 @@ mod test {
         let m = c.new_metric("test".into(), InputKind::Marker);
         m.write(33, labels![]);
     }
+
+    #[test]
+    fn batch_per_flush() {
+        let _lock = crate::label::test::TEST_SEQUENCE
+            .lock()
+            .expect("Test Sequence");
+        let scope = Stream::write_to(Vec::new())
+            .formatting(crate::EmfFormat::new("MyApp"))
+            .buffered(crate::Buffering::Unlimited)
+            .metrics();
+        let counter = scope.counter("c");
+        let gauge = scope.gauge("g");
+        counter.count(1);
+        gauge.value(5);
+        counter.count(2);
+        scope.flush().unwrap();
+
+        let out = String::from_utf8(read_lock!(scope.input.inner).clone()).unwrap();
+        assert_eq!(1, out.lines().count());
+        assert!(out.ends_with(",\"c\":[1,2],\"g\":5}\n"));
+    }
 }
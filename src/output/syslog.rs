@@ -0,0 +1,516 @@
+//! Send metrics to a syslog daemon.
+
+use crate::attributes::{
+    Attributes, Buffered, MAX_PENDING_VALUES, MetricId, OnFlush, Prefixed, WithAttributes,
+};
+use crate::input::InputKind;
+use crate::input::{Input, InputMetric, InputScope};
+use crate::label::Labels;
+use crate::metrics;
+use crate::name::MetricName;
+use crate::output::format::{Formatting, LineFormat, LineTemplate, SimpleFormat};
+use crate::output::socket::RetrySocket;
+use crate::{CachedInput, QueuedInput};
+use crate::{Flush, MetricValue};
+
+use std::fmt::{Debug, Write as _};
+use std::io::Write;
+use std::net::{ToSocketAddrs, UdpSocket};
+#[cfg(unix)]
+use std::os::unix::net::UnixDatagram;
+#[cfg(unix)]
+use std::path::Path;
+use std::sync::Arc;
+use time::{OffsetDateTime, UtcOffset};
+
+#[cfg(not(feature = "parking_lot"))]
+use std::sync::RwLock;
+
+#[cfg(feature = "parking_lot")]
+use parking_lot::RwLock;
+use std::io;
+
+/// Severity of the metrics messages, "Informational".
+const SEVERITY: u8 = 6;
+
+/// Syslog message formats.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub enum SyslogProtocol {
+    /// The structured syslog protocol, labels are sent as structured data.
+    #[default]
+    Rfc5424,
+    /// The legacy BSD syslog protocol, labels can only be printed by the line format.
+    Rfc3164,
+}
+
+/// Syslog facilities, identifying the type of program sending the messages.
+#[allow(missing_docs)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub enum SyslogFacility {
+    Kern = 0,
+    #[default]
+    User = 1,
+    Mail = 2,
+    Daemon = 3,
+    Auth = 4,
+    Syslog = 5,
+    Lpr = 6,
+    News = 7,
+    Uucp = 8,
+    Cron = 9,
+    AuthPriv = 10,
+    Ftp = 11,
+    Local0 = 16,
+    Local1 = 17,
+    Local2 = 18,
+    Local3 = 19,
+    Local4 = 20,
+    Local5 = 21,
+    Local6 = 22,
+    Local7 = 23,
+}
+
+/// Where the syslog messages are sent.
+#[derive(Clone, Debug)]
+enum SyslogTarget {
+    Udp(Arc<UdpSocket>),
+    Tcp(Arc<RwLock<RetrySocket>>),
+    #[cfg(unix)]
+    Unix(Arc<UnixDatagram>),
+}
+
+/// Syslog Input sends each formatted metric value as a syslog message.
+/// The transport is shared between scopes opened from the Input.
+#[derive(Clone)]
+pub struct Syslog {
+    attributes: Attributes,
+    format: Arc<dyn LineFormat>,
+    target: SyslogTarget,
+    protocol: SyslogProtocol,
+    facility: SyslogFacility,
+    app_name: String,
+    hostname: Option<String>,
+    sd_id: String,
+    local_offset: UtcOffset,
+}
+
+impl Syslog {
+    fn new(target: SyslogTarget) -> Syslog {
+        Syslog {
+            attributes: Attributes::default(),
+            format: Arc::new(SimpleFormat::default()),
+            target,
+            protocol: SyslogProtocol::default(),
+            facility: SyslogFacility::default(),
+            app_name: "dipstick".to_string(),
+            hostname: None,
+            sd_id: "dipstick@32473".to_string(),
+            // the local offset can not always be determined once threads are running
+            local_offset: UtcOffset::current_local_offset().unwrap_or(UtcOffset::UTC),
+        }
+    }
+
+    /// Send messages over UDP to a syslog server at the address and port provided.
+    pub fn send_to_udp<ADDR: ToSocketAddrs>(address: ADDR) -> io::Result<Syslog> {
+        let socket = UdpSocket::bind("0.0.0.0:0")?;
+        socket.set_nonblocking(true)?;
+        socket.connect(address)?;
+        Ok(Syslog::new(SyslogTarget::Udp(Arc::new(socket))))
+    }
+
+    /// Send messages over TCP to a syslog server at the address and port provided.
+    /// Messages are framed using octet counting (RFC 6587).
+    pub fn send_to_tcp<A: ToSocketAddrs + Debug + Clone>(address: A) -> io::Result<Syslog> {
+        debug!("Connecting to syslog {address:?}");
+        let socket = RetrySocket::new(address)?;
+        Ok(Syslog::new(SyslogTarget::Tcp(Arc::new(RwLock::new(
+            socket,
+        )))))
+    }
+
+    /// Send messages to the local syslog daemon's Unix datagram socket at the path provided.
+    #[cfg(unix)]
+    pub fn send_to_unix<P: AsRef<Path>>(path: P) -> io::Result<Syslog> {
+        let socket = UnixDatagram::unbound()?;
+        socket.connect(path)?;
+        socket.set_nonblocking(true)?;
+        Ok(Syslog::new(SyslogTarget::Unix(Arc::new(socket))))
+    }
+
+    /// Send messages to the local syslog daemon through `/dev/log`.
+    #[cfg(unix)]
+    pub fn send_to_local() -> io::Result<Syslog> {
+        Syslog::send_to_unix("/dev/log")
+    }
+
+    /// Use the specified message format for scopes opened afterwards.
+    pub fn protocol(&self, protocol: SyslogProtocol) -> Self {
+        let mut cloned = self.clone();
+        cloned.protocol = protocol;
+        cloned
+    }
+
+    /// Use the specified facility for scopes opened afterwards.
+    pub fn facility(&self, facility: SyslogFacility) -> Self {
+        let mut cloned = self.clone();
+        cloned.facility = facility;
+        cloned
+    }
+
+    /// Identify the messages with the specified application name (the RFC 3164 tag).
+    /// Defaults to "dipstick".
+    pub fn app_name(&self, app_name: &str) -> Self {
+        let mut cloned = self.clone();
+        cloned.app_name = app_name.to_string();
+        cloned
+    }
+
+    /// Identify the messages with the specified host name.
+    /// By default, the host name is left for the syslog daemon to fill.
+    pub fn hostname(&self, hostname: &str) -> Self {
+        let mut cloned = self.clone();
+        cloned.hostname = Some(hostname.to_string());
+        cloned
+    }
+
+    /// Use the specified RFC 5424 structured data ID for labels, e.g. `labels@12345`.
+    /// Defaults to "dipstick@32473", using the enterprise number reserved for documentation.
+    pub fn structured_data_id(&self, sd_id: &str) -> Self {
+        let mut cloned = self.clone();
+        cloned.sd_id = sd_id.to_string();
+        cloned
+    }
+}
+
+impl Input for Syslog {
+    type SCOPE = SyslogScope;
+
+    fn metrics(&self) -> Self::SCOPE {
+        SyslogScope {
+            attributes: self.attributes.clone(),
+            entries: Arc::new(RwLock::new(Vec::new())),
+            syslog: self.clone(),
+        }
+    }
+}
+
+impl WithAttributes for Syslog {
+    fn get_attributes(&self) -> &Attributes {
+        &self.attributes
+    }
+    fn mut_attributes(&mut self) -> &mut Attributes {
+        &mut self.attributes
+    }
+}
+
+impl Buffered for Syslog {}
+
+impl Formatting for Syslog {
+    fn formatting(&self, format: impl LineFormat + 'static) -> Self {
+        let mut cloned = self.clone();
+        cloned.format = Arc::new(format);
+        cloned
+    }
+}
+
+impl QueuedInput for Syslog {}
+impl CachedInput for Syslog {}
+
+/// Syslog Input
+#[derive(Clone)]
+pub struct SyslogScope {
+    attributes: Attributes,
+    entries: Arc<RwLock<Vec<Vec<u8>>>>,
+    syslog: Syslog,
+}
+
+impl InputScope for SyslogScope {
+    /// Define a metric of the specified type.
+    fn new_metric(&self, name: MetricName, kind: InputKind) -> InputMetric {
+        let name = self.prefix_append(name);
+        let template = self.syslog.format.template(&name, kind);
+        let cloned = self.clone();
+
+        InputMetric::new(MetricId::forge("syslog", name), move |value, labels| {
+            cloned.print(&template, value, labels)
+        })
+    }
+}
+
+impl Flush for SyslogScope {
+    fn flush(&self) -> io::Result<()> {
+        self.notify_flush_listeners();
+        let mut entries = write_lock!(self.entries);
+        for (sent, entry) in entries.iter().enumerate() {
+            if let Err(e) = self.send(entry) {
+                entries.drain(..sent);
+                return Err(e);
+            }
+        }
+        entries.clear();
+        Ok(())
+    }
+}
+
+impl SyslogScope {
+    fn print(&self, template: &LineTemplate, value: MetricValue, labels: Labels) {
+        let mut line = Vec::with_capacity(32);
+        if let Err(err) = template.print_labels(&mut line, value, &labels) {
+            debug!("Could not format syslog metric: {err}");
+            return;
+        }
+        // syslog messages are single lines
+        while line.last() == Some(&b'\n') {
+            line.pop();
+        }
+
+        let message = self.message(&line, labels);
+        if !self.is_buffered() {
+            if let Err(e) = self.send(&message) {
+                debug!("Could not send to syslog {e}")
+            }
+            return;
+        }
+
+        let mut entries = write_lock!(self.entries);
+        if entries.len() >= MAX_PENDING_VALUES {
+            metrics::SYSLOG_PENDING_DROPPED.mark();
+            debug!("Too many syslog messages pending, dropping message");
+            return;
+        }
+        entries.push(message);
+        if self.is_buffer_full(entries.len()) {
+            drop(entries);
+            if let Err(e) = self.flush() {
+                debug!("Could not send to syslog {e}")
+            }
+        }
+    }
+
+    /// Frame the formatted line as a syslog message.
+    fn message(&self, line: &[u8], labels: Labels) -> Vec<u8> {
+        let syslog = &self.syslog;
+        let priority = (syslog.facility as u8) * 8 + SEVERITY;
+        let pid = std::process::id();
+
+        let mut header = format!("<{priority}>");
+        match syslog.protocol {
+            SyslogProtocol::Rfc5424 => {
+                let now = OffsetDateTime::now_utc();
+                let _ = write!(
+                    header,
+                    "1 {:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z {} {} {pid} - {} ",
+                    now.year(),
+                    u8::from(now.month()),
+                    now.day(),
+                    now.hour(),
+                    now.minute(),
+                    now.second(),
+                    now.millisecond(),
+                    syslog.hostname.as_deref().unwrap_or("-"),
+                    syslog.app_name,
+                    structured_data(&syslog.sd_id, labels),
+                );
+            }
+            SyslogProtocol::Rfc3164 => {
+                // BSD syslog timestamps are in local time
+                let now = OffsetDateTime::now_local()
+                    .unwrap_or_else(|_| OffsetDateTime::now_utc().to_offset(syslog.local_offset));
+                const MONTHS: [&str; 12] = [
+                    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov",
+                    "Dec",
+                ];
+                let _ = write!(
+                    header,
+                    "{} {:2} {:02}:{:02}:{:02} ",
+                    MONTHS[u8::from(now.month()) as usize - 1],
+                    now.day(),
+                    now.hour(),
+                    now.minute(),
+                    now.second()
+                );
+                if let Some(hostname) = &syslog.hostname {
+                    let _ = write!(header, "{hostname} ");
+                }
+                let _ = write!(header, "{}[{pid}]: ", syslog.app_name);
+            }
+        }
+        let mut message = header.into_bytes();
+        message.extend_from_slice(line);
+        message
+    }
+
+    fn send(&self, message: &[u8]) -> io::Result<()> {
+        let result = match &self.syslog.target {
+            SyslogTarget::Udp(socket) => socket.send(message).map(|_| ()),
+            #[cfg(unix)]
+            SyslogTarget::Unix(socket) => socket.send(message).map(|_| ()),
+            SyslogTarget::Tcp(socket) => {
+                // octet counting framing
+                let mut framed = format!("{} ", message.len()).into_bytes();
+                framed.extend_from_slice(message);
+                write_lock!(socket).write_all(&framed)
+            }
+        };
+        match result {
+            Ok(()) => {
+                metrics::SYSLOG_SENT_BYTES.count(message.len());
+                trace!("Sent {} bytes to syslog", message.len());
+                Ok(())
+            }
+            Err(e) => {
+                metrics::SYSLOG_SEND_ERR.mark();
+                debug!("Failed to send message to syslog: {e}");
+                Err(e)
+            }
+        }
+    }
+}
+
+impl WithAttributes for SyslogScope {
+    fn get_attributes(&self) -> &Attributes {
+        &self.attributes
+    }
+    fn mut_attributes(&mut self) -> &mut Attributes {
+        &mut self.attributes
+    }
+}
+
+impl Buffered for SyslogScope {}
+
+/// Print labels sorted by key as an RFC 5424 structured data element, or "-" if there are no labels.
+/// Labels with an empty key can not be sent as param names and are skipped.
+fn structured_data(sd_id: &str, labels: Labels) -> String {
+    let mut labels: Vec<_> = labels
+        .into_map()
+        .into_iter()
+        .filter(|(key, _)| !key.is_empty())
+        .collect();
+    if labels.is_empty() {
+        return "-".to_string();
+    }
+    labels.sort();
+
+    let mut sd = format!("[{sd_id}");
+    for (key, value) in labels {
+        sd.push(' ');
+        // param names are limited to 32 printable characters other than '=', ' ', ']' and '"'
+        for c in key.chars().take(32) {
+            match c {
+                '=' | ']' | '"' => sd.push('_'),
+                c if !c.is_ascii_graphic() => sd.push('_'),
+                c => sd.push(c),
+            }
+        }
+        sd.push_str("=\"");
+        for c in value.chars() {
+            if let '"' | '\\' | ']' = c {
+                sd.push('\\');
+            }
+            sd.push(c);
+        }
+        sd.push('"');
+    }
+    sd.push(']');
+    sd
+}
+
+/// Any remaining buffered data is flushed on Drop.
+impl Drop for SyslogScope {
+    fn drop(&mut self) {
+        if let Err(err) = self.flush() {
+            warn!("Could not flush syslog metrics upon Drop: {err}")
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::label::test::TEST_SEQUENCE;
+
+    #[test]
+    fn empty_keys_skipped() {
+        let _lock = TEST_SEQUENCE.lock().expect("Test Sequence");
+        assert_eq!("-", structured_data("id", labels!("" => "x")));
+        assert_eq!(
+            "[id a=\"1\"]",
+            structured_data("id", labels!("" => "x", "a" => "1"))
+        );
+    }
+
+    #[test]
+    fn pending_messages_capped() {
+        let _lock = TEST_SEQUENCE.lock().expect("Test Sequence");
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let metrics = Syslog::send_to_udp(receiver.local_addr().unwrap())
+            .unwrap()
+            .buffered(crate::Buffering::Unlimited)
+            .metrics();
+
+        let marker = metrics.marker("m");
+        for _ in 0..=MAX_PENDING_VALUES {
+            marker.mark();
+        }
+        assert_eq!(MAX_PENDING_VALUES, read_lock!(metrics.entries).len());
+        write_lock!(metrics.entries).clear();
+    }
+
+    #[test]
+    fn rfc5424_over_udp() {
+        let _lock = TEST_SEQUENCE.lock().expect("Test Sequence");
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let metrics = Syslog::send_to_udp(receiver.local_addr().unwrap())
+            .unwrap()
+            .facility(SyslogFacility::Local0)
+            .hostname("h1")
+            .app_name("app")
+            .metrics();
+
+        let counter = metrics.counter("counter_a");
+        counter.write(3, labels!("path" => "/a]", "x=y" => "\"q\""));
+
+        let mut buf = [0; 1024];
+        let len = receiver.recv(&mut buf).unwrap();
+        let message = String::from_utf8_lossy(&buf[..len]);
+        // skip the timestamp
+        assert!(message.starts_with("<134>1 "));
+        let (_, rest) = message.split_once('Z').unwrap();
+        assert_eq!(
+            format!(
+                " h1 app {} - [dipstick@32473 path=\"/a\\]\" x_y=\"\\\"q\\\"\"] counter_a 3",
+                std::process::id()
+            ),
+            rest
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn rfc3164_over_unix_socket() {
+        let _lock = TEST_SEQUENCE.lock().expect("Test Sequence");
+        let path = std::env::temp_dir().join(format!("dipstick-syslog-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let receiver = UnixDatagram::bind(&path).unwrap();
+        let metrics = Syslog::send_to_unix(&path)
+            .unwrap()
+            .protocol(SyslogProtocol::Rfc3164)
+            .buffered(crate::Buffering::Unlimited)
+            .metrics();
+
+        let gauge = metrics.gauge("gauge_a");
+        gauge.write(7, labels!("a" => "1"));
+        metrics.flush().unwrap();
+
+        let mut buf = [0; 1024];
+        let len = receiver.recv(&mut buf).unwrap();
+        let message = String::from_utf8_lossy(&buf[..len]);
+        // <14> is user.info, then "Mmm dd hh:mm:ss "
+        assert!(message.starts_with("<14>"));
+        assert_eq!(
+            format!("dipstick[{}]: gauge_a 7", std::process::id()),
+            message[4 + 16..]
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,10 +1,10 @@
 //! Decouple metric definition from configuration with trait objects.
 
-use crate::Flush;
 use crate::attributes::{Attributes, MetricId, OnFlush, Prefixed, WithAttributes};
-use crate::input::{InputKind, InputMetric, InputScope};
+use crate::input::{BucketsMetric, InputKind, InputMetric, InputScope};
 use crate::name::{MetricName, NameParts};
 use crate::output::void::VOID_INPUT;
+use crate::{Flush, MetricValue};
 
 use std::collections::{BTreeMap, HashMap};
 use std::sync::{Arc, Weak};
@@ -32,6 +32,8 @@ struct ProxyMetric {
     // basic info for this metric, needed to recreate new corresponding trait object if target changes
     name: NameParts,
     kind: InputKind,
+    // bucket bounds of histograms defined with them
+    bounds: Option<Vec<MetricValue>>,
 
     // the metric trait object to proxy metric values to
     // the second part can be up to namespace.len() + 1 if this metric was individually targeted
@@ -42,6 +44,26 @@ struct ProxyMetric {
     proxy: Arc<RwLock<InnerProxy>>,
 }
 
+impl ProxyMetric {
+    /// Define the corresponding metric in the target scope.
+    fn define_in(&self, target: &Arc<dyn InputScope + Send + Sync>) -> InputMetric {
+        define(target, &self.name, self.kind, self.bounds.as_deref())
+    }
+}
+
+/// Define a metric in the target scope, with the bucket bounds of the histogram if it has any.
+fn define(
+    target: &Arc<dyn InputScope + Send + Sync>,
+    name: &NameParts,
+    kind: InputKind,
+    bounds: Option<&[MetricValue]>,
+) -> InputMetric {
+    match bounds {
+        Some(bounds) => target.new_histogram(name.short(), bounds),
+        None => target.new_metric(name.short(), kind),
+    }
+}
+
 /// Dispatcher weak ref does not prevent dropping but still needs to be cleaned out.
 impl Drop for ProxyMetric {
     fn drop(&mut self) {
@@ -107,7 +129,7 @@ impl InnerProxy {
                     continue;
                 }
 
-                let target_metric = target_scope.new_metric(metric.name.short(), metric.kind);
+                let target_metric = metric.define_in(&target_scope);
                 *metric.target.borrow_mut() = (target_metric, namespace.len());
             }
         }
@@ -154,7 +176,7 @@ impl InnerProxy {
                     continue;
                 }
 
-                let new_metric = up_target.new_metric(name.short(), metric.kind);
+                let new_metric = metric.define_in(&up_target);
                 *metric.target.borrow_mut() = (new_metric, up_nslen);
             }
         }
@@ -227,9 +249,14 @@ impl<S: AsRef<str>> From<S> for Proxy {
     }
 }
 
-impl InputScope for Proxy {
+impl Proxy {
     /// Lookup or create a proxy stub for the requested metric.
-    fn new_metric(&self, name: MetricName, kind: InputKind) -> InputMetric {
+    fn proxy_metric(
+        &self,
+        name: MetricName,
+        kind: InputKind,
+        bounds: Option<&[MetricValue]>,
+    ) -> InputMetric {
         let name: MetricName = self.prefix_append(name);
         let mut inner = write_lock!(self.inner);
         let proxy = inner
@@ -244,10 +271,11 @@ impl InputScope for Proxy {
                     let (target, target_namespace_length) = inner
                         .get_effective_target(namespace)
                         .unwrap_or_else(|| (VOID_INPUT.input_dyn(), 0));
-                    let metric_object = target.new_metric(namespace.short(), kind);
+                    let metric_object = define(&target, namespace, kind, bounds);
                     let proxy = Arc::new(ProxyMetric {
                         name: namespace.clone(),
                         kind,
+                        bounds: bounds.map(<[MetricValue]>::to_vec),
                         target: AtomicRefCell::new((metric_object, target_namespace_length)),
                         proxy: self.inner.clone(),
                     });
@@ -263,6 +291,27 @@ impl InputScope for Proxy {
     }
 }
 
+impl InputScope for Proxy {
+    /// Lookup or create a proxy stub for the requested metric.
+    fn new_metric(&self, name: MetricName, kind: InputKind) -> InputMetric {
+        self.proxy_metric(name, kind, None)
+    }
+
+    /// Lookup or create a proxy stub for the requested histogram,
+    /// keeping its bounds to define it again if the target changes.
+    fn new_histogram(&self, name: MetricName, bounds: &[MetricValue]) -> InputMetric {
+        self.proxy_metric(name, InputKind::Histogram, Some(bounds))
+    }
+
+    /// Define the buckets metric in the current target.
+    /// Buckets metrics are defined anew at every flush, they are not proxied.
+    fn new_buckets(&self, name: MetricName) -> Option<BucketsMetric> {
+        let name: MetricName = self.prefix_append(name);
+        let (target, _) = read_lock!(self.inner).get_effective_target(&name)?;
+        target.new_buckets(name.short())
+    }
+}
+
 impl Flush for Proxy {
     fn flush(&self) -> io::Result<()> {
         self.notify_flush_listeners();
@@ -4,7 +4,9 @@
 
 use crate::CachedInput;
 use crate::attributes::{Attributes, MetricId, OnFlush, Prefixed, WithAttributes};
-use crate::input::{Input, InputDyn, InputKind, InputMetric, InputScope};
+use crate::input::{
+    BucketsMetric, HistogramBuckets, Input, InputDyn, InputKind, InputMetric, InputScope,
+};
 use crate::label::Labels;
 use crate::metrics;
 use crate::name::MetricName;
@@ -42,6 +44,9 @@ fn new_async_channel(length: usize) -> Arc<mpsc::SyncSender<InputQueueCmd>> {
             while !done {
                 match receiver.recv() {
                     Ok(InputQueueCmd::Write(metric, value, labels)) => metric.write(value, labels),
+                    Ok(InputQueueCmd::WriteBuckets(metric, buckets, labels)) => {
+                        metric.write(&buckets, labels)
+                    }
                     Ok(InputQueueCmd::Flush(scope)) => {
                         if let Err(e) = scope.flush() {
                             debug!("Could not asynchronously flush metrics: {e}");
@@ -73,6 +78,9 @@ fn new_async_channel(length: usize) -> Arc<crossbeam::Sender<InputQueueCmd>> {
             while !done {
                 match receiver.recv() {
                     Ok(InputQueueCmd::Write(metric, value, labels)) => metric.write(value, labels),
+                    Ok(InputQueueCmd::WriteBuckets(metric, buckets, labels)) => {
+                        metric.write(&buckets, labels)
+                    }
                     Ok(InputQueueCmd::Flush(scope)) => {
                         if let Err(e) = scope.flush() {
                             debug!("Could not asynchronously flush metrics: {e}");
@@ -142,6 +150,8 @@ impl Input for InputQueue {
 pub enum InputQueueCmd {
     /// Send metric write
     Write(InputMetric, MetricValue, Labels),
+    /// Send histogram buckets write
+    WriteBuckets(BucketsMetric, HistogramBuckets, Labels),
     /// Send metric flush
     Flush(Arc<dyn InputScope + Send + Sync + 'static>),
 }
@@ -185,6 +195,37 @@ impl InputScope for InputQueueScope {
     fn new_metric(&self, name: MetricName, kind: InputKind) -> InputMetric {
         let name = self.prefix_append(name);
         let target_metric = self.target.new_metric(name.clone(), kind);
+        self.queue_metric(name, target_metric)
+    }
+
+    fn new_histogram(&self, name: MetricName, bounds: &[MetricValue]) -> InputMetric {
+        let name = self.prefix_append(name);
+        let target_metric = self.target.new_histogram(name.clone(), bounds);
+        self.queue_metric(name, target_metric)
+    }
+
+    fn new_buckets(&self, name: MetricName) -> Option<BucketsMetric> {
+        let name = self.prefix_append(name);
+        let target_metric = self.target.new_buckets(name.clone())?;
+        let sender = self.sender.clone();
+        Some(BucketsMetric::new(
+            MetricId::forge("queue", name),
+            move |buckets, mut labels| {
+                labels.save_context();
+                let cmd =
+                    InputQueueCmd::WriteBuckets(target_metric.clone(), buckets.clone(), labels);
+                if let Err(e) = sender.send(cmd) {
+                    metrics::SEND_FAILED.mark();
+                    debug!("Failed to send async metrics: {e}");
+                }
+            },
+        ))
+    }
+}
+
+impl InputQueueScope {
+    /// Send values written to the metric to the queue.
+    fn queue_metric(&self, name: MetricName, target_metric: InputMetric) -> InputMetric {
         let sender = self.sender.clone();
         InputMetric::new(MetricId::forge("queue", name), move |value, mut labels| {
             labels.save_context();
@@ -19,6 +19,22 @@ pub enum ScoreType {
     Mean(f64),
     /// Mean rate (hit count / period length in seconds, non-atomic)
     Rate(f64),
+    /// Exponentially weighted moving average rate per second over a window of minutes (1, 5 or 15).
+    MovingRate(usize, f64),
+    /// Estimated value below which the fraction (0.0 to 1.0) of observed values fall.
+    Percentile(f64, isize),
+    /// Counts of a histogram's values in each of its buckets, with their sum.
+    /// Histograms without declared bounds have a single, unbounded bucket.
+    /// If a stats function exports it, the buckets are written to outputs that can represent them,
+    /// under the exported name. The exported value is ignored.
+    Buckets,
+}
+
+/// Name of a percentile stat, e.g. `p99` for the 0.99 quantile, `p999` for 0.999.
+pub fn percentile_name(quantile: f64) -> String {
+    // round away float noise such as 0.29 * 100.0 == 28.999999999999996
+    let percent = (quantile * 100_000.0).round() / 1000.0;
+    format!("p{percent}").replace('.', "")
 }
 
 /// A predefined export strategy reporting all aggregated stats for all metric types.
@@ -40,6 +56,16 @@ pub fn stats_all(
             name.make_name("rate"),
             rate.round() as MetricValue,
         )),
+        ScoreType::MovingRate(minutes, rate) => Some((
+            InputKind::Gauge,
+            name.make_name(format!("m{minutes}_rate")),
+            rate.round() as MetricValue,
+        )),
+        ScoreType::Percentile(quantile, value) => {
+            Some((kind, name.make_name(percentile_name(quantile)), value))
+        }
+        // a single value can not represent buckets
+        ScoreType::Buckets => None,
     }
 }
 
@@ -54,7 +80,7 @@ pub fn stats_average(
     score: ScoreType,
 ) -> Option<(InputKind, MetricName, MetricValue)> {
     match kind {
-        InputKind::Marker => match score {
+        InputKind::Marker | InputKind::Set => match score {
             ScoreType::Count(count) => Some((InputKind::Counter, name, count)),
             _ => None,
         },
@@ -67,8 +93,8 @@ pub fn stats_average(
 
 /// A predefined single-stat-per-metric export strategy:
 ///   - Timers and Counters each export their sums
-///   - Markers each export their hit count
-///   - Gauges each export their average
+///   - Markers and Sets each export their hit count
+///   - Gauges, Histograms and Distributions each export their average
 ///
 /// Since there is only one stat per metric, there is no risk of collision
 /// and so exported stats copy their metric's name.
@@ -79,7 +105,7 @@ pub fn stats_summary(
     score: ScoreType,
 ) -> Option<(InputKind, MetricName, MetricValue)> {
     match kind {
-        InputKind::Marker => match score {
+        InputKind::Marker | InputKind::Set => match score {
             ScoreType::Count(count) => Some((InputKind::Counter, name, count)),
             _ => None,
         },
@@ -87,9 +113,34 @@ pub fn stats_summary(
             ScoreType::Sum(sum) => Some((kind, name, sum)),
             _ => None,
         },
-        InputKind::Gauge | InputKind::Level => match score {
-            ScoreType::Mean(mean) => Some((InputKind::Gauge, name, mean.round() as MetricValue)),
+        InputKind::Gauge | InputKind::Level | InputKind::Histogram | InputKind::Distribution => {
+            match score {
+                ScoreType::Mean(mean) => {
+                    Some((InputKind::Gauge, name, mean.round() as MetricValue))
+                }
+                _ => None,
+            }
+        }
+    }
+}
+
+/// A predefined export strategy for outputs with native histograms, such as Prometheus:
+///   - Histograms export their buckets, to outputs that can represent them only
+///   - Other metrics export the same stat as `stats_summary`
+///
+/// Since there is only one stat per metric, exported stats copy their metric's name.
+#[allow(dead_code)]
+pub fn stats_buckets(
+    kind: InputKind,
+    name: MetricName,
+    score: ScoreType,
+) -> Option<(InputKind, MetricName, MetricValue)> {
+    match kind {
+        InputKind::Histogram => match score {
+            // bucket counts are written by the bucket itself
+            ScoreType::Buckets => Some((kind, name, 0)),
             _ => None,
         },
+        _ => stats_summary(kind, name, score),
     }
 }
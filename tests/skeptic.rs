@@ -1,2 +0,0 @@
-#[cfg(feature = "skeptic")]
-include!(concat!(env!("OUT_DIR"), "/skeptic_tests"));